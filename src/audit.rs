@@ -0,0 +1,333 @@
+use std::{
+    fs::{self, File},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::{OnceLock, mpsc},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::Serialize;
+use tokio::sync::oneshot;
+use tracing::{debug, error, info, warn};
+
+use crate::{
+    error::RusbmuxError,
+    handler::{ResultCode, access::Peer},
+    parser::usbmux::UsbMuxRequest,
+    watcher::CONNECTED_DEVICES,
+};
+
+static AUDIT_LOG: OnceLock<AuditLog> = OnceLock::new();
+
+/// rotate once the log is bigger than this, if nothing else was given
+pub const DEFAULT_AUDIT_MAX_SIZE: u64 = 10 * 1024 * 1024;
+
+/// how many rotated logs are kept, if nothing else was given
+pub const DEFAULT_AUDIT_KEEP: usize = 5;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuditLogOptions {
+    pub path: PathBuf,
+
+    /// in bytes
+    pub max_size: u64,
+
+    /// the rotated logs are `<path>.1` (the newest) up to `<path>.<keep>`
+    pub keep: usize,
+}
+
+impl AuditLogOptions {
+    #[must_use]
+    pub const fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            max_size: DEFAULT_AUDIT_MAX_SIZE,
+            keep: DEFAULT_AUDIT_KEEP,
+        }
+    }
+}
+
+/// who read or changed the pair records, and who connected to what, one json object per line
+///
+/// it's kept apart from the tracing logs, they can be turned down or thrown away, this can't
+///
+/// the entries are written (and the log rotated) on it's own thread, so a slow disk doesn't hold up
+/// the clients
+#[derive(Debug)]
+pub struct AuditLog {
+    path: PathBuf,
+    tx: mpsc::Sender<Message>,
+}
+
+enum Message {
+    Line(Vec<u8>),
+
+    /// answered once everything before it is written
+    Flush(oneshot::Sender<()>),
+}
+
+impl AuditLog {
+    /// opens the log right away, so a log that can't be written is found before the daemon starts
+    ///
+    /// the writer thread is only started by [`OpenedAuditLog::start`], so it's spawned inside the
+    /// sandbox
+    pub fn open(options: AuditLogOptions) -> io::Result<OpenedAuditLog> {
+        if let Some(dir) = options.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        let file = open_append(&options.path)?;
+        let size = file.metadata()?.len();
+
+        Ok(OpenedAuditLog(Writer {
+            options,
+            file,
+            size,
+        }))
+    }
+
+    #[must_use]
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn write(&self, entry: &AuditEntry) -> io::Result<()> {
+        let mut line = serde_json::to_vec(entry)?;
+        line.push(b'\n');
+
+        self.tx
+            .send(Message::Line(line))
+            .map_err(|_| io::Error::other("the audit log writer is gone"))
+    }
+
+    /// waits for every entry written so far to reach the file
+    pub async fn flush(&self) {
+        let (tx, rx) = oneshot::channel();
+
+        if self.tx.send(Message::Flush(tx)).is_ok() {
+            let _ = rx.await;
+        }
+    }
+}
+
+/// the log is open, but nothing writes to it yet
+#[derive(Debug)]
+pub struct OpenedAuditLog(Writer);
+
+impl OpenedAuditLog {
+    pub fn start(self) -> io::Result<AuditLog> {
+        let Self(mut writer) = self;
+
+        let (tx, rx) = mpsc::channel();
+        let path = writer.options.path.clone();
+
+        std::thread::Builder::new()
+            .name("rusbmux-audit".to_string())
+            .spawn(move || writer.run(&rx))?;
+
+        Ok(AuditLog { path, tx })
+    }
+}
+
+/// owns the file, it's the only one touching it
+#[derive(Debug)]
+struct Writer {
+    options: AuditLogOptions,
+    file: File,
+    size: u64,
+}
+
+impl Writer {
+    fn run(&mut self, rx: &mpsc::Receiver<Message>) {
+        while let Ok(message) = rx.recv() {
+            match message {
+                Message::Line(line) => {
+                    if let Err(e) = self.write(&line) {
+                        error!(err = %e, path = ?self.options.path, "Failed to write to the audit log");
+                    }
+                }
+                Message::Flush(done) => {
+                    let _ = done.send(());
+                }
+            }
+        }
+
+        debug!("The audit log writer is done");
+    }
+
+    fn write(&mut self, line: &[u8]) -> io::Result<()> {
+        if self.size > 0 && self.size + line.len() as u64 > self.options.max_size {
+            match self.rotate() {
+                Ok(new) => {
+                    self.file = new;
+                    self.size = 0;
+                }
+
+                // better a log that is too big than a missing entry
+                Err(e) => warn!(err = %e, "Failed to rotate the audit log"),
+            }
+        }
+
+        // a single write, so a line is never split even if something else appends to it
+        self.file.write_all(line)?;
+        self.size += line.len() as u64;
+
+        Ok(())
+    }
+
+    fn rotate(&self) -> io::Result<File> {
+        let path = &self.options.path;
+        let rotated = |n: usize| {
+            let mut p = path.clone().into_os_string();
+            p.push(format!(".{n}"));
+            PathBuf::from(p)
+        };
+
+        if self.options.keep == 0 {
+            fs::remove_file(path)?;
+        } else {
+            for n in (1..self.options.keep).rev() {
+                match fs::rename(rotated(n), rotated(n + 1)) {
+                    Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                    _ => {}
+                }
+            }
+
+            fs::rename(path, rotated(1))?;
+        }
+
+        info!(?path, "Rotated the audit log");
+
+        open_append(path)
+    }
+}
+
+fn open_append(path: &Path) -> io::Result<File> {
+    let mut options = File::options();
+    options.create(true).append(true);
+
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o640);
+
+    options.open(path)
+}
+
+/// the audit log, if it's turned on
+pub fn audit_log() -> Option<&'static AuditLog> {
+    AUDIT_LOG.get()
+}
+
+/// gives the log back if one is already set
+pub fn set_audit_log(log: AuditLog) -> Result<(), AuditLog> {
+    AUDIT_LOG.set(log)
+}
+
+/// one request, it's written once the result is known
+#[derive(Debug, Clone, Serialize)]
+pub struct AuditEntry {
+    /// only the pair record requests, `ReadBUID`, `Connect` and `AcquireLease` are written
+    #[serde(skip)]
+    audited: bool,
+
+    /// seconds since the unix epoch
+    time: f64,
+
+    request: String,
+    pid: Option<i32>,
+    uid: Option<u32>,
+    prog_name: Option<String>,
+    udid: Option<String>,
+    port: Option<u16>,
+
+    /// the result code the client got, there is none if it got nothing back
+    result: Option<u16>,
+
+    /// refused by the access policy
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    denied: bool,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+impl AuditEntry {
+    #[must_use]
+    pub fn new(peer: &Peer, request: &UsbMuxRequest) -> Self {
+        let (udid, port) = match request {
+            UsbMuxRequest::ReadPairRecord { pair_record_id, .. }
+            | UsbMuxRequest::SavePairRecord { pair_record_id, .. }
+            | UsbMuxRequest::DeletePairRecord { pair_record_id, .. } => {
+                (Some(pair_record_id.clone()), None)
+            }
+
+            UsbMuxRequest::AcquireLease { serial_number, .. } => {
+                (Some(serial_number.clone()), None)
+            }
+
+            UsbMuxRequest::Connect {
+                device_id, port, ..
+            } => (
+                CONNECTED_DEVICES
+                    .get(device_id)
+                    .map(|d| d.serial_number().into_owned()),
+                Some(u16::from_be(*port)),
+            ),
+
+            _ => (None, None),
+        };
+
+        Self {
+            audited: matches!(
+                request,
+                UsbMuxRequest::ReadPairRecord { .. }
+                    | UsbMuxRequest::SavePairRecord { .. }
+                    | UsbMuxRequest::DeletePairRecord { .. }
+                    | UsbMuxRequest::ReadBUID { .. }
+                    | UsbMuxRequest::Connect { .. }
+                    | UsbMuxRequest::AcquireLease { .. }
+            ),
+            time: 0.0,
+            request: request.message_type().to_string(),
+            pid: peer.pid,
+            uid: peer.uid,
+            prog_name: request.common().prog_name.clone(),
+            udid,
+            port,
+            result: None,
+            denied: false,
+            error: None,
+        }
+    }
+
+    /// writes the entry with the result the client got
+    pub fn record(self, result: ResultCode) {
+        self.write(Some(result), None);
+    }
+
+    /// writes the entry for a request the access policy refused
+    pub fn record_denied(mut self, result: ResultCode) {
+        self.denied = true;
+        self.write(Some(result), None);
+    }
+
+    /// writes the entry for a request that failed, the client may not have gotten anything back
+    pub fn record_error(self, result: Option<ResultCode>, error: &RusbmuxError) {
+        self.write(result, Some(error));
+    }
+
+    fn write(mut self, result: Option<ResultCode>, error: Option<&RusbmuxError>) {
+        let Some(log) = audit_log().filter(|_| self.audited) else {
+            return;
+        };
+
+        self.time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0.0, |d| d.as_secs_f64());
+        self.result = result.map(|r| r as u16);
+        self.error = error.map(ToString::to_string);
+
+        if let Err(e) = log.write(&self) {
+            error!(err = %e, path = ?log.path(), "Failed to write to the audit log");
+        }
+    }
+}
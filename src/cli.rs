@@ -0,0 +1,481 @@
+use std::{
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use clap::{Parser, Subcommand, ValueEnum};
+use rusbmux::{
+    config::{ConfigFile, DEFAULT_CONFIG_PATH},
+    daemon::{DaemonOptions, ListenAddr},
+    error::RusbmuxError,
+    handler::LOCKDOWN_PATH,
+    pair_record::{
+        EncryptedPairRecordStore, FsPairRecordStore, LockdownLayout, PairRecordBundle,
+        PairRecordKey, PairRecordStore, open_store, read_store_system_buid, restore_pair_record,
+    },
+    usb_backend::UsbBackendKind,
+};
+use tracing::info;
+use tracing_subscriber::EnvFilter;
+
+#[derive(Debug, Parser)]
+#[command(version, about = "A usbmuxd compatible daemon")]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// a unix socket to listen on, it can be given more than once
+    #[cfg(unix)]
+    #[arg(long, value_name = "PATH")]
+    pub socket: Vec<PathBuf>,
+
+    /// a tcp address to listen on (e.g. 127.0.0.1:27015), it can be given more than once
+    #[arg(long, value_name = "ADDR")]
+    pub tcp: Vec<SocketAddr>,
+
+    /// a vsock port to listen on, so the VMs on this host can reach the devices
+    #[cfg(target_os = "linux")]
+    #[arg(long, value_name = "PORT")]
+    pub vsock: Vec<u32>,
+
+    /// the permissions of the unix sockets, in octal (e.g. 660)
+    #[cfg(unix)]
+    #[arg(long, value_name = "MODE", value_parser = parse_mode)]
+    pub socket_mode: Option<u32>,
+
+    /// the group that owns the unix sockets
+    #[cfg(unix)]
+    #[arg(long, value_name = "GROUP")]
+    pub socket_group: Option<String>,
+
+    /// the lock file that keeps a second instance away, it's only used for the default socket if
+    /// it's not given
+    #[cfg(unix)]
+    #[arg(long, value_name = "PATH")]
+    pub pid_file: Option<PathBuf>,
+
+    /// ask the running rusbmux or usbmuxd to exit, instead of refusing to start
+    #[cfg(unix)]
+    #[arg(short = 'X', long)]
+    pub replace: bool,
+
+    /// where the pair records are kept
+    #[arg(long, value_name = "DIR", global = true)]
+    pub lockdown_dir: Option<PathBuf>,
+
+    /// the configuration file, the flags win over it
+    #[arg(long, value_name = "PATH", global = true)]
+    pub config: Option<PathBuf>,
+
+    /// the log filter, e.g. `debug` or `rusbmux=trace`, `RUST_LOG` is used if it's not given
+    #[arg(long, value_name = "FILTER", global = true)]
+    pub log_level: Option<String>,
+
+    /// how the logs are printed
+    #[arg(long, value_enum, default_value_t = LogFormat::Text, global = true)]
+    pub log_format: LogFormat,
+
+    /// record who read or changed the pair records and who connected to what, as json lines
+    #[arg(long, value_name = "PATH")]
+    pub audit_log: Option<PathBuf>,
+
+    /// don't watch for usb devices
+    #[arg(long)]
+    pub no_usb: bool,
+
+    /// don't watch for network devices
+    #[arg(long)]
+    pub no_network: bool,
+
+    /// stay in the foreground (the default, it's what service managers expect)
+    #[arg(short, long, overrides_with = "daemonize")]
+    pub foreground: bool,
+
+    /// detach from the terminal and run in the background, the logs are discarded
+    #[cfg(unix)]
+    #[arg(short, long)]
+    pub daemonize: bool,
+
+    /// exit once there were no devices and no clients for this many seconds
+    #[arg(long, value_name = "SECS")]
+    pub exit_on_idle: Option<u64>,
+
+    /// how many seconds the connections get to finish once the daemon is asked to stop
+    #[arg(long, value_name = "SECS")]
+    pub shutdown_grace: Option<u64>,
+
+    /// started by udev for a device, it stays in the foreground and exits once idle
+    #[cfg(target_os = "linux")]
+    #[arg(short, long, conflicts_with = "daemonize")]
+    pub udev: bool,
+
+    /// which usb backend to use (nusb or rusb, if they're compiled in)
+    #[arg(long, value_name = "BACKEND")]
+    pub usb_backend: Option<UsbBackendKind>,
+
+    /// drop root and run as this user once the sockets are bound
+    #[cfg(unix)]
+    #[arg(short = 'U', long, value_name = "USER")]
+    pub user: Option<String>,
+
+    /// the group to run as, the user's primary group if it's not given
+    #[cfg(unix)]
+    #[arg(long, value_name = "GROUP", requires = "user")]
+    pub group: Option<String>,
+
+    /// keep a small root helper around to open the usb devices the user can't
+    #[cfg(all(target_os = "linux", feature = "nusb"))]
+    #[arg(long, requires = "user")]
+    pub usb_helper: bool,
+
+    /// restrict the filesystem (landlock) and the syscalls (seccomp) once it's started
+    #[cfg(target_os = "linux")]
+    #[arg(long)]
+    pub sandbox: bool,
+}
+
+/// how long to stay around in udev mode, if nothing else was given
+#[cfg(target_os = "linux")]
+const UDEV_EXIT_ON_IDLE: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+impl Cli {
+    /// the given configuration file has to exist, the default one doesn't
+    pub fn load_config(&self) -> Result<(PathBuf, ConfigFile), RusbmuxError> {
+        match &self.config {
+            Some(path) => Ok((path.clone(), ConfigFile::load(path)?)),
+            None => {
+                let path = PathBuf::from(DEFAULT_CONFIG_PATH);
+                let file = ConfigFile::load_or_default(&path)?;
+                Ok((path, file))
+            }
+        }
+    }
+
+    pub fn daemon_options(&self, config_path: PathBuf, file: &ConfigFile) -> DaemonOptions {
+        let mut options = DaemonOptions::default();
+        file.apply_options(&mut options);
+
+        // the listeners given as flags replace the ones from the file
+        let mut listeners = Vec::new();
+
+        #[cfg(unix)]
+        listeners.extend(self.socket.iter().cloned().map(ListenAddr::Unix));
+
+        listeners.extend(self.tcp.iter().copied().map(ListenAddr::Tcp));
+
+        #[cfg(target_os = "linux")]
+        listeners.extend(self.vsock.iter().copied().map(ListenAddr::Vsock));
+
+        if !listeners.is_empty() {
+            options.listeners = listeners;
+        }
+
+        options.lockdown_dir = self.lockdown_dir(file);
+        options.usb &= !self.no_usb;
+        options.network &= !self.no_network;
+        options.usb_backend = self.usb_backend.unwrap_or(options.usb_backend);
+        options.config_path = Some(config_path);
+
+        if let Some(path) = &self.audit_log {
+            options.audit_log = Some(file.audit.log_options(path.clone()));
+        }
+
+        #[cfg(unix)]
+        if let Some(user) = &self.user {
+            options.user = Some(user.clone());
+            options.group.clone_from(&self.group);
+        }
+
+        #[cfg(unix)]
+        {
+            if let Some(path) = &self.pid_file {
+                options.pid_file = Some(path.clone());
+            }
+
+            options.replace |= self.replace;
+        }
+
+        #[cfg(unix)]
+        {
+            options.socket_mode = self.socket_mode.unwrap_or(options.socket_mode);
+
+            if let Some(group) = &self.socket_group {
+                options.socket_group = Some(group.clone());
+            }
+        }
+
+        #[cfg(all(target_os = "linux", feature = "nusb"))]
+        {
+            options.usb_helper |= self.usb_helper;
+        }
+
+        #[cfg(target_os = "linux")]
+        {
+            options.sandbox |= self.sandbox;
+        }
+
+        if let Some(secs) = self.exit_on_idle {
+            options.exit_on_idle = Some(Duration::from_secs(secs));
+        }
+
+        if let Some(secs) = self.shutdown_grace {
+            options.shutdown_grace = Duration::from_secs(secs);
+        }
+
+        #[cfg(target_os = "linux")]
+        if self.udev && options.exit_on_idle.is_none() {
+            options.exit_on_idle = Some(UDEV_EXIT_ON_IDLE);
+        }
+
+        options
+    }
+
+    pub fn lockdown_dir(&self, file: &ConfigFile) -> PathBuf {
+        self.lockdown_dir
+            .clone()
+            .or_else(|| file.pair_records.lockdown_dir.clone())
+            .unwrap_or_else(|| PathBuf::from(LOCKDOWN_PATH))
+    }
+
+    pub fn init_tracing(&self) {
+        let filter = match &self.log_level {
+            Some(level) => EnvFilter::new(level),
+            None => EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("error")),
+        };
+
+        let subscriber = tracing_subscriber::fmt().with_env_filter(filter);
+
+        match self.log_format {
+            LogFormat::Text => subscriber.init(),
+            LogFormat::Json => subscriber.json().init(),
+        }
+    }
+
+    /// whether to detach from the terminal
+    pub fn daemonize(&self) -> bool {
+        #[cfg(unix)]
+        return self.daemonize && !self.foreground;
+
+        #[cfg(windows)]
+        return false;
+    }
+}
+
+#[cfg(unix)]
+fn parse_mode(s: &str) -> Result<u32, String> {
+    u32::from_str_radix(s.trim_start_matches("0o"), 8)
+        .ok()
+        .filter(|mode| *mode <= 0o7777)
+        .ok_or_else(|| format!("`{s}` is not an octal mode"))
+}
+
+/// forks into the background, it must be called before the runtime is created
+#[cfg(unix)]
+pub fn daemonize() -> std::io::Result<()> {
+    use std::os::fd::AsRawFd;
+
+    // SAFETY: there's only one thread at this point
+    match unsafe { libc::fork() } {
+        -1 => return Err(std::io::Error::last_os_error()),
+        0 => {}
+        _ => std::process::exit(0),
+    }
+
+    // SAFETY: we're the child, so we're not a process group leader
+    if unsafe { libc::setsid() } == -1 {
+        return Err(std::io::Error::last_os_error());
+    }
+
+    std::env::set_current_dir("/")?;
+
+    let dev_null = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open("/dev/null")?;
+
+    for fd in [libc::STDIN_FILENO, libc::STDOUT_FILENO, libc::STDERR_FILENO] {
+        // SAFETY: both fds are valid
+        if unsafe { libc::dup2(dev_null.as_raw_fd(), fd) } == -1 {
+            return Err(std::io::Error::last_os_error());
+        }
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// bundles pair records and the SystemBUID into one file
+    Export {
+        /// where to write the bundle
+        #[arg(short, long)]
+        output: PathBuf,
+
+        #[command(flatten)]
+        source: LockdownDir,
+
+        /// the udids to export, all of them if none is given
+        udids: Vec<String>,
+    },
+
+    /// saves the pair records from a bundle
+    Import {
+        /// the bundle to import
+        input: PathBuf,
+
+        #[command(flatten)]
+        target: LockdownDir,
+
+        /// make the records use this host's SystemBUID instead of the one they were made with
+        #[arg(long)]
+        rewrite_system_buid: bool,
+
+        /// import the records made with another SystemBUID as they are, they won't connect until
+        /// the device is paired again
+        #[arg(long, conflicts_with = "rewrite_system_buid")]
+        force: bool,
+
+        /// the udids to import, all of them if none is given
+        udids: Vec<String>,
+    },
+
+    /// puts the pair records that were quarantined for being stale back
+    Restore {
+        #[command(flatten)]
+        target: LockdownDir,
+
+        /// the udids to restore
+        #[arg(required = true)]
+        udids: Vec<String>,
+    },
+
+    /// turns the sealed pair records back into plaintext, e.g. before going back to usbmuxd
+    ///
+    /// the daemon must be stopped first, it seals them again otherwise
+    Decrypt,
+
+    /// the root helper started by the daemon with `--usb-helper`, it's not meant to be run by hand
+    #[cfg(all(target_os = "linux", feature = "nusb"))]
+    #[command(name = "usb-helper", hide = true)]
+    UsbHelper,
+}
+
+/// which lockdown directory to use, this host's store if nothing is given
+#[derive(Debug, clap::Args)]
+pub struct LockdownDir {
+    /// a lockdown directory, e.g. one copied from another host
+    #[arg(long, conflicts_with = "layout")]
+    dir: Option<PathBuf>,
+
+    /// the default lockdown directory of a platform (linux, macos or windows)
+    #[arg(long)]
+    layout: Option<LockdownLayout>,
+}
+
+impl LockdownDir {
+    /// other directories are read as is, only this host's store gets sealed records
+    fn open(&self, lockdown_dir: &Path) -> Result<Arc<dyn PairRecordStore>, RusbmuxError> {
+        match (&self.dir, self.layout) {
+            (Some(dir), _) => Ok(Arc::new(FsPairRecordStore::new(dir))),
+            (None, Some(layout)) if Path::new(layout.path()) != lockdown_dir => {
+                Ok(Arc::new(FsPairRecordStore::new(layout.path())))
+            }
+            (None, _) => open_store(lockdown_dir),
+        }
+    }
+}
+
+pub async fn run_command(command: &Command, lockdown_dir: &Path) -> Result<(), RusbmuxError> {
+    match command {
+        Command::Export {
+            output,
+            source,
+            udids,
+        } => {
+            let bundle =
+                PairRecordBundle::export(source.open(lockdown_dir)?.as_ref(), udids).await?;
+            bundle.write_to_file(output).await?;
+
+            // the logs are off by default, so the result is printed
+            println!(
+                "Exported {} pair records to {}",
+                bundle.records.len(),
+                output.display()
+            );
+        }
+
+        Command::Import {
+            input,
+            target,
+            rewrite_system_buid,
+            force,
+            udids,
+        } => {
+            let mut bundle = PairRecordBundle::read_from_file(input).await?;
+
+            if !udids.is_empty() {
+                bundle.records.retain(|udid, _| udids.contains(udid));
+            }
+
+            let store = target.open(lockdown_dir)?;
+            store.init().await?;
+
+            if *rewrite_system_buid
+                && let Some(system_buid) = read_store_system_buid(store.as_ref()).await?
+            {
+                info!(system_buid, "Rewriting the SystemBUID of the records");
+                bundle.rewrite_system_buid(&system_buid);
+            }
+
+            let imported = bundle.import(store.as_ref(), *force).await?;
+
+            println!("Imported {} pair records", imported.len());
+            for udid in &imported {
+                println!("  {udid}");
+            }
+        }
+
+        Command::Restore { target, udids } => {
+            let store = target.open(lockdown_dir)?;
+            store.init().await?;
+
+            for udid in udids {
+                restore_pair_record(store.as_ref(), udid).await?;
+                println!("Restored {udid}");
+            }
+        }
+
+        Command::Decrypt => {
+            let key = PairRecordKey::find()?.ok_or_else(|| {
+                RusbmuxError::PairRecordCrypto("there is no pair record key".to_string())
+            })?;
+
+            let store =
+                EncryptedPairRecordStore::new(Arc::new(FsPairRecordStore::new(lockdown_dir)), &key);
+
+            let decrypted = store.decrypt_all().await?;
+
+            println!("Decrypted {} pair records", decrypted.len());
+            for id in &decrypted {
+                println!("  {id}");
+            }
+        }
+
+        #[cfg(all(target_os = "linux", feature = "nusb"))]
+        Command::UsbHelper => {
+            tokio::task::spawn_blocking(rusbmux::usb_backend::helper::serve_usb_helper)
+                .await
+                .map_err(std::io::Error::other)??;
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,525 @@
+use std::{
+    collections::BTreeMap,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use serde::{Deserialize, Deserializer};
+use tracing::info;
+
+use crate::{
+    audit::AuditLogOptions,
+    config::{Config, PowerAssertionPolicy, Transport},
+    daemon::{DaemonOptions, Limits, ListenAddr},
+    error::RusbmuxError,
+    handler::{
+        access::{AccessPolicy, AccessRule},
+        visibility::Visibility,
+    },
+    usb_backend::UsbBackendKind,
+};
+
+#[cfg(unix)]
+pub const DEFAULT_CONFIG_PATH: &str = "/etc/rusbmux/config.toml";
+
+#[cfg(windows)]
+pub const DEFAULT_CONFIG_PATH: &str = "C:\\ProgramData\\rusbmux\\config.toml";
+
+/// the configuration file, everything in it is optional
+///
+/// the durations are in seconds
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ConfigFile {
+    /// where to accept the clients, the flags win over it
+    #[serde(rename = "listener")]
+    pub listeners: Vec<ListenerConfig>,
+
+    pub discovery: DiscoveryConfig,
+    pub devices: DevicesConfig,
+    pub timeouts: TimeoutsConfig,
+    pub pair_records: PairRecordsConfig,
+    pub power_assertion: PowerAssertionConfig,
+    pub activation: ActivationConfig,
+    pub privileges: PrivilegesConfig,
+    pub access: AccessConfig,
+    pub audit: AuditConfig,
+    pub limits: Limits,
+    pub leases: LeasesConfig,
+    pub instance: InstanceConfig,
+}
+
+/// a listener, and what it's clients can see
+///
+/// the filters still apply when the same listener is given as a flag
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct ListenerConfig {
+    #[serde(flatten)]
+    pub addr: ListenAddr,
+
+    #[serde(flatten)]
+    pub visibility: Visibility,
+
+    /// `deny_unknown_fields` doesn't work with `flatten`, whatever is left ends up here
+    #[serde(flatten)]
+    unknown: BTreeMap<String, toml::Value>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LeasesConfig {
+    /// wait for the exclusive lease to be released, instead of refusing the connection
+    pub queue_connects: Option<bool>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct InstanceConfig {
+    pub pid_file: Option<PathBuf>,
+    pub replace: Option<bool>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuditConfig {
+    pub path: Option<PathBuf>,
+
+    /// in bytes
+    pub max_size: Option<u64>,
+    pub keep: Option<usize>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AccessConfig {
+    /// the permissions of the unix sockets, e.g. `0o660`
+    pub socket_mode: Option<u32>,
+
+    /// the group that owns the unix sockets
+    pub socket_group: Option<String>,
+
+    /// what the clients no rule matched can do, it's matchers are ignored
+    pub default: Option<AccessRule>,
+
+    #[serde(rename = "rule")]
+    pub rules: Vec<AccessRule>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PrivilegesConfig {
+    pub user: Option<String>,
+    pub group: Option<String>,
+    pub usb_helper: Option<bool>,
+    pub sandbox: Option<bool>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ActivationConfig {
+    pub exit_on_idle: Option<u64>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DiscoveryConfig {
+    pub usb: Option<bool>,
+    pub network: Option<bool>,
+    pub usb_backend: Option<UsbBackendKind>,
+    pub transport: Option<Transport>,
+    pub interfaces: Vec<String>,
+    pub ignored_interfaces: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DevicesConfig {
+    pub allow: Vec<String>,
+    pub deny: Vec<String>,
+    pub preflight: Option<bool>,
+    pub auto_pair: Option<bool>,
+    pub enable_wifi_connections: Option<bool>,
+    pub properties: Option<bool>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TimeoutsConfig {
+    pub preflight: Option<u64>,
+    pub pairing: Option<u64>,
+    pub shutdown_grace: Option<u64>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PairRecordsConfig {
+    pub lockdown_dir: Option<PathBuf>,
+    pub poll_interval: Option<u64>,
+    pub quarantine_stale: Option<bool>,
+    pub notify_stale: Option<bool>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PowerAssertionConfig {
+    pub policy: Option<PowerAssertionPolicy>,
+    pub timeout: Option<u64>,
+    pub renewal_interval: Option<u64>,
+}
+
+impl AuditConfig {
+    /// the log at `path` with the rest of the settings, the path can come from a flag
+    #[must_use]
+    pub fn log_options(&self, path: PathBuf) -> AuditLogOptions {
+        let mut log = AuditLogOptions::new(path);
+        log.max_size = self.max_size.unwrap_or(log.max_size);
+        log.keep = self.keep.unwrap_or(log.keep);
+        log
+    }
+}
+
+impl ConfigFile {
+    pub fn parse(s: &str) -> Result<Self, RusbmuxError> {
+        let file: Self = toml::from_str(s).map_err(|e| RusbmuxError::Config(e.to_string()))?;
+        file.validate().map_err(RusbmuxError::Config)?;
+
+        Ok(file)
+    }
+
+    /// it's read before the runtime starts, so it's blocking
+    pub fn load(path: &Path) -> Result<Self, RusbmuxError> {
+        let s = std::fs::read_to_string(path)?;
+
+        Self::parse(&s).map_err(|e| match e {
+            RusbmuxError::Config(e) => RusbmuxError::Config(format!("{}: {e}", path.display())),
+            e => e,
+        })
+    }
+
+    /// what serde can't check by it self
+    fn validate(&self) -> Result<(), String> {
+        for listener in &self.listeners {
+            if let Some(key) = listener.unknown.keys().next() {
+                return Err(format!(
+                    "unknown field `{key}` in listener `{}`",
+                    listener.addr
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// like `load`, but a missing file is the same as an empty one
+    pub fn load_or_default(path: &Path) -> Result<Self, RusbmuxError> {
+        match Self::load(path) {
+            Err(RusbmuxError::IO(e)) if e.kind() == ErrorKind::NotFound => {
+                info!(?path, "No configuration file, using the defaults");
+                Ok(Self::default())
+            }
+            res => res,
+        }
+    }
+
+    /// the configuration the file describes, anything it doesn't set is left at the default
+    #[must_use]
+    pub fn to_config(&self) -> Config {
+        let mut config = Config::default();
+
+        let secs = |s: Option<u64>, default: Duration| s.map_or(default, Duration::from_secs);
+
+        config.preflight = self.devices.preflight.unwrap_or(config.preflight);
+        config.auto_pair = self.devices.auto_pair.unwrap_or(config.auto_pair);
+        config.enable_wifi_connections = self
+            .devices
+            .enable_wifi_connections
+            .unwrap_or(config.enable_wifi_connections);
+        config.device_properties = self.devices.properties.unwrap_or(config.device_properties);
+        config.allowed_devices.clone_from(&self.devices.allow);
+        config.denied_devices.clone_from(&self.devices.deny);
+
+        config.transport = self.discovery.transport.unwrap_or(config.transport);
+        config
+            .network_interfaces
+            .clone_from(&self.discovery.interfaces);
+        config
+            .ignored_network_interfaces
+            .clone_from(&self.discovery.ignored_interfaces);
+
+        config.preflight_timeout = secs(self.timeouts.preflight, config.preflight_timeout);
+        config.pairing_timeout = secs(self.timeouts.pairing, config.pairing_timeout);
+
+        config.pair_record_poll_interval = secs(
+            self.pair_records.poll_interval,
+            config.pair_record_poll_interval,
+        );
+        config.quarantine_stale_pair_records = self
+            .pair_records
+            .quarantine_stale
+            .unwrap_or(config.quarantine_stale_pair_records);
+        config.notify_stale_pair_records = self
+            .pair_records
+            .notify_stale
+            .unwrap_or(config.notify_stale_pair_records);
+
+        config.power_assertion = self
+            .power_assertion
+            .policy
+            .unwrap_or(config.power_assertion);
+        config.power_assertion_timeout =
+            secs(self.power_assertion.timeout, config.power_assertion_timeout);
+        config.power_assertion_renewal_interval = secs(
+            self.power_assertion.renewal_interval,
+            config.power_assertion_renewal_interval,
+        );
+
+        config.limits = self.limits.clone();
+        config.visibility = self
+            .listeners
+            .iter()
+            .map(|l| (l.addr.canonical(), l.visibility.clone()))
+            .collect();
+        config.queue_leased_connects = self
+            .leases
+            .queue_connects
+            .unwrap_or(config.queue_leased_connects);
+
+        config.access = AccessPolicy {
+            rules: self.access.rules.clone(),
+            default: self.access.default.clone().unwrap_or_default(),
+        };
+
+        config
+    }
+
+    /// sets the options the file has on the daemon options
+    pub fn apply_options(&self, options: &mut DaemonOptions) {
+        if !self.listeners.is_empty() {
+            options.listeners = self.listeners.iter().map(|l| l.addr.clone()).collect();
+        }
+
+        if let Some(dir) = &self.pair_records.lockdown_dir {
+            options.lockdown_dir.clone_from(dir);
+        }
+
+        options.usb = self.discovery.usb.unwrap_or(options.usb);
+        options.network = self.discovery.network.unwrap_or(options.network);
+        options.usb_backend = self.discovery.usb_backend.unwrap_or(options.usb_backend);
+
+        if let Some(secs) = self.activation.exit_on_idle {
+            options.exit_on_idle = Some(Duration::from_secs(secs));
+        }
+
+        if let Some(secs) = self.timeouts.shutdown_grace {
+            options.shutdown_grace = Duration::from_secs(secs);
+        }
+
+        if let Some(path) = &self.audit.path {
+            options.audit_log = Some(self.audit.log_options(path.clone()));
+        }
+
+        #[cfg(unix)]
+        if let Some(user) = &self.privileges.user {
+            options.user = Some(user.clone());
+            options.group.clone_from(&self.privileges.group);
+        }
+
+        #[cfg(unix)]
+        {
+            if let Some(path) = &self.instance.pid_file {
+                options.pid_file = Some(path.clone());
+            }
+
+            options.replace = self.instance.replace.unwrap_or(options.replace);
+        }
+
+        #[cfg(unix)]
+        {
+            options.socket_mode = self.access.socket_mode.unwrap_or(options.socket_mode);
+
+            if let Some(group) = &self.access.socket_group {
+                options.socket_group = Some(group.clone());
+            }
+        }
+
+        #[cfg(all(target_os = "linux", feature = "nusb"))]
+        {
+            options.usb_helper = self.privileges.usb_helper.unwrap_or(options.usb_helper);
+        }
+
+        #[cfg(target_os = "linux")]
+        {
+            options.sandbox = self.privileges.sandbox.unwrap_or(options.sandbox);
+        }
+    }
+
+    /// the settings that changed from `self` to `new` but are only read once the daemon starts
+    #[must_use]
+    pub fn restart_required(&self, new: &Self) -> Vec<&'static str> {
+        let mut changed = Vec::new();
+
+        // the filters are re-read, only the addresses need a restart
+        let addrs = |file: &Self| {
+            file.listeners
+                .iter()
+                .map(|l| l.addr.clone())
+                .collect::<Vec<_>>()
+        };
+
+        if addrs(self) != addrs(new) {
+            changed.push("listener");
+        }
+
+        if self.discovery.usb != new.discovery.usb {
+            changed.push("discovery.usb");
+        }
+
+        if self.discovery.network != new.discovery.network {
+            changed.push("discovery.network");
+        }
+
+        if self.discovery.usb_backend != new.discovery.usb_backend {
+            changed.push("discovery.usb_backend");
+        }
+
+        if self.discovery.transport != new.discovery.transport {
+            changed.push("discovery.transport");
+        }
+
+        if self.pair_records.lockdown_dir != new.pair_records.lockdown_dir {
+            changed.push("pair_records.lockdown_dir");
+        }
+
+        if self.timeouts.shutdown_grace != new.timeouts.shutdown_grace {
+            changed.push("timeouts.shutdown_grace");
+        }
+
+        if self.privileges != new.privileges {
+            changed.push("privileges");
+        }
+
+        if self.access.socket_mode != new.access.socket_mode {
+            changed.push("access.socket_mode");
+        }
+
+        if self.access.socket_group != new.access.socket_group {
+            changed.push("access.socket_group");
+        }
+
+        if self.instance != new.instance {
+            changed.push("instance");
+        }
+
+        if self.audit != new.audit {
+            changed.push("audit");
+        }
+
+        if self.activation != new.activation {
+            changed.push("activation.exit_on_idle");
+        }
+
+        changed
+    }
+}
+
+/// the enums are written the same way they're displayed
+macro_rules! deserialize_from_str {
+    ($($ty:ty),* $(,)?) => {
+        $(
+            impl<'de> Deserialize<'de> for $ty {
+                fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                    String::deserialize(deserializer)?
+                        .parse()
+                        .map_err(serde::de::Error::custom)
+                }
+            }
+        )*
+    };
+}
+
+deserialize_from_str!(Transport, PowerAssertionPolicy, UsbBackendKind);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXAMPLE: &str = include_str!("../../config.example.toml");
+
+    /// the example with every setting in it turned on
+    fn uncommented_example() -> String {
+        EXAMPLE
+            .lines()
+            .map(|line| match line.strip_prefix('#') {
+                Some(rest)
+                    if rest.starts_with('[')
+                        || rest.split_once('=').is_some_and(|(key, _)| {
+                            key.trim()
+                                .chars()
+                                .all(|c| c.is_ascii_lowercase() || c == '_')
+                        }) =>
+                {
+                    rest
+                }
+                _ => line,
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    #[test]
+    fn parses_the_example() {
+        assert_eq!(ConfigFile::parse(EXAMPLE).unwrap(), ConfigFile::default());
+    }
+
+    #[test]
+    fn parses_every_setting_of_the_example() {
+        let file = ConfigFile::parse(&uncommented_example()).unwrap();
+
+        assert_eq!(file.listeners.len(), 4);
+        assert!(file.listeners[1].visibility.devices.is_some());
+        assert_eq!(file.leases.queue_connects, Some(false));
+
+        let _ = file.to_config();
+    }
+
+    #[test]
+    fn rejects_unknown_keys() {
+        assert!(matches!(
+            ConfigFile::parse("[devices]\nunknown = 1\n"),
+            Err(RusbmuxError::Config(_))
+        ));
+
+        assert!(matches!(
+            ConfigFile::parse("[[listener]]\nunix = \"/tmp/a\"\nunknown = 1\n"),
+            Err(RusbmuxError::Config(_))
+        ));
+    }
+
+    #[test]
+    fn unlisted_listeners_see_nothing_once_one_is_filtered() {
+        let dir = std::env::temp_dir();
+        let filtered = ConfigFile::parse(&format!(
+            "[[listener]]\nunix = \"{}/./a\"\ndevices = [\"udid\"]\n",
+            dir.display()
+        ))
+        .unwrap()
+        .to_config();
+
+        // the same socket written differently is still found
+        let socket = ListenAddr::Unix(dir.join("a")).canonical();
+        assert_eq!(
+            filtered.visibility_for(&socket).devices,
+            Some(vec!["udid".to_string()])
+        );
+
+        let other = ListenAddr::Unix(dir.join("b")).canonical();
+        assert_eq!(*filtered.visibility_for(&other), Visibility::NONE);
+
+        let unfiltered =
+            ConfigFile::parse(&format!("[[listener]]\nunix = \"{}/a\"\n", dir.display()))
+                .unwrap()
+                .to_config();
+        assert_eq!(*unfiltered.visibility_for(&other), Visibility::ALL);
+    }
+}
@@ -0,0 +1,266 @@
+use std::{
+    fmt,
+    str::FromStr,
+    sync::{Arc, LazyLock, PoisonError, RwLock},
+    time::Duration,
+};
+
+use crate::{
+    daemon::{Limits, ListenAddr},
+    handler::{access::AccessPolicy, visibility::Visibility},
+};
+
+#[cfg(feature = "bin")]
+mod file;
+
+#[cfg(feature = "bin")]
+pub use file::{
+    AccessConfig, ActivationConfig, AuditConfig, ConfigFile, DEFAULT_CONFIG_PATH, DevicesConfig,
+    DiscoveryConfig, InstanceConfig, LeasesConfig, ListenerConfig, PairRecordsConfig,
+    PowerAssertionConfig, PrivilegesConfig, TimeoutsConfig,
+};
+
+/// the daemon wide configuration
+///
+/// everything that is optional is off by default, so the daemon behaves like usbmuxd unless asked
+/// otherwise
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// talk to lockdownd before announcing a usb device, to collect it's trust state, name and
+    /// version
+    pub preflight: bool,
+
+    /// how long the preflight is allowed to take before the device gets announced without it
+    pub preflight_timeout: Duration,
+
+    /// pair with untrusted usb devices by it self, instead of waiting for a client to do it
+    ///
+    /// it needs the preflight to know the trust state, so it runs it even if `preflight` is off
+    pub auto_pair: bool,
+
+    /// how long to wait for the user to accept the trust dialog
+    pub pairing_timeout: Duration,
+
+    /// turn on the WiFi connections on paired usb devices, so they can be reached over the
+    /// network once unplugged
+    pub enable_wifi_connections: bool,
+
+    /// read and cache the lockdown properties (name, model, version...) of every device once it's
+    /// attached
+    pub device_properties: bool,
+
+    /// how often to re-read the pair records if the store can't be watched for changes
+    pub pair_record_poll_interval: Duration,
+
+    /// move the pair records the devices rejected out of the way, so they're not used again
+    pub quarantine_stale_pair_records: bool,
+
+    /// send a `PairRecordStale` message to the `Listen` clients when a device rejects our pair
+    /// record, so they can ask the user to pair again
+    pub notify_stale_pair_records: bool,
+
+    /// the only devices (by udid) that are exposed, every device is if it's empty
+    pub allowed_devices: Vec<String>,
+
+    /// the devices (by udid) that are never exposed, it wins over `allowed_devices`
+    pub denied_devices: Vec<String>,
+
+    /// what to do when a device is reachable over both usb and the network
+    ///
+    /// it's only read once the daemon starts
+    pub transport: Transport,
+
+    /// the only network interfaces (e.g. `wlan0`) that network devices are used over, every
+    /// interface is if it's empty
+    pub network_interfaces: Vec<String>,
+
+    /// the network interfaces that network devices are never used over
+    pub ignored_network_interfaces: Vec<String>,
+
+    /// whether network devices need a power assertion to stay awake
+    pub power_assertion: PowerAssertionPolicy,
+
+    /// how long a single power assertion holds the device awake
+    pub power_assertion_timeout: Duration,
+
+    /// how often the power assertion gets renewed, it should be less than the timeout
+    pub power_assertion_renewal_interval: Duration,
+
+    /// what every client is allowed to do
+    pub access: AccessPolicy,
+
+    /// how many clients, connections and requests there can be
+    pub limits: Limits,
+
+    /// make the `Connect`s to a device another client has an exclusive lease on wait for it to be
+    /// released, instead of refusing them
+    pub queue_leased_connects: bool,
+
+    /// what the clients of each listener can see, the listeners that aren't in it see everything
+    pub visibility: Vec<(ListenAddr, Visibility)>,
+}
+
+/// how a device that is connected over both usb and the network is exposed
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Transport {
+    /// only the usb one is exposed, the network one takes it's place once it's unplugged
+    #[default]
+    PreferUsb,
+
+    /// both are exposed as two devices, like usbmuxd on macOS does
+    Both,
+}
+
+impl fmt::Display for Transport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::PreferUsb => "prefer-usb",
+            Self::Both => "both",
+        })
+    }
+}
+
+impl FromStr for Transport {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "prefer-usb" => Ok(Self::PreferUsb),
+            "both" => Ok(Self::Both),
+            _ => Err(format!(
+                "unknown transport `{s}`, expected prefer-usb or both"
+            )),
+        }
+    }
+}
+
+/// what happens when a network device refuses the power assertion
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PowerAssertionPolicy {
+    /// the device is not used without it, it would fall asleep anyway
+    #[default]
+    Required,
+
+    /// the device is still used, it may fall asleep while idle
+    BestEffort,
+
+    /// never ask for one
+    Off,
+}
+
+impl fmt::Display for PowerAssertionPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Required => "required",
+            Self::BestEffort => "best-effort",
+            Self::Off => "off",
+        })
+    }
+}
+
+impl FromStr for PowerAssertionPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "required" => Ok(Self::Required),
+            "best-effort" => Ok(Self::BestEffort),
+            "off" => Ok(Self::Off),
+            _ => Err(format!(
+                "unknown power assertion policy `{s}`, expected required, best-effort or off"
+            )),
+        }
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            preflight: false,
+            preflight_timeout: Duration::from_secs(10),
+            auto_pair: false,
+            pairing_timeout: Duration::from_secs(5 * 60),
+            enable_wifi_connections: false,
+            device_properties: false,
+            pair_record_poll_interval: Duration::from_secs(5),
+            quarantine_stale_pair_records: false,
+            notify_stale_pair_records: false,
+            allowed_devices: Vec::new(),
+            denied_devices: Vec::new(),
+            transport: Transport::default(),
+            network_interfaces: Vec::new(),
+            ignored_network_interfaces: Vec::new(),
+            power_assertion: PowerAssertionPolicy::default(),
+            power_assertion_timeout: Duration::from_secs(20 * 60),
+            power_assertion_renewal_interval: Duration::from_secs(10 * 60),
+            access: AccessPolicy::default(),
+            limits: Limits::default(),
+            queue_leased_connects: false,
+            visibility: Vec::new(),
+        }
+    }
+}
+
+impl Config {
+    /// whether anything needs the trust state from the preflight
+    #[must_use]
+    pub const fn needs_preflight(&self) -> bool {
+        self.preflight || self.auto_pair || self.enable_wifi_connections
+    }
+
+    /// whether the device with the given udid can be exposed
+    #[must_use]
+    pub fn is_device_allowed(&self, udid: &str) -> bool {
+        !self.denied_devices.iter().any(|d| d == udid)
+            && (self.allowed_devices.is_empty() || self.allowed_devices.iter().any(|d| d == udid))
+    }
+
+    /// what the clients that came in on the listener (by it's [`ListenAddr::canonical`] address)
+    /// can see
+    ///
+    /// a listener without an entry sees nothing once another one is filtered, so a socket that is
+    /// written differently doesn't see every device by mistake
+    #[must_use]
+    pub fn visibility_for(&self, listener: &ListenAddr) -> &Visibility {
+        match self.visibility.iter().find(|(addr, _)| addr == listener) {
+            Some((_, visibility)) => visibility,
+            None if self.has_filtered_listeners() => {
+                static NONE: Visibility = Visibility::NONE;
+                &NONE
+            }
+            None => &Visibility::ALL,
+        }
+    }
+
+    /// whether any listener has a filter (or it's own `SystemBUID` or pair records)
+    #[must_use]
+    pub fn has_filtered_listeners(&self) -> bool {
+        self.visibility
+            .iter()
+            .any(|(_, visibility)| *visibility != Visibility::ALL)
+    }
+
+    /// whether network devices can be used over the given interface
+    #[must_use]
+    pub fn is_interface_allowed(&self, name: &str) -> bool {
+        !self.ignored_network_interfaces.iter().any(|i| i == name)
+            && (self.network_interfaces.is_empty()
+                || self.network_interfaces.iter().any(|i| i == name))
+    }
+}
+
+static CONFIG: LazyLock<RwLock<Arc<Config>>> =
+    LazyLock::new(|| RwLock::new(Arc::new(Config::default())));
+
+/// gets the current configuration
+///
+/// the returned value is a snapshot, it won't change if the config got replaced later
+#[must_use]
+pub fn config() -> Arc<Config> {
+    Arc::clone(&CONFIG.read().unwrap_or_else(PoisonError::into_inner))
+}
+
+/// replaces the current configuration
+pub fn set_config(config: Config) {
+    *CONFIG.write().unwrap_or_else(PoisonError::into_inner) = Arc::new(config);
+}
@@ -327,6 +327,47 @@ impl UsbDeviceConn {
         Ok(())
     }
 
+    /// closes the connection gracefully, unlike `close` whatever was sent before it still reaches
+    /// the device
+    pub async fn finish(&self) -> Result<(), RusbmuxError> {
+        self.set_dropped();
+        self.send_fin().await?;
+
+        if let Some(router) = self.device_router.upgrade() {
+            router.unregister(self.source_port);
+        }
+
+        Ok(())
+    }
+
+    pub async fn send_fin(&self) -> Result<(), RusbmuxError> {
+        debug!(
+            src = self.source_port,
+            dst = self.destination_port,
+            "Finishing connection"
+        );
+
+        let fin_packet = UsbDevicePacket::builder()
+            .header_tcp(AUTO_SEQ, AUTO_SEQ)
+            .tcp_header(
+                self.source_port,
+                self.destination_port,
+                self.get_sent_bytes(),
+                self.get_received_bytes(),
+                TcpFlags::FIN | TcpFlags::ACK,
+            )
+            .build();
+
+        self.tx.send(fin_packet).await?;
+
+        trace!(
+            src = self.source_port,
+            dst = self.destination_port,
+            "Sent FIN"
+        );
+        Ok(())
+    }
+
     pub async fn ack(&self) -> Result<(), RusbmuxError> {
         let tcp_ack = UsbDevicePacket::builder()
             .header_tcp(AUTO_SEQ, AUTO_SEQ)
@@ -403,6 +444,11 @@ impl UsbDeviceConn {
         self.device_core.canceler.cancelled().await;
         Ok(())
     }
+
+    /// resolves once the device is being drained
+    pub async fn wait_drain(&self) {
+        self.device_core.drainer.cancelled().await;
+    }
 }
 
 // atomic setters and getters
@@ -0,0 +1,58 @@
+use std::{
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
+
+use tracing::{debug, info, warn};
+
+use crate::watcher::{CONNECTED_DEVICES, DeviceEvent, HOTPLUG_EVENT_TX};
+
+/// set once the daemon starts shutting down, no new `Connect` or `Listen` is accepted after it
+static DRAINING: AtomicBool = AtomicBool::new(false);
+
+/// how long the connections get to finish, if nothing else was given
+pub const DEFAULT_SHUTDOWN_GRACE: Duration = Duration::from_secs(5);
+
+#[inline]
+pub fn is_draining() -> bool {
+    DRAINING.load(Ordering::Relaxed)
+}
+
+/// the first half of shutting down, the devices are still there, but they're on their way out
+///
+/// the listeners are told every device is detached, and the usb connections finish what they have
+/// and close with a FIN, it gives up once the grace period is over, what's left gets RST by
+/// `cleanup`
+pub async fn drain(grace: Duration) {
+    DRAINING.store(true, Ordering::Relaxed);
+
+    info!(?grace, "Draining the connections");
+
+    if let Some(tx) = HOTPLUG_EVENT_TX.get() {
+        for device in CONNECTED_DEVICES.iter() {
+            let _ = tx.send(DeviceEvent::Detached { id: device.id() });
+        }
+    }
+
+    let mut draining = tokio::task::JoinSet::new();
+
+    for device in CONNECTED_DEVICES.iter() {
+        if let Some(dev) = device.as_usb().cloned() {
+            draining.spawn(async move { dev.drain().await });
+        }
+    }
+
+    let drained = tokio::time::timeout(grace, async {
+        while draining.join_next().await.is_some() {}
+    })
+    .await;
+
+    if drained.is_ok() {
+        debug!("Every connection was drained");
+    } else {
+        warn!(
+            left = draining.len(),
+            "The grace period is over, closing the rest"
+        );
+    }
+}
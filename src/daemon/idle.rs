@@ -0,0 +1,69 @@
+use std::{
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Duration,
+};
+
+use tokio::time::Instant;
+use tracing::debug;
+
+use crate::watcher::CONNECTED_DEVICES;
+
+/// how many clients are connected right now
+static ACTIVE_CLIENTS: AtomicUsize = AtomicUsize::new(0);
+
+/// counts a client as connected for as long as it's alive
+pub struct ClientGuard(());
+
+impl ClientGuard {
+    /// `None` if there are already `max` clients
+    pub fn try_new(max: Option<usize>) -> Option<Self> {
+        ACTIVE_CLIENTS
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| {
+                max.is_none_or(|max| n < max).then_some(n + 1)
+            })
+            .ok()?;
+
+        Some(Self(()))
+    }
+}
+
+impl Drop for ClientGuard {
+    fn drop(&mut self) {
+        ACTIVE_CLIENTS.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+#[must_use]
+pub fn active_clients() -> usize {
+    ACTIVE_CLIENTS.load(Ordering::Relaxed)
+}
+
+fn is_idle() -> bool {
+    active_clients() == 0 && CONNECTED_DEVICES.is_empty()
+}
+
+/// returns once there were no devices and no clients for the whole timeout
+///
+/// it's checked every quarter of the timeout, so it may take a bit longer than that
+pub async fn wait_idle(timeout: Duration) {
+    let mut interval = tokio::time::interval((timeout / 4).max(Duration::from_secs(1)));
+    let mut idle_since = None;
+
+    loop {
+        interval.tick().await;
+
+        if !is_idle() {
+            idle_since = None;
+            continue;
+        }
+
+        let since = *idle_since.get_or_insert_with(|| {
+            debug!("No devices and no clients, waiting before exiting");
+            Instant::now()
+        });
+
+        if since.elapsed() >= timeout {
+            return;
+        }
+    }
+}
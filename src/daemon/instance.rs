@@ -0,0 +1,226 @@
+use std::{
+    fs::File,
+    io::{self, Write},
+    mem,
+    os::{fd::AsRawFd, unix::fs::OpenOptionsExt, unix::net::UnixStream},
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use tracing::{debug, info, warn};
+
+use crate::{
+    daemon::{DaemonOptions, LISTENER_PATH, ListenAddr},
+    error::RusbmuxError,
+};
+
+/// the lock of the instance that serves the default socket, if nothing else was given
+pub const PID_FILE: &str = "/var/run/rusbmux.pid";
+
+/// usbmuxd keeps the same kind of lock on it
+pub const USBMUXD_PID_FILE: &str = "/var/run/usbmuxd.pid";
+
+const TAKEOVER_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// on top of the grace period, for the other instance to let go of the devices
+const TAKEOVER_SLACK: Duration = Duration::from_secs(5);
+
+/// the locked pid file, it's held for as long as the daemon runs, so another instance knows it's
+/// here
+///
+/// the file is left behind once it exits, only the lock says if it's still running
+#[derive(Debug)]
+pub struct Instance {
+    _pid_file: Option<File>,
+}
+
+impl Instance {
+    /// makes sure nothing else serves the same sockets, what's there is asked to exit (SIGTERM)
+    /// if `replace` is set, like `usbmuxd -X`, otherwise it's an error
+    ///
+    /// it's blocking, it may wait for the other instance to drain it's connections
+    pub fn claim(options: &DaemonOptions) -> Result<Self, RusbmuxError> {
+        let serves_default = options.listeners.iter().any(
+            |addr| matches!(addr, ListenAddr::Unix(path) if path.as_os_str() == LISTENER_PATH),
+        );
+
+        // the isolated instances with their own sockets don't take the lock unless asked to
+        let pid_file = options
+            .pid_file
+            .clone()
+            .or_else(|| serves_default.then(|| PathBuf::from(PID_FILE)));
+
+        let deadline = Instant::now() + options.shutdown_grace + TAKEOVER_SLACK;
+        let mut replaced = false;
+
+        let pid_file = match pid_file {
+            Some(path) => {
+                let file = File::options()
+                    .read(true)
+                    .write(true)
+                    .create(true)
+                    .truncate(false)
+                    .mode(0o644)
+                    .open(&path)?;
+
+                if let Some(pid) = try_lock(&file)? {
+                    stop(pid, "rusbmux", options.replace)?;
+                    replaced = true;
+
+                    if !wait_until(deadline, || matches!(try_lock(&file), Ok(None))) {
+                        return Err(RusbmuxError::AlreadyRunning(format!(
+                            "rusbmux ({pid}) didn't exit in time"
+                        )));
+                    }
+                }
+
+                file.set_len(0)?;
+                writeln!(&file, "{}", std::process::id())?;
+
+                debug!(?path, "Locked the pid file");
+
+                Some(file)
+            }
+            None => None,
+        };
+
+        if serves_default && let Some(pid) = lock_holder(Path::new(USBMUXD_PID_FILE))? {
+            stop(pid, "usbmuxd", options.replace)?;
+            replaced = true;
+
+            let exited = wait_until(deadline, || {
+                matches!(lock_holder(Path::new(USBMUXD_PID_FILE)), Ok(None))
+            });
+
+            if !exited {
+                return Err(RusbmuxError::AlreadyRunning(format!(
+                    "usbmuxd ({pid}) didn't exit in time"
+                )));
+            }
+        }
+
+        // the sockets systemd passed are ours, something answering on them is systemd itself
+        #[cfg(target_os = "linux")]
+        let activated = super::systemd::is_socket_activated();
+
+        #[cfg(not(target_os = "linux"))]
+        let activated = false;
+
+        if !activated {
+            for addr in &options.listeners {
+                let ListenAddr::Unix(path) = addr else {
+                    continue;
+                };
+
+                // whatever was asked to exit may still be closing it's socket
+                if is_serving(path) && !(replaced && wait_until(deadline, || !is_serving(path))) {
+                    return Err(RusbmuxError::AlreadyRunning(format!(
+                        "something is already serving on {}, and it's not known what, it has to be \
+                         stopped by hand",
+                        path.display()
+                    )));
+                }
+            }
+        }
+
+        Ok(Self {
+            _pid_file: pid_file,
+        })
+    }
+}
+
+/// asks the other instance to exit, if it's allowed to
+fn stop(pid: libc::pid_t, what: &str, replace: bool) -> Result<(), RusbmuxError> {
+    if !replace {
+        return Err(RusbmuxError::AlreadyRunning(format!(
+            "{what} is running as {pid}, pass `--replace` to take over"
+        )));
+    }
+
+    info!(pid, what, "Asking the running instance to exit");
+
+    // SAFETY: no pointers are involved
+    if unsafe { libc::kill(pid, libc::SIGTERM) } < 0 {
+        let e = io::Error::last_os_error();
+
+        // it's already gone
+        if e.raw_os_error() != Some(libc::ESRCH) {
+            return Err(RusbmuxError::AlreadyRunning(format!(
+                "failed to stop {what} ({pid}): {e}"
+            )));
+        }
+    }
+
+    Ok(())
+}
+
+fn wait_until(deadline: Instant, mut done: impl FnMut() -> bool) -> bool {
+    while !done() {
+        if Instant::now() >= deadline {
+            return false;
+        }
+
+        std::thread::sleep(TAKEOVER_POLL_INTERVAL);
+    }
+
+    true
+}
+
+fn is_serving(path: &Path) -> bool {
+    UnixStream::connect(path).is_ok()
+}
+
+fn whole_file_lock(kind: libc::c_int) -> libc::flock {
+    // SAFETY: all zeros is a valid `flock`
+    let mut lock: libc::flock = unsafe { mem::zeroed() };
+    lock.l_type = kind as _;
+    lock.l_whence = libc::SEEK_SET as _;
+    lock
+}
+
+/// locks the file, or gives the pid of who has it
+///
+/// it's a `fcntl` lock like usbmuxd uses, so it goes away with the process
+fn try_lock(file: &File) -> io::Result<Option<libc::pid_t>> {
+    loop {
+        let lock = whole_file_lock(libc::F_WRLCK);
+
+        // SAFETY: the fd and the lock are valid
+        if unsafe { libc::fcntl(file.as_raw_fd(), libc::F_SETLK, &lock) } == 0 {
+            return Ok(None);
+        }
+
+        let e = io::Error::last_os_error();
+        if !matches!(e.raw_os_error(), Some(libc::EAGAIN | libc::EACCES)) {
+            return Err(e);
+        }
+
+        // it may have been let go of in between
+        if let Some(pid) = holder(file)? {
+            return Ok(Some(pid));
+        }
+    }
+}
+
+fn holder(file: &File) -> io::Result<Option<libc::pid_t>> {
+    let mut lock = whole_file_lock(libc::F_WRLCK);
+
+    // SAFETY: the fd and the lock are valid
+    if unsafe { libc::fcntl(file.as_raw_fd(), libc::F_GETLK, &mut lock) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok((libc::c_int::from(lock.l_type) != libc::F_UNLCK).then_some(lock.l_pid))
+}
+
+/// who holds the lock on the file, if anyone
+fn lock_holder(path: &Path) -> io::Result<Option<libc::pid_t>> {
+    match File::open(path) {
+        Ok(file) => holder(&file),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => {
+            warn!(?path, err = %e, "Failed to check the pid file");
+            Ok(None)
+        }
+    }
+}
@@ -0,0 +1,292 @@
+use std::{
+    fmt,
+    hash::Hash,
+    sync::{
+        LazyLock,
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+    },
+    time::Duration,
+};
+
+use dashmap::DashMap;
+use serde::Deserialize;
+use tokio::time::Instant;
+
+use crate::{
+    config::config,
+    daemon::idle::{self, ClientGuard},
+    handler::access::Peer,
+};
+
+/// the peers are told apart by their uid, the tcp and vsock clients have none, so they all count
+/// as one
+type PeerKey = Option<u32>;
+
+/// how much the clients can take from the daemon and the devices, nothing is limited by default
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Limits {
+    pub max_clients: Option<usize>,
+    pub max_clients_per_peer: Option<usize>,
+
+    /// the `Listen` clients, every one of them gets every hotplug event
+    pub max_listeners: Option<usize>,
+
+    /// the open connections to a single device, every one of them takes a source port
+    pub max_connects_per_device: Option<usize>,
+
+    /// the open connections to a single port of a device
+    pub max_connects_per_port: Option<usize>,
+
+    /// the requests a peer can make in a second, over all of it's clients
+    pub max_requests_per_second: Option<u32>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Limit {
+    Clients,
+    ClientsPerPeer,
+    Listeners,
+    ConnectsPerDevice,
+    ConnectsPerPort,
+    RequestRate,
+}
+
+impl Limit {
+    pub const ALL: [Self; 6] = [
+        Self::Clients,
+        Self::ClientsPerPeer,
+        Self::Listeners,
+        Self::ConnectsPerDevice,
+        Self::ConnectsPerPort,
+        Self::RequestRate,
+    ];
+
+    #[must_use]
+    pub const fn as_str(&self) -> &'static str {
+        match self {
+            Self::Clients => "Clients",
+            Self::ClientsPerPeer => "ClientsPerPeer",
+            Self::Listeners => "Listeners",
+            Self::ConnectsPerDevice => "ConnectsPerDevice",
+            Self::ConnectsPerPort => "ConnectsPerPort",
+            Self::RequestRate => "RequestRate",
+        }
+    }
+}
+
+impl fmt::Display for Limit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// how many times each limit refused something, in the order of [`Limit::ALL`]
+static REFUSED: [AtomicU64; 6] = [const { AtomicU64::new(0) }; 6];
+
+static CLIENTS_PER_PEER: LazyLock<DashMap<PeerKey, usize>> = LazyLock::new(DashMap::new);
+static LISTENERS: AtomicUsize = AtomicUsize::new(0);
+static CONNECTS_PER_DEVICE: LazyLock<DashMap<u64, usize>> = LazyLock::new(DashMap::new);
+static CONNECTS_PER_PORT: LazyLock<DashMap<(u64, u16), usize>> = LazyLock::new(DashMap::new);
+
+/// when the current second started for each peer, and how many requests it made in it
+static REQUESTS: LazyLock<DashMap<PeerKey, (Instant, u32)>> = LazyLock::new(DashMap::new);
+
+fn refused(limit: Limit) -> Limit {
+    REFUSED[limit as usize].fetch_add(1, Ordering::Relaxed);
+    limit
+}
+
+fn take<K: Hash + Eq + Copy>(counts: &DashMap<K, usize>, key: K, max: Option<usize>) -> bool {
+    let mut count = counts.entry(key).or_default();
+
+    if max.is_some_and(|max| *count >= max) {
+        drop(count);
+        counts.remove_if(&key, |_, count| *count == 0);
+        return false;
+    }
+
+    *count += 1;
+    true
+}
+
+fn give_back<K: Hash + Eq + Copy>(counts: &DashMap<K, usize>, key: K) {
+    if let Some(mut count) = counts.get_mut(&key) {
+        *count = count.saturating_sub(1);
+    }
+
+    counts.remove_if(&key, |_, count| *count == 0);
+}
+
+/// a client, it's given back once it's dropped
+pub struct ClientSlot {
+    _guard: ClientGuard,
+    peer: PeerKey,
+}
+
+impl Drop for ClientSlot {
+    fn drop(&mut self) {
+        give_back(&CLIENTS_PER_PEER, self.peer);
+    }
+}
+
+/// a `Listen` client, it's given back once it's dropped
+pub struct ListenerSlot(());
+
+impl Drop for ListenerSlot {
+    fn drop(&mut self) {
+        LISTENERS.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// an open connection to a device, it's given back once it's dropped
+pub struct ConnectSlot {
+    device_id: u64,
+    port: u16,
+}
+
+impl Drop for ConnectSlot {
+    fn drop(&mut self) {
+        give_back(&CONNECTS_PER_PORT, (self.device_id, self.port));
+        give_back(&CONNECTS_PER_DEVICE, self.device_id);
+    }
+}
+
+/// counts a new client, unless there are already too many
+pub fn admit_client(peer: &Peer) -> Result<ClientSlot, Limit> {
+    let limits = &config().limits;
+
+    let guard = ClientGuard::try_new(limits.max_clients).ok_or_else(|| refused(Limit::Clients))?;
+
+    if !take(&CLIENTS_PER_PEER, peer.uid, limits.max_clients_per_peer) {
+        return Err(refused(Limit::ClientsPerPeer));
+    }
+
+    Ok(ClientSlot {
+        _guard: guard,
+        peer: peer.uid,
+    })
+}
+
+pub fn take_listener() -> Result<ListenerSlot, Limit> {
+    let max = config().limits.max_listeners;
+
+    LISTENERS
+        .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| {
+            max.is_none_or(|max| n < max).then_some(n + 1)
+        })
+        .map_err(|_| refused(Limit::Listeners))?;
+
+    Ok(ListenerSlot(()))
+}
+
+/// the port is in host order
+pub fn take_connect(device_id: u64, port: u16) -> Result<ConnectSlot, Limit> {
+    let limits = &config().limits;
+
+    if !take(
+        &CONNECTS_PER_DEVICE,
+        device_id,
+        limits.max_connects_per_device,
+    ) {
+        return Err(refused(Limit::ConnectsPerDevice));
+    }
+
+    if !take(
+        &CONNECTS_PER_PORT,
+        (device_id, port),
+        limits.max_connects_per_port,
+    ) {
+        give_back(&CONNECTS_PER_DEVICE, device_id);
+        return Err(refused(Limit::ConnectsPerPort));
+    }
+
+    Ok(ConnectSlot { device_id, port })
+}
+
+/// counts a request against the peer's rate
+pub fn check_rate(peer: &Peer) -> Result<(), Limit> {
+    let Some(max) = config().limits.max_requests_per_second else {
+        return Ok(());
+    };
+
+    let now = Instant::now();
+    let mut window = REQUESTS.entry(peer.uid).or_insert((now, 0));
+
+    if now.duration_since(window.0) >= Duration::from_secs(1) {
+        *window = (now, 0);
+    }
+
+    if window.1 >= max {
+        return Err(refused(Limit::RequestRate));
+    }
+
+    window.1 += 1;
+
+    Ok(())
+}
+
+/// what a limit is set to, and how close it is
+#[derive(Debug, Clone, Copy)]
+pub struct LimitUsage {
+    pub limit: Limit,
+    pub max: Option<u64>,
+
+    /// for the per peer, device and port limits, it's the one that is the closest
+    pub current: u64,
+
+    pub refused: u64,
+}
+
+#[must_use]
+pub fn usage() -> Vec<LimitUsage> {
+    let limits = config().limits.clone();
+
+    let highest = |counts: &mut dyn Iterator<Item = usize>| counts.max().unwrap_or(0) as u64;
+
+    Limit::ALL
+        .into_iter()
+        .map(|limit| {
+            let (max, current) = match limit {
+                Limit::Clients => (limits.max_clients, idle::active_clients() as u64),
+                Limit::ClientsPerPeer => (
+                    limits.max_clients_per_peer,
+                    highest(&mut CLIENTS_PER_PEER.iter().map(|c| *c)),
+                ),
+                Limit::Listeners => (
+                    limits.max_listeners,
+                    LISTENERS.load(Ordering::Relaxed) as u64,
+                ),
+                Limit::ConnectsPerDevice => (
+                    limits.max_connects_per_device,
+                    highest(&mut CONNECTS_PER_DEVICE.iter().map(|c| *c)),
+                ),
+                Limit::ConnectsPerPort => (
+                    limits.max_connects_per_port,
+                    highest(&mut CONNECTS_PER_PORT.iter().map(|c| *c)),
+                ),
+                Limit::RequestRate => {
+                    let now = Instant::now();
+                    let current = REQUESTS
+                        .iter()
+                        .filter(|w| now.duration_since(w.0) < Duration::from_secs(1))
+                        .map(|w| w.1)
+                        .max()
+                        .unwrap_or(0);
+
+                    (
+                        limits.max_requests_per_second.map(|max| max as usize),
+                        u64::from(current),
+                    )
+                }
+            };
+
+            LimitUsage {
+                limit,
+                max: max.map(|max| max as u64),
+                current,
+                refused: REFUSED[limit as usize].load(Ordering::Relaxed),
+            }
+        })
+        .collect()
+}
@@ -0,0 +1,852 @@
+use std::{fmt, net::SocketAddr, path::PathBuf, time::Duration};
+
+use crate::{
+    ReadWrite,
+    audit::AuditLogOptions,
+    error::RusbmuxError,
+    handler::{LOCKDOWN_PATH, access::Peer},
+    usb_backend::UsbBackendKind,
+};
+use tracing::{debug, error, info, warn};
+
+mod drain;
+mod idle;
+pub mod limits;
+
+#[cfg(unix)]
+mod instance;
+
+#[cfg(unix)]
+mod privileges;
+
+#[cfg(target_os = "linux")]
+mod vsock;
+
+#[cfg(target_os = "linux")]
+mod sandbox;
+
+#[cfg(target_os = "linux")]
+pub mod systemd;
+
+pub use drain::{DEFAULT_SHUTDOWN_GRACE, is_draining};
+pub use idle::active_clients;
+pub use limits::Limits;
+
+#[cfg(unix)]
+pub use instance::{Instance, PID_FILE};
+
+#[cfg(unix)]
+pub use privileges::User;
+
+#[cfg(target_os = "linux")]
+pub use sandbox::Sandbox;
+
+#[cfg(target_os = "linux")]
+pub use vsock::{VsockListener, VsockStream};
+
+#[cfg(unix)]
+pub const LISTENER_PATH: &str = "/var/run/usbmuxd";
+
+#[cfg(windows)]
+pub const LISTENER_PATH: &str = "127.0.0.1:27015";
+
+#[cfg(unix)]
+pub const DEFAULT_SOCKET_MODE: u32 = 0o666;
+
+/// where the daemon accepts the clients
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ListenAddr {
+    #[cfg(unix)]
+    Unix(PathBuf),
+    Tcp(SocketAddr),
+
+    /// an `AF_VSOCK` port, for the VMs on this host
+    #[cfg(target_os = "linux")]
+    Vsock(u32),
+}
+
+impl Default for ListenAddr {
+    fn default() -> Self {
+        #[cfg(unix)]
+        return Self::Unix(PathBuf::from(LISTENER_PATH));
+
+        #[cfg(windows)]
+        return Self::Tcp(LISTENER_PATH.parse().expect("the default address is valid"));
+    }
+}
+
+impl ListenAddr {
+    /// the same address, with the unix socket's folder resolved (e.g. `/var/run` is `/run`), so the
+    /// ones written differently can be compared
+    ///
+    /// the path is left as is if it's folder doesn't exist (yet)
+    #[must_use]
+    pub fn canonical(&self) -> Self {
+        match self {
+            #[cfg(unix)]
+            Self::Unix(path) => {
+                let resolved = path
+                    .parent()
+                    .zip(path.file_name())
+                    .and_then(|(dir, name)| Some(std::fs::canonicalize(dir).ok()?.join(name)));
+
+                Self::Unix(resolved.unwrap_or_else(|| path.clone()))
+            }
+            other => other.clone(),
+        }
+    }
+}
+
+impl fmt::Display for ListenAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            #[cfg(unix)]
+            Self::Unix(path) => write!(f, "unix:{}", path.display()),
+            Self::Tcp(addr) => write!(f, "tcp:{addr}"),
+            #[cfg(target_os = "linux")]
+            Self::Vsock(port) => write!(f, "vsock:{port}"),
+        }
+    }
+}
+
+/// the settings that are only read once the daemon starts
+#[derive(Debug, Clone)]
+pub struct DaemonOptions {
+    /// all of them are served at the same time
+    pub listeners: Vec<ListenAddr>,
+    pub lockdown_dir: PathBuf,
+
+    /// watch for usb devices
+    pub usb: bool,
+
+    /// watch for network devices over mDNS
+    pub network: bool,
+
+    pub usb_backend: UsbBackendKind,
+
+    /// the configuration file that gets re-read on SIGHUP
+    pub config_path: Option<PathBuf>,
+
+    /// exit once there were no devices and no clients for this long, so it can be started on
+    /// demand (by udev or socket activation) instead of running all the time
+    pub exit_on_idle: Option<Duration>,
+
+    /// how long the connections get to finish once the daemon is asked to stop
+    pub shutdown_grace: Duration,
+
+    /// where the pair record reads and changes, and the connects are recorded, see
+    /// [`crate::audit::AuditLog`]
+    pub audit_log: Option<AuditLogOptions>,
+
+    /// drop root and run as this user once the listeners are bound and the pair records are
+    /// ready
+    #[cfg(unix)]
+    pub user: Option<String>,
+
+    /// the group to run as, the user's primary group if it's not set
+    #[cfg(unix)]
+    pub group: Option<String>,
+
+    /// the permissions of the unix sockets, anyone can connect by default like usbmuxd
+    #[cfg(unix)]
+    pub socket_mode: u32,
+
+    /// the group that owns the unix sockets, so `socket_mode` can let only it in
+    #[cfg(unix)]
+    pub socket_group: Option<String>,
+
+    /// the lock that keeps a second instance from taking the sockets, [`PID_FILE`] is used if
+    /// it's not set and the default socket is served
+    #[cfg(unix)]
+    pub pid_file: Option<PathBuf>,
+
+    /// ask the running instance (rusbmux or usbmuxd) to exit instead of refusing to start
+    #[cfg(unix)]
+    pub replace: bool,
+
+    /// keep a root helper around to open the usb devices, for when the user can't
+    #[cfg(all(target_os = "linux", feature = "nusb"))]
+    pub usb_helper: bool,
+
+    /// restrict the filesystem with landlock and the syscalls with seccomp, see [`Sandbox`]
+    #[cfg(target_os = "linux")]
+    pub sandbox: bool,
+}
+
+impl Default for DaemonOptions {
+    fn default() -> Self {
+        Self {
+            listeners: vec![ListenAddr::default()],
+            lockdown_dir: PathBuf::from(LOCKDOWN_PATH),
+            usb: true,
+            network: true,
+            usb_backend: UsbBackendKind::default(),
+            config_path: None,
+            exit_on_idle: None,
+            shutdown_grace: DEFAULT_SHUTDOWN_GRACE,
+            audit_log: None,
+            #[cfg(unix)]
+            user: None,
+            #[cfg(unix)]
+            group: None,
+            #[cfg(unix)]
+            socket_mode: DEFAULT_SOCKET_MODE,
+            #[cfg(unix)]
+            socket_group: None,
+            #[cfg(unix)]
+            pid_file: None,
+            #[cfg(unix)]
+            replace: false,
+            #[cfg(all(target_os = "linux", feature = "nusb"))]
+            usb_helper: false,
+            #[cfg(target_os = "linux")]
+            sandbox: false,
+        }
+    }
+}
+
+pub enum Listener {
+    #[cfg(unix)]
+    Unix(tokio::net::UnixListener),
+    Tcp(tokio::net::TcpListener),
+    #[cfg(target_os = "linux")]
+    Vsock(VsockListener),
+}
+
+impl Listener {
+    /// the peer is only known for the unix sockets
+    pub async fn accept(&self) -> std::io::Result<(Box<dyn ReadWrite>, Peer)> {
+        Ok(match self {
+            #[cfg(unix)]
+            Self::Unix(l) => {
+                let (socket, _) = l.accept().await?;
+                let peer = Peer::from_unix(&socket);
+                (Box::new(socket), peer)
+            }
+            Self::Tcp(l) => {
+                let (socket, _) = l.accept().await?;
+                socket.set_nodelay(true)?;
+                (Box::new(socket), Peer::default())
+            }
+            #[cfg(target_os = "linux")]
+            Self::Vsock(l) => (Box::new(l.accept().await?), Peer::default()),
+        })
+    }
+}
+
+/// a listener that isn't tied to a runtime, so it can be bound on the one that sets the daemon up and
+/// served on another one
+pub enum DetachedListener {
+    #[cfg(unix)]
+    Unix(std::os::unix::net::UnixListener),
+    Tcp(std::net::TcpListener),
+    #[cfg(target_os = "linux")]
+    Vsock(std::os::fd::OwnedFd),
+}
+
+impl Listener {
+    pub fn detach(self) -> std::io::Result<DetachedListener> {
+        Ok(match self {
+            #[cfg(unix)]
+            Self::Unix(l) => DetachedListener::Unix(l.into_std()?),
+            Self::Tcp(l) => DetachedListener::Tcp(l.into_std()?),
+            #[cfg(target_os = "linux")]
+            Self::Vsock(l) => DetachedListener::Vsock(l.into_fd()),
+        })
+    }
+}
+
+impl DetachedListener {
+    /// it has to be called inside the runtime that serves it
+    pub fn attach(self) -> std::io::Result<Listener> {
+        Ok(match self {
+            #[cfg(unix)]
+            Self::Unix(l) => Listener::Unix(tokio::net::UnixListener::from_std(l)?),
+            Self::Tcp(l) => Listener::Tcp(tokio::net::TcpListener::from_std(l)?),
+            #[cfg(target_os = "linux")]
+            Self::Vsock(fd) => Listener::Vsock(VsockListener::from_fd(fd)?),
+        })
+    }
+}
+
+#[cfg(target_os = "macos")]
+unsafe extern "C" {
+    fn launch_activate_socket(
+        name: *const libc::c_char,
+        fds: *mut *mut libc::c_int,
+        cnt: *mut libc::size_t,
+    ) -> libc::c_int;
+}
+
+#[cfg(target_os = "macos")]
+fn launchd_listener(name: &str) -> Result<Option<tokio::net::UnixListener>, RusbmuxError> {
+    use std::{
+        ffi::CString,
+        os::{
+            fd::{FromRawFd, RawFd},
+            unix::net::UnixListener as StdUnixListener,
+        },
+    };
+
+    let name = CString::new(name).unwrap();
+
+    let mut fds: *mut libc::c_int = std::ptr::null_mut();
+    let mut count: libc::size_t = 0;
+
+    let ret = unsafe { launch_activate_socket(name.as_ptr(), &mut fds, &mut count) };
+
+    if ret != 0 || count == 0 {
+        return Ok(None);
+    }
+
+    let fd: RawFd = unsafe { *fds };
+
+    let listener = unsafe { StdUnixListener::from_raw_fd(fd) };
+
+    listener.set_nonblocking(true)?;
+
+    Ok(Some(tokio::net::UnixListener::from_std(listener)?))
+}
+
+#[cfg(feature = "bin")]
+async fn get_listener(
+    addr: &ListenAddr,
+    options: &DaemonOptions,
+) -> Result<Listener, RusbmuxError> {
+    match addr {
+        #[cfg(unix)]
+        ListenAddr::Unix(path) => {
+            #[cfg(target_os = "macos")]
+            if path.as_os_str() == LISTENER_PATH
+                && let Some(l) = launchd_listener("Listeners")?
+            {
+                // TODO: if it came from launchd, then don't try to remove it or set permissions
+                return Ok(Listener::Unix(l));
+            }
+
+            if path.exists() {
+                debug!(?path, "Socket file already exists, removing...");
+
+                if let Err(e) = std::fs::remove_file(path) {
+                    warn!(err = ?e, "Failed to remove existing socket file");
+                }
+            }
+
+            let listener = tokio::net::UnixListener::bind(path)?;
+
+            debug!("Setting the `ReuseAddr` socket option");
+            if let Err(e) = rustix::net::sockopt::set_socket_reuseaddr(&listener, true) {
+                warn!(err = ?e, "Failed to set ReuseAddr socket option");
+            }
+
+            // macos shuts the entire process if there's something wrong when reading or writing
+            // to the socket, so this stops it
+            #[cfg(target_os = "macos")]
+            {
+                debug!("Setting the `Nosigpipe` socket option");
+                if let Err(e) = rustix::net::sockopt::set_socket_nosigpipe(&listener, true) {
+                    warn!(err = ?e, "Failed to set Nosigpipe socket option");
+                }
+            }
+
+            if let Some(group) = &options.socket_group {
+                let gid = privileges::lookup_group(group)?;
+
+                debug!(group, gid, "Setting the socket group");
+                if let Err(e) = std::os::unix::fs::chown(path, None, Some(gid)) {
+                    warn!(err = ?e, "Failed to set the socket group");
+                }
+            }
+
+            {
+                use std::os::unix::fs::PermissionsExt;
+                debug!(
+                    mode = format!("{:o}", options.socket_mode),
+                    "Setting the socket permissions"
+                );
+                if let Err(e) = std::fs::set_permissions(
+                    path,
+                    std::fs::Permissions::from_mode(options.socket_mode),
+                ) {
+                    warn!(err = ?e, "Failed to set socket permissions");
+                }
+            }
+
+            Ok(Listener::Unix(listener))
+        }
+        ListenAddr::Tcp(addr) => Ok(Listener::Tcp(tokio::net::TcpListener::bind(addr).await?)),
+        #[cfg(target_os = "linux")]
+        ListenAddr::Vsock(port) => Ok(Listener::Vsock(VsockListener::bind(*port)?)),
+    }
+}
+
+/// once a listener is filtered, the ones without an entry of their own would see every device, so
+/// it refuses to start instead
+#[cfg(feature = "bin")]
+fn check_listener_filters<'a>(
+    active: impl Iterator<Item = &'a ListenAddr>,
+) -> Result<(), RusbmuxError> {
+    let config = crate::config::config();
+
+    if !config.has_filtered_listeners() {
+        return Ok(());
+    }
+
+    for addr in active {
+        if !config
+            .visibility
+            .iter()
+            .any(|(configured, _)| configured == addr)
+        {
+            return Err(RusbmuxError::Config(format!(
+                "{addr} has no [[listener]] entry, but other listeners are filtered, add one for it"
+            )));
+        }
+    }
+
+    Ok(())
+}
+
+/// what the daemon set up before it starts serving
+#[cfg(feature = "bin")]
+pub struct Prepared {
+    options: DaemonOptions,
+    listeners: Vec<(ListenAddr, DetachedListener)>,
+    audit_log: Option<crate::audit::OpenedAuditLog>,
+}
+
+/// everything that needs root or a path outside of the sandbox, it binds the listeners, prepares
+/// the pair record store, opens the audit log and spawns the usb helper, then drops root and
+/// applies the sandbox
+///
+/// it has to run on a current thread runtime that is dropped before [`Prepared::serve`], landlock
+/// only restricts the calling thread and the ones it spawns after, so the runtime that serves has
+/// to be built after it
+#[cfg(feature = "bin")]
+pub async fn prepare(options: DaemonOptions) -> Result<Prepared, RusbmuxError> {
+    use crate::pair_record::{
+        migrate_wrapped_records, open_store, pair_record_store, set_pair_record_store,
+    };
+
+    // looked up first, so a typo doesn't leave anything half done
+    #[cfg(unix)]
+    let user = options
+        .user
+        .as_deref()
+        .map(|user| User::lookup(user, options.group.as_deref()))
+        .transpose()?;
+
+    #[cfg(target_os = "linux")]
+    let mut listeners = systemd::listeners()?;
+
+    #[cfg(not(target_os = "linux"))]
+    let mut listeners = Vec::new();
+
+    if listeners.is_empty() {
+        for addr in &options.listeners {
+            info!(listen = %addr, "Listening");
+            listeners.push((addr.clone(), get_listener(addr, &options).await?));
+        }
+    } else {
+        for (addr, _) in &listeners {
+            info!(listen = %addr, "Listening on a socket passed by systemd");
+        }
+
+        for addr in &options.listeners {
+            let canonical = addr.canonical();
+
+            if !listeners
+                .iter()
+                .any(|(active, _)| active.canonical() == canonical)
+            {
+                warn!(listen = %addr, "Configured, but systemd didn't pass it, it's not listened on");
+            }
+        }
+    }
+
+    // the filters are found by the address, so they have to be written the same way
+    let listeners = listeners
+        .into_iter()
+        .map(|(addr, listener)| Ok((addr.canonical(), listener.detach()?)))
+        .collect::<Result<Vec<_>, std::io::Error>>()?;
+
+    check_listener_filters(listeners.iter().map(|(addr, _)| addr))?;
+
+    if set_pair_record_store(open_store(&options.lockdown_dir)?).is_err() {
+        warn!("The pair record store is already set, using it as is");
+    }
+
+    if let Err(e) = pair_record_store().init().await {
+        error!(err = ?e, "Failed to prepare the pair record store");
+    }
+
+    if let Err(e) = migrate_wrapped_records().await {
+        error!(err = ?e, "Failed to migrate the wrapped pair records");
+    }
+
+    let audit_log = options
+        .audit_log
+        .as_ref()
+        .map(|log| {
+            info!(path = ?log.path, "Writing the audit log");
+            crate::audit::AuditLog::open(log.clone())
+        })
+        .transpose()?;
+
+    #[cfg(unix)]
+    if let Some(user) = user {
+        drop_privileges(&user, &options)?;
+    }
+
+    #[cfg(target_os = "linux")]
+    if options.sandbox {
+        Sandbox::for_daemon(&options).apply()?;
+    }
+
+    Ok(Prepared {
+        options,
+        listeners,
+        audit_log,
+    })
+}
+
+#[cfg(feature = "bin")]
+impl Prepared {
+    pub async fn serve(self) -> Result<(), RusbmuxError> {
+        use crate::watcher::{watch_network_daemon, watch_pair_records_daemon};
+
+        let Self {
+            options,
+            listeners,
+            audit_log,
+        } = self;
+
+        let listeners = listeners
+            .into_iter()
+            .map(|(addr, listener)| Ok((addr, listener.attach()?)))
+            .collect::<Result<Vec<_>, std::io::Error>>()?;
+
+        if let Some(log) = audit_log
+            && crate::audit::set_audit_log(log.start()?).is_err()
+        {
+            warn!("The audit log is already set, using it as is");
+        }
+
+        // kept to know if they're still alive
+        let mut watchers = Vec::new();
+
+        if options.usb {
+            info!(backend = %options.usb_backend, "Spawning the device watcher");
+            watchers.push(("usb", spawn_usb_watcher(options.usb_backend)));
+        } else {
+            info!("USB discovery is disabled");
+        }
+
+        if options.network {
+            info!("Spawning the network watcher");
+            watchers.push(("network", tokio::spawn(watch_network_daemon())));
+        } else {
+            info!("Network discovery is disabled");
+        }
+
+        info!("Spawning the pair record watcher");
+        watchers.push(("pair record", tokio::spawn(watch_pair_records_daemon())));
+
+        #[cfg(target_os = "linux")]
+        {
+            systemd::notify("READY=1");
+
+            if let Some(interval) = systemd::watchdog_interval() {
+                info!(?interval, "Feeding the systemd watchdog");
+                tokio::spawn(feed_watchdog(interval, watchers));
+            }
+        }
+
+        #[cfg(unix)]
+        if let Some(path) = options.config_path {
+            tokio::spawn(reload_config_on_sighup(path));
+        }
+
+        let wait_idle = async {
+            match options.exit_on_idle {
+                Some(timeout) => {
+                    info!(?timeout, "Exiting once idle");
+                    idle::wait_idle(timeout).await;
+                }
+                None => std::future::pending().await,
+            }
+        };
+
+        let accepting = start_accepting(listeners);
+        tokio::pin!(accepting);
+
+        tokio::select! {
+            _ = &mut accepting => {}
+            _ = wait_idle => {
+                info!("No devices or clients for a while, exiting...");
+            }
+            _ = tokio::signal::ctrl_c()  => {
+                info!("Got a Ctrl+C, closing...");
+            }
+            _ = wait_shutdown() => {}
+        };
+
+        #[cfg(target_os = "linux")]
+        systemd::notify("STOPPING=1");
+
+        // still accepting, so the new clients are refused properly instead of finding nothing there
+        tokio::select! {
+            _ = &mut accepting => {}
+            _ = drain::drain(options.shutdown_grace) => {}
+        }
+
+        cleanup().await;
+
+        Ok(())
+    }
+}
+
+/// everything that needs root is done by now, apart from opening the usb devices, that's what the
+/// helper is for
+#[cfg(all(feature = "bin", unix))]
+fn drop_privileges(user: &User, options: &DaemonOptions) -> Result<(), RusbmuxError> {
+    #[cfg(all(target_os = "linux", feature = "nusb"))]
+    if options.usb_helper {
+        use crate::usb_backend::helper::{UsbHelper, set_usb_helper};
+
+        if options.usb_backend != UsbBackendKind::Nusb {
+            warn!(backend = %options.usb_backend, "The usb helper only works with nusb");
+        } else if set_usb_helper(UsbHelper::spawn()?).is_err() {
+            warn!("The usb helper is already running");
+        }
+    }
+
+    // the pair records are written as the user from now on
+    if let Err(e) = user.own_dir(&options.lockdown_dir) {
+        warn!(
+            dir = ?options.lockdown_dir,
+            err = %e,
+            "Failed to give the lockdown directory to the user"
+        );
+    }
+
+    // and the audit log is rotated as the user, so it needs it's own directory
+    if let Some(dir) = options.audit_log.as_ref().and_then(|log| log.path.parent())
+        && let Err(e) = user.own_dir(dir)
+    {
+        warn!(?dir, err = %e, "Failed to give the audit log directory to the user");
+    }
+
+    user.switch()
+}
+
+#[cfg(feature = "bin")]
+fn spawn_usb_watcher(backend: UsbBackendKind) -> tokio::task::JoinHandle<()> {
+    use crate::watcher::watch_usb_daemon;
+
+    match backend {
+        #[cfg(feature = "nusb")]
+        UsbBackendKind::Nusb => tokio::spawn(watch_usb_daemon(crate::usb_backend::NusbBackend)),
+        #[cfg(feature = "rusb")]
+        UsbBackendKind::Rusb => tokio::spawn(watch_usb_daemon(crate::usb_backend::RusbBackend)),
+    }
+}
+
+/// pings the watchdog as long as every watcher is alive
+///
+/// once one of them stops, the pings stop too, so systemd restarts the daemon instead of it
+/// silently missing devices
+#[cfg(all(feature = "bin", target_os = "linux"))]
+async fn feed_watchdog(
+    interval: std::time::Duration,
+    watchers: Vec<(&'static str, tokio::task::JoinHandle<()>)>,
+) {
+    loop {
+        if let Some((watcher, _)) = watchers.iter().find(|(_, w)| w.is_finished()) {
+            error!(
+                watcher,
+                "A watcher stopped, not feeding the watchdog anymore"
+            );
+            systemd::notify(&format!("STATUS=The {watcher} watcher stopped"));
+            return;
+        }
+
+        systemd::notify("WATCHDOG=1");
+
+        tokio::time::sleep(interval).await;
+    }
+}
+
+/// re-reads the configuration file on every SIGHUP
+///
+/// what can be changed is applied right away, but the existing devices and connections are kept
+/// as they are, the rest is only logged, it needs a restart
+#[cfg(all(feature = "bin", unix))]
+async fn reload_config_on_sighup(path: PathBuf) {
+    use crate::config::{ConfigFile, config, set_config};
+
+    let Ok(mut sighup) = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup())
+    else {
+        warn!("Failed to register SIGHUP handler");
+        return;
+    };
+
+    // what the daemon started with, the settings that need a restart are compared against it
+    let started = match ConfigFile::load_or_default(&path) {
+        Ok(file) => file,
+        Err(e) => {
+            error!(err = %e, "Failed to read the configuration file");
+            ConfigFile::default()
+        }
+    };
+
+    while sighup.recv().await.is_some() {
+        info!(?path, "Got a SIGHUP signal, reloading the configuration...");
+
+        let file = match ConfigFile::load_or_default(&path) {
+            Ok(file) => file,
+            Err(e) => {
+                error!(err = %e, "Failed to reload the configuration, keeping the current one");
+                continue;
+            }
+        };
+
+        for setting in started.restart_required(&file) {
+            warn!(
+                setting,
+                "The setting was changed, it needs a restart to take effect"
+            );
+        }
+
+        let mut new = file.to_config();
+
+        // only read once at start
+        new.transport = config().transport;
+
+        set_config(new);
+
+        info!("Reloaded the configuration");
+    }
+}
+
+#[cfg(feature = "bin")]
+pub async fn wait_shutdown() {
+    #[cfg(unix)]
+    {
+        let Ok(mut sigterm) =
+            tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+        else {
+            warn!("Failed to register SIGTERM handler");
+            return;
+        };
+
+        tokio::select! {
+            _ = sigterm.recv() => {
+                info!("Got a SIGTERM signal, closing...");
+            }
+        }
+    }
+
+    #[cfg(windows)]
+    {
+        let mut shutdown = match tokio::signal::windows::ctrl_shutdown() {
+            Ok(s) => s,
+            Err(e) => {
+                warn!(err = ?e, "Failed to register ctrl_shutdown handler");
+                return;
+            }
+        };
+        let mut cbreak = match tokio::signal::windows::ctrl_break() {
+            Ok(s) => s,
+            Err(e) => {
+                warn!(err = ?e, "Failed to register ctrl_break handler");
+                return;
+            }
+        };
+        let mut close = match tokio::signal::windows::ctrl_close() {
+            Ok(s) => s,
+            Err(e) => {
+                warn!(err = ?e, "Failed to register ctrl_close handler");
+                return;
+            }
+        };
+        let mut logoff = match tokio::signal::windows::ctrl_logoff() {
+            Ok(s) => s,
+            Err(e) => {
+                warn!(err = ?e, "Failed to register ctrl_logoff handler");
+                return;
+            }
+        };
+        tokio::select! {
+            _ = shutdown.recv() => {
+                info!("Got a shutdown signal, closing...");
+
+            }
+            _ = cbreak.recv() => {
+                info!("Got a break signal, closing...");
+            }
+
+            _ = close.recv() => {
+                info!("Got a close signal, closing...");
+            }
+            _ = logoff.recv() => {
+                info!("Got a logoff signal, closing...");
+            }
+        }
+    }
+}
+
+pub async fn cleanup() {
+    for device in &*crate::watcher::CONNECTED_DEVICES {
+        if let Err(e) = device.shutdown().await {
+            error!(id = device.id(), ?e, "Failed to shutdown device");
+        }
+    }
+
+    // the entries are written on it's own thread, they'd be lost with the process otherwise
+    if let Some(log) = crate::audit::audit_log() {
+        log.flush().await;
+    }
+}
+
+/// accepts the clients of every listener, they're all handled the same way
+#[cfg(feature = "bin")]
+pub async fn start_accepting(listeners: Vec<(ListenAddr, Listener)>) {
+    let mut accepting = tokio::task::JoinSet::new();
+
+    for (addr, listener) in listeners {
+        accepting.spawn(accept_clients(addr, listener));
+    }
+
+    while accepting.join_next().await.is_some() {}
+}
+
+#[cfg(feature = "bin")]
+async fn accept_clients(addr: ListenAddr, listener: Listener) {
+    use crate::handler;
+
+    loop {
+        match listener.accept().await {
+            Ok((socket, peer)) => {
+                let slot = match limits::admit_client(&peer) {
+                    Ok(slot) => slot,
+                    Err(limit) => {
+                        warn!(listen = %addr, uid = peer.uid, pid = peer.pid, %limit, "Too many clients, refusing");
+                        tokio::spawn(handler::refuse_client(socket));
+                        continue;
+                    }
+                };
+
+                info!(listen = %addr, uid = peer.uid, pid = peer.pid, "New connection");
+
+                let addr = addr.clone();
+                tokio::spawn(async move {
+                    handler::handle_client(socket, peer, addr).await;
+                    drop(slot);
+                });
+            }
+            Err(e) => error!(listen = %addr, "Unable to accept the connection: {e:?}"),
+        }
+    }
+}
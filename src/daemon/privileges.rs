@@ -0,0 +1,174 @@
+use std::{
+    ffi::{CString, c_char},
+    io, mem,
+    path::Path,
+    ptr,
+};
+
+use tracing::{debug, info, warn};
+
+use crate::error::RusbmuxError;
+
+/// who the daemon runs as once it's done with what needs root
+#[derive(Debug, Clone)]
+pub struct User {
+    pub name: String,
+    pub uid: libc::uid_t,
+    pub gid: libc::gid_t,
+}
+
+impl User {
+    /// looks the user up, it keeps it's primary group unless another one is given
+    pub fn lookup(name: &str, group: Option<&str>) -> Result<Self, RusbmuxError> {
+        let c_name = CString::new(name)
+            .map_err(|_| RusbmuxError::Privileges(format!("`{name}` is not a valid user name")))?;
+
+        // SAFETY: all zeros is a valid `passwd`
+        let mut pwd: libc::passwd = unsafe { mem::zeroed() };
+        let mut result = ptr::null_mut();
+
+        with_lookup_buf(|buf| {
+            // SAFETY: every pointer is valid, and the buffer is valid for it's whole length
+            unsafe {
+                libc::getpwnam_r(
+                    c_name.as_ptr(),
+                    &mut pwd,
+                    buf.as_mut_ptr(),
+                    buf.len(),
+                    &mut result,
+                )
+            }
+        })?;
+
+        if result.is_null() {
+            return Err(RusbmuxError::Privileges(format!(
+                "there is no `{name}` user"
+            )));
+        }
+
+        let gid = match group {
+            Some(group) => lookup_group(group)?,
+            None => pwd.pw_gid,
+        };
+
+        Ok(Self {
+            name: name.to_owned(),
+            uid: pwd.pw_uid,
+            gid,
+        })
+    }
+
+    /// switches the whole process to the user, there's no way back
+    ///
+    /// the supplementary groups of the user are kept, that's usually how udev gives access to the
+    /// usb devices (e.g. `plugdev`)
+    pub fn switch(&self) -> Result<(), RusbmuxError> {
+        // SAFETY: no pointers are involved
+        if unsafe { libc::geteuid() } == self.uid {
+            debug!(user = self.name, "Already running as the user");
+            return Ok(());
+        }
+
+        let c_name = CString::new(self.name.as_str())
+            .map_err(|_| RusbmuxError::Privileges(format!("`{}` is not valid", self.name)))?;
+
+        // SAFETY: the name is a valid c string
+        if unsafe { libc::initgroups(c_name.as_ptr(), self.gid as _) } < 0 {
+            return Err(last_error("set the supplementary groups"));
+        }
+
+        // the group goes first, it can't be changed once the user isn't root anymore
+        //
+        // SAFETY: no pointers are involved
+        if unsafe { libc::setgid(self.gid) } < 0 {
+            return Err(last_error("set the group"));
+        }
+
+        // SAFETY: no pointers are involved
+        if unsafe { libc::setuid(self.uid) } < 0 {
+            return Err(last_error("set the user"));
+        }
+
+        // SAFETY: no pointers are involved
+        if self.uid != 0 && unsafe { libc::setuid(0) } == 0 {
+            return Err(RusbmuxError::Privileges(
+                "root could be taken back after switching".to_string(),
+            ));
+        }
+
+        info!(
+            user = self.name,
+            uid = self.uid,
+            gid = self.gid,
+            "Dropped the root privileges"
+        );
+
+        Ok(())
+    }
+
+    /// gives the user the directory and everything in it, so it can still write there afterwards
+    pub fn own_dir(&self, path: &Path) -> Result<(), RusbmuxError> {
+        std::os::unix::fs::lchown(path, Some(self.uid), Some(self.gid))?;
+
+        if !path.is_dir() || path.is_symlink() {
+            return Ok(());
+        }
+
+        for entry in std::fs::read_dir(path)? {
+            let entry = entry?;
+
+            if let Err(e) = self.own_dir(&entry.path()) {
+                warn!(path = ?entry.path(), err = %e, "Failed to change the owner");
+            }
+        }
+
+        Ok(())
+    }
+}
+
+pub(super) fn lookup_group(name: &str) -> Result<libc::gid_t, RusbmuxError> {
+    let c_name = CString::new(name)
+        .map_err(|_| RusbmuxError::Privileges(format!("`{name}` is not a valid group name")))?;
+
+    // SAFETY: all zeros is a valid `group`
+    let mut grp: libc::group = unsafe { mem::zeroed() };
+    let mut result = ptr::null_mut();
+
+    with_lookup_buf(|buf| {
+        // SAFETY: every pointer is valid, and the buffer is valid for it's whole length
+        unsafe {
+            libc::getgrnam_r(
+                c_name.as_ptr(),
+                &mut grp,
+                buf.as_mut_ptr(),
+                buf.len(),
+                &mut result,
+            )
+        }
+    })?;
+
+    if result.is_null() {
+        return Err(RusbmuxError::Privileges(format!(
+            "there is no `{name}` group"
+        )));
+    }
+
+    Ok(grp.gr_gid)
+}
+
+/// the `*_r` lookups need a buffer for the strings, it grows until they fit
+fn with_lookup_buf(mut lookup: impl FnMut(&mut [c_char]) -> libc::c_int) -> io::Result<()> {
+    let mut buf = vec![0; 1024];
+
+    loop {
+        match lookup(&mut buf) {
+            0 => return Ok(()),
+            libc::ERANGE => buf.resize(buf.len() * 2, 0),
+            errno => return Err(io::Error::from_raw_os_error(errno)),
+        }
+    }
+}
+
+fn last_error(what: &str) -> RusbmuxError {
+    RusbmuxError::Privileges(format!("failed to {what}: {}", io::Error::last_os_error()))
+}
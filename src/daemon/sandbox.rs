@@ -0,0 +1,657 @@
+use std::{
+    ffi::CString,
+    io, mem,
+    os::{
+        fd::{AsRawFd, FromRawFd, OwnedFd},
+        unix::ffi::OsStrExt,
+    },
+    path::{Path, PathBuf},
+};
+
+use tracing::{debug, info, warn};
+
+use crate::{daemon::DaemonOptions, error::RusbmuxError};
+
+/// what the daemon is still allowed to do once it's running, it's opt-in
+///
+/// it's applied once the daemon is set up (the listeners are bound, root is dropped and the usb
+/// helper is running), but before the runtime that serves is built, landlock only restricts the
+/// calling thread and the ones it spawns after
+#[derive(Debug, Default)]
+pub struct Sandbox {
+    read_only: Vec<PathBuf>,
+    read_write: Vec<PathBuf>,
+
+    /// made before the rules are added, a rule can't be added for what doesn't exist
+    create: Vec<PathBuf>,
+}
+
+impl Sandbox {
+    /// the paths the daemon needs with these options
+    #[must_use]
+    pub fn for_daemon(options: &DaemonOptions) -> Self {
+        let mut sandbox = Self::default();
+
+        // the configuration, the pair record key, the users and the shared libraries (for NSS)
+        for path in ["/etc", "/usr", "/lib", "/lib64"] {
+            sandbox.read_only.push(PathBuf::from(path));
+        }
+
+        // nusb finds the devices through sysfs
+        sandbox.read_only.push(PathBuf::from("/sys"));
+        sandbox.read_only.push(PathBuf::from("/proc"));
+
+        if let Some(dir) = std::env::var_os("CREDENTIALS_DIRECTORY") {
+            sandbox.read_only.push(PathBuf::from(dir));
+        }
+
+        if let Some(path) = &options.config_path
+            && let Some(dir) = path.parent()
+        {
+            sandbox.read_only.push(dir.to_path_buf());
+        }
+
+        sandbox.create.push(options.lockdown_dir.clone());
+        sandbox.read_write.push(options.lockdown_dir.clone());
+        sandbox.read_write.push(PathBuf::from("/dev/null"));
+
+        if options.usb {
+            sandbox.read_write.push(PathBuf::from("/dev/bus/usb"));
+        }
+
+        // the rotated logs are made next to it
+        if let Some(dir) = options.audit_log.as_ref().and_then(|log| log.path.parent()) {
+            sandbox.create.push(dir.to_path_buf());
+            sandbox.read_write.push(dir.to_path_buf());
+        }
+
+        sandbox
+    }
+
+    pub fn apply(&self) -> Result<(), RusbmuxError> {
+        for dir in &self.create {
+            if let Err(e) = std::fs::create_dir_all(dir) {
+                warn!(?dir, err = %e, "Failed to create a directory for the sandbox");
+            }
+        }
+
+        // SAFETY: no pointers are involved
+        if unsafe { libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) } < 0 {
+            return Err(sandbox_error("set no_new_privs"));
+        }
+
+        match landlock::restrict(&self.read_only, &self.read_write) {
+            Ok(abi) => info!(abi, "Restricted the filesystem with landlock"),
+            Err(e) if matches!(e.raw_os_error(), Some(libc::ENOSYS | libc::EOPNOTSUPP)) => {
+                warn!("Landlock isn't available, the filesystem is not restricted");
+            }
+            Err(e) => {
+                return Err(RusbmuxError::Sandbox(format!(
+                    "failed to apply landlock: {e}"
+                )));
+            }
+        }
+
+        seccomp::install()?;
+
+        info!("Installed the seccomp filter, the blocked syscalls are logged");
+
+        Ok(())
+    }
+}
+
+fn sandbox_error(what: &str) -> RusbmuxError {
+    RusbmuxError::Sandbox(format!("failed to {what}: {}", io::Error::last_os_error()))
+}
+
+/// the kernel interface by hand, libc doesn't have the structs
+mod landlock {
+    use super::*;
+
+    const CREATE_RULESET_VERSION: u32 = 1 << 0;
+    const RULE_PATH_BENEATH: libc::c_int = 1;
+
+    const ACCESS_FS_EXECUTE: u64 = 1 << 0;
+    const ACCESS_FS_READ_FILE: u64 = 1 << 2;
+    const ACCESS_FS_READ_DIR: u64 = 1 << 3;
+
+    /// the file only rights, the others are rejected on files
+    const ACCESS_FILE: u64 = ACCESS_FS_EXECUTE | 1 << 1 | ACCESS_FS_READ_FILE | 1 << 14 | 1 << 15;
+
+    const READ_ONLY: u64 = ACCESS_FS_EXECUTE | ACCESS_FS_READ_FILE | ACCESS_FS_READ_DIR;
+
+    #[repr(C)]
+    struct RulesetAttr {
+        handled_access_fs: u64,
+    }
+
+    #[repr(C, packed)]
+    struct PathBeneathAttr {
+        allowed_access: u64,
+        parent_fd: i32,
+    }
+
+    /// what every abi version knows about, the newer ones add to the older ones
+    fn handled_access(abi: i32) -> u64 {
+        match abi {
+            // up to `MAKE_SYM`
+            1 => (1 << 13) - 1,
+            // `REFER`
+            2 => (1 << 14) - 1,
+            // `TRUNCATE`
+            3 | 4 => (1 << 15) - 1,
+            // `IOCTL_DEV`, usbfs is driven with ioctls
+            _ => (1 << 16) - 1,
+        }
+    }
+
+    /// returns the abi version it was applied with
+    pub(super) fn restrict(read_only: &[PathBuf], read_write: &[PathBuf]) -> io::Result<i32> {
+        // SAFETY: asking for the version takes no attributes
+        let abi = unsafe {
+            libc::syscall(
+                libc::SYS_landlock_create_ruleset,
+                std::ptr::null::<RulesetAttr>(),
+                0,
+                CREATE_RULESET_VERSION,
+            )
+        };
+        if abi < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let abi = abi as i32;
+        let handled = handled_access(abi);
+
+        let attr = RulesetAttr {
+            handled_access_fs: handled,
+        };
+
+        // SAFETY: the attributes are valid for their whole size
+        let fd = unsafe {
+            libc::syscall(
+                libc::SYS_landlock_create_ruleset,
+                &raw const attr,
+                mem::size_of::<RulesetAttr>(),
+                0,
+            )
+        };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        // SAFETY: the kernel gave us a new fd
+        let ruleset = unsafe { OwnedFd::from_raw_fd(fd as i32) };
+
+        let rules = read_only
+            .iter()
+            .map(|path| (path, READ_ONLY))
+            .chain(read_write.iter().map(|path| (path, handled)));
+
+        for (path, access) in rules {
+            if let Err(e) = add_rule(&ruleset, path, access & handled) {
+                debug!(?path, err = %e, "Skipping a landlock rule");
+                continue;
+            }
+
+            debug!(
+                ?path,
+                read_write = access == handled,
+                "Added a landlock rule"
+            );
+        }
+
+        // SAFETY: the fd is a landlock ruleset
+        if unsafe { libc::syscall(libc::SYS_landlock_restrict_self, ruleset.as_raw_fd(), 0) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(abi)
+    }
+
+    fn add_rule(ruleset: &OwnedFd, path: &Path, mut access: u64) -> io::Result<()> {
+        let c_path = CString::new(path.as_os_str().as_bytes())?;
+
+        // SAFETY: the path is a valid c string
+        let fd = unsafe { libc::open(c_path.as_ptr(), libc::O_PATH | libc::O_CLOEXEC) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        // SAFETY: open gave us a new fd
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+
+        if !path.is_dir() {
+            access &= ACCESS_FILE;
+        }
+
+        let attr = PathBeneathAttr {
+            allowed_access: access,
+            parent_fd: fd.as_raw_fd(),
+        };
+
+        // SAFETY: the attributes are valid for their whole size
+        let ret = unsafe {
+            libc::syscall(
+                libc::SYS_landlock_add_rule,
+                ruleset.as_raw_fd(),
+                RULE_PATH_BENEATH,
+                &raw const attr,
+                0,
+            )
+        };
+        if ret < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+mod seccomp {
+    use super::*;
+
+    #[cfg(target_arch = "x86_64")]
+    const AUDIT_ARCH: u32 = 0xc000_003e;
+
+    #[cfg(target_arch = "aarch64")]
+    const AUDIT_ARCH: u32 = 0xc000_00b7;
+
+    /// the x32 syscalls on x86_64 have it set, they're not allowed
+    #[cfg(target_arch = "x86_64")]
+    const X32_SYSCALL_BIT: u32 = 0x4000_0000;
+
+    /// what tokio, nusb, mdns-sd and the rest need, anything else is blocked and logged
+    const ALLOWED: &[libc::c_long] = &[
+        // files
+        libc::SYS_read,
+        libc::SYS_write,
+        libc::SYS_readv,
+        libc::SYS_writev,
+        libc::SYS_pread64,
+        libc::SYS_pwrite64,
+        libc::SYS_openat,
+        libc::SYS_close,
+        libc::SYS_close_range,
+        libc::SYS_fstat,
+        libc::SYS_newfstatat,
+        libc::SYS_statx,
+        libc::SYS_lseek,
+        libc::SYS_fcntl,
+        libc::SYS_flock,
+        libc::SYS_getdents64,
+        libc::SYS_faccessat,
+        libc::SYS_faccessat2,
+        libc::SYS_readlinkat,
+        libc::SYS_mkdirat,
+        libc::SYS_unlinkat,
+        libc::SYS_renameat,
+        libc::SYS_renameat2,
+        libc::SYS_fchmod,
+        libc::SYS_fchmodat,
+        libc::SYS_fchown,
+        libc::SYS_fchownat,
+        libc::SYS_ftruncate,
+        libc::SYS_fsync,
+        libc::SYS_fdatasync,
+        libc::SYS_getcwd,
+        libc::SYS_chdir,
+        libc::SYS_umask,
+        libc::SYS_inotify_init1,
+        libc::SYS_inotify_add_watch,
+        libc::SYS_inotify_rm_watch,
+        // memory
+        libc::SYS_mmap,
+        libc::SYS_munmap,
+        libc::SYS_mremap,
+        libc::SYS_mprotect,
+        libc::SYS_madvise,
+        libc::SYS_brk,
+        libc::SYS_membarrier,
+        // sockets
+        libc::SYS_socket,
+        libc::SYS_socketpair,
+        libc::SYS_bind,
+        libc::SYS_listen,
+        libc::SYS_accept4,
+        libc::SYS_connect,
+        libc::SYS_shutdown,
+        libc::SYS_sendto,
+        libc::SYS_recvfrom,
+        libc::SYS_sendmsg,
+        libc::SYS_recvmsg,
+        libc::SYS_sendmmsg,
+        libc::SYS_recvmmsg,
+        libc::SYS_getsockname,
+        libc::SYS_getpeername,
+        libc::SYS_setsockopt,
+        libc::SYS_getsockopt,
+        // polling
+        libc::SYS_epoll_create1,
+        libc::SYS_epoll_ctl,
+        libc::SYS_epoll_pwait,
+        libc::SYS_epoll_pwait2,
+        libc::SYS_ppoll,
+        libc::SYS_pselect6,
+        libc::SYS_eventfd2,
+        libc::SYS_pipe2,
+        libc::SYS_dup,
+        libc::SYS_dup3,
+        libc::SYS_timerfd_create,
+        libc::SYS_timerfd_settime,
+        // threads, `clone` and `clone3` are handled in `filter`
+        libc::SYS_exit,
+        libc::SYS_exit_group,
+        libc::SYS_futex,
+        libc::SYS_set_robust_list,
+        libc::SYS_get_robust_list,
+        libc::SYS_set_tid_address,
+        libc::SYS_rseq,
+        libc::SYS_sched_yield,
+        libc::SYS_sched_getaffinity,
+        libc::SYS_prctl,
+        libc::SYS_prlimit64,
+        libc::SYS_getrlimit,
+        libc::SYS_tgkill,
+        libc::SYS_getpid,
+        libc::SYS_getppid,
+        libc::SYS_gettid,
+        // signals
+        libc::SYS_rt_sigaction,
+        libc::SYS_rt_sigprocmask,
+        libc::SYS_rt_sigreturn,
+        libc::SYS_sigaltstack,
+        libc::SYS_restart_syscall,
+        // users, root is already dropped
+        libc::SYS_getuid,
+        libc::SYS_geteuid,
+        libc::SYS_getgid,
+        libc::SYS_getegid,
+        libc::SYS_getgroups,
+        // time and the rest
+        libc::SYS_clock_gettime,
+        libc::SYS_clock_getres,
+        libc::SYS_clock_nanosleep,
+        libc::SYS_nanosleep,
+        libc::SYS_gettimeofday,
+        libc::SYS_getrandom,
+        libc::SYS_uname,
+        libc::SYS_sysinfo,
+        // the older ones glibc still uses on x86_64
+        #[cfg(target_arch = "x86_64")]
+        libc::SYS_open,
+        #[cfg(target_arch = "x86_64")]
+        libc::SYS_stat,
+        #[cfg(target_arch = "x86_64")]
+        libc::SYS_lstat,
+        #[cfg(target_arch = "x86_64")]
+        libc::SYS_access,
+        #[cfg(target_arch = "x86_64")]
+        libc::SYS_readlink,
+        #[cfg(target_arch = "x86_64")]
+        libc::SYS_unlink,
+        #[cfg(target_arch = "x86_64")]
+        libc::SYS_rename,
+        #[cfg(target_arch = "x86_64")]
+        libc::SYS_mkdir,
+        #[cfg(target_arch = "x86_64")]
+        libc::SYS_rmdir,
+        #[cfg(target_arch = "x86_64")]
+        libc::SYS_chmod,
+        #[cfg(target_arch = "x86_64")]
+        libc::SYS_chown,
+        #[cfg(target_arch = "x86_64")]
+        libc::SYS_lchown,
+        #[cfg(target_arch = "x86_64")]
+        libc::SYS_poll,
+        #[cfg(target_arch = "x86_64")]
+        libc::SYS_epoll_wait,
+        #[cfg(target_arch = "x86_64")]
+        libc::SYS_pipe,
+        #[cfg(target_arch = "x86_64")]
+        libc::SYS_dup2,
+        #[cfg(target_arch = "x86_64")]
+        libc::SYS_getdents,
+        #[cfg(target_arch = "x86_64")]
+        libc::SYS_arch_prctl,
+        #[cfg(target_arch = "x86_64")]
+        libc::SYS_time,
+    ];
+
+    /// the fd and terminal ones std, tokio and tracing use, the usbfs ones are allowed by their type
+    const ALLOWED_IOCTLS: &[u32] = &[
+        libc::FIONBIO as u32,
+        libc::FIONREAD as u32,
+        libc::FIOCLEX as u32,
+        libc::FIONCLEX as u32,
+        libc::TCGETS as u32,
+        libc::TIOCGWINSZ as u32,
+    ];
+
+    /// the type byte of the usbfs ioctls (`USBDEVFS_*`)
+    const USBDEVFS_IOCTL_TYPE: u32 = (b'U' as u32) << 8;
+
+    fn stmt(code: u32, k: u32) -> libc::sock_filter {
+        libc::sock_filter {
+            code: code as u16,
+            jt: 0,
+            jf: 0,
+            k,
+        }
+    }
+
+    fn jump(code: u32, k: u32, jt: u8, jf: u8) -> libc::sock_filter {
+        libc::sock_filter {
+            code: code as u16,
+            jt,
+            jf,
+            k,
+        }
+    }
+
+    fn filter() -> Vec<libc::sock_filter> {
+        use libc::{BPF_ABS, BPF_JEQ, BPF_JMP, BPF_K, BPF_LD, BPF_RET, BPF_W};
+
+        let nr_offset = mem::offset_of!(libc::seccomp_data, nr) as u32;
+        let arch_offset = mem::offset_of!(libc::seccomp_data, arch) as u32;
+
+        let mut filter = vec![
+            stmt(BPF_LD | BPF_W | BPF_ABS, arch_offset),
+            jump(BPF_JMP | BPF_JEQ | BPF_K, AUDIT_ARCH, 1, 0),
+            stmt(BPF_RET | BPF_K, libc::SECCOMP_RET_KILL_PROCESS),
+            stmt(BPF_LD | BPF_W | BPF_ABS, nr_offset),
+        ];
+
+        #[cfg(target_arch = "x86_64")]
+        filter.extend([
+            jump(BPF_JMP | libc::BPF_JSET | BPF_K, X32_SYSCALL_BIT, 0, 1),
+            stmt(BPF_RET | BPF_K, libc::SECCOMP_RET_KILL_PROCESS),
+        ]);
+
+        let arg_offset = |i: u32| {
+            // the low half, both architectures are little endian
+            mem::offset_of!(libc::seccomp_data, args) as u32 + i * 8
+        };
+
+        // `clone3` hides it's flags behind a pointer, so it's refused quietly, glibc falls back to
+        // `clone` then
+        filter.extend([
+            jump(BPF_JMP | BPF_JEQ | BPF_K, libc::SYS_clone3 as u32, 0, 1),
+            stmt(
+                BPF_RET | BPF_K,
+                libc::SECCOMP_RET_ERRNO | libc::ENOSYS as u32,
+            ),
+        ]);
+
+        // only threads, no new processes
+        filter.extend([
+            jump(BPF_JMP | BPF_JEQ | BPF_K, libc::SYS_clone as u32, 0, 4),
+            stmt(BPF_LD | BPF_W | BPF_ABS, arg_offset(0)),
+            jump(
+                BPF_JMP | libc::BPF_JSET | BPF_K,
+                libc::CLONE_THREAD as u32,
+                0,
+                1,
+            ),
+            stmt(BPF_RET | BPF_K, libc::SECCOMP_RET_ALLOW),
+            stmt(BPF_RET | BPF_K, libc::SECCOMP_RET_TRAP),
+        ]);
+
+        // the ioctl's request is checked, the ones that aren't allowed are trapped
+        let count = ALLOWED_IOCTLS.len() as u8;
+        filter.extend([
+            jump(
+                BPF_JMP | BPF_JEQ | BPF_K,
+                libc::SYS_ioctl as u32,
+                0,
+                count + 5,
+            ),
+            stmt(BPF_LD | BPF_W | BPF_ABS, arg_offset(1)),
+        ]);
+        for (i, &request) in ALLOWED_IOCTLS.iter().enumerate() {
+            filter.push(jump(
+                BPF_JMP | BPF_JEQ | BPF_K,
+                request,
+                count - i as u8 + 2,
+                0,
+            ));
+        }
+        filter.extend([
+            stmt(libc::BPF_ALU | libc::BPF_AND | BPF_K, 0xff00),
+            jump(BPF_JMP | BPF_JEQ | BPF_K, USBDEVFS_IOCTL_TYPE, 1, 0),
+            stmt(BPF_RET | BPF_K, libc::SECCOMP_RET_TRAP),
+            stmt(BPF_RET | BPF_K, libc::SECCOMP_RET_ALLOW),
+            // the syscall number again, for the rest
+            stmt(BPF_LD | BPF_W | BPF_ABS, nr_offset),
+        ]);
+
+        for &nr in ALLOWED {
+            filter.extend([
+                jump(BPF_JMP | BPF_JEQ | BPF_K, nr as u32, 0, 1),
+                stmt(BPF_RET | BPF_K, libc::SECCOMP_RET_ALLOW),
+            ]);
+        }
+
+        // the rest go to `on_blocked`
+        filter.push(stmt(BPF_RET | BPF_K, libc::SECCOMP_RET_TRAP));
+
+        filter
+    }
+
+    pub(super) fn install() -> Result<(), RusbmuxError> {
+        install_sigsys_handler()?;
+
+        let filter = filter();
+        let prog = libc::sock_fprog {
+            len: filter.len() as u16,
+            filter: filter.as_ptr().cast_mut(),
+        };
+
+        // SAFETY: the program points to the filter, which outlives the call
+        let ret = unsafe {
+            libc::syscall(
+                libc::SYS_seccomp,
+                libc::SECCOMP_SET_MODE_FILTER,
+                libc::SECCOMP_FILTER_FLAG_TSYNC,
+                &raw const prog,
+            )
+        };
+        if ret != 0 {
+            return Err(sandbox_error("install the seccomp filter"));
+        }
+
+        Ok(())
+    }
+
+    fn install_sigsys_handler() -> Result<(), RusbmuxError> {
+        // SAFETY: all zeros is a valid `sigaction`
+        let mut action: libc::sigaction = unsafe { mem::zeroed() };
+        action.sa_sigaction = on_blocked as *const () as libc::sighandler_t;
+        action.sa_flags = libc::SA_SIGINFO;
+
+        // SAFETY: the action is valid, and the handler only does async signal safe things
+        if unsafe { libc::sigaction(libc::SIGSYS, &action, std::ptr::null_mut()) } < 0 {
+            return Err(sandbox_error("set the SIGSYS handler"));
+        }
+
+        Ok(())
+    }
+
+    /// the `SIGSYS` part of `siginfo_t`
+    #[repr(C)]
+    struct SigsysInfo {
+        signo: libc::c_int,
+        errno: libc::c_int,
+        code: libc::c_int,
+        call_addr: *mut libc::c_void,
+        syscall: libc::c_int,
+        arch: libc::c_uint,
+    }
+
+    /// logs the blocked syscall and makes it fail with `ENOSYS`, so the caller gets an error
+    /// instead of the whole daemon going down
+    ///
+    /// it runs in a signal handler, so it can't use tracing, only `write`
+    extern "C" fn on_blocked(
+        _: libc::c_int,
+        info: *mut libc::siginfo_t,
+        context: *mut libc::c_void,
+    ) {
+        // SAFETY: the kernel gives a `SIGSYS` siginfo, it has the syscall in it
+        let nr = unsafe { (*info.cast::<SigsysInfo>()).syscall };
+
+        let mut msg = [0u8; 96];
+        let mut len = 0;
+        let mut push = |bytes: &[u8]| {
+            msg[len..len + bytes.len()].copy_from_slice(bytes);
+            len += bytes.len();
+        };
+
+        let mut digits = [0u8; 10];
+        let mut start = digits.len();
+        let mut n = nr.unsigned_abs();
+
+        loop {
+            start -= 1;
+            digits[start] = b'0' + (n % 10) as u8;
+            n /= 10;
+
+            if n == 0 {
+                break;
+            }
+        }
+
+        push(b"rusbmux: the sandbox blocked syscall ");
+        push(&digits[start..]);
+        push(b", it needs a rule\n");
+
+        // SAFETY: the buffer is valid for `len` bytes
+        unsafe { libc::write(libc::STDERR_FILENO, msg.as_ptr().cast(), len) };
+
+        let context = context.cast::<libc::ucontext_t>();
+
+        // SAFETY: the kernel gives a valid context, the return value of the syscall goes in it
+        unsafe {
+            #[cfg(target_arch = "x86_64")]
+            {
+                (*context).uc_mcontext.gregs[libc::REG_RAX as usize] = -i64::from(libc::ENOSYS);
+            }
+
+            #[cfg(target_arch = "aarch64")]
+            {
+                (*context).uc_mcontext.regs[0] = (-i64::from(libc::ENOSYS)) as u64;
+            }
+        }
+    }
+}
+
+#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+mod seccomp {
+    use super::*;
+
+    pub(super) fn install() -> Result<(), RusbmuxError> {
+        warn!("There's no seccomp filter for this architecture, the syscalls are not restricted");
+        Ok(())
+    }
+}
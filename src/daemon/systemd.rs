@@ -0,0 +1,166 @@
+use std::{
+    io, mem,
+    os::{
+        fd::{AsRawFd, FromRawFd, OwnedFd, RawFd},
+        linux::net::SocketAddrExt,
+        unix::net::{SocketAddr as UnixSocketAddr, UnixDatagram},
+    },
+    path::PathBuf,
+    time::Duration,
+};
+
+use tracing::{debug, warn};
+
+use crate::daemon::{ListenAddr, Listener, VsockListener};
+
+/// the first fd systemd passes
+const LISTEN_FDS_START: RawFd = 3;
+
+/// takes the sockets systemd passed us (socket activation), if any
+///
+/// they're already bound, so their paths and permissions are left alone
+pub fn listeners() -> io::Result<Vec<(ListenAddr, Listener)>> {
+    let Some(count) = listen_fds_count() else {
+        return Ok(Vec::new());
+    };
+
+    let mut listeners = Vec::with_capacity(count);
+
+    for fd in LISTEN_FDS_START..LISTEN_FDS_START + count as RawFd {
+        // SAFETY: systemd gave us the fd, and nothing else took ownership of it
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+
+        set_cloexec(&fd)?;
+
+        listeners.push(listener_from_fd(fd)?);
+    }
+
+    Ok(listeners)
+}
+
+/// whether systemd passed us the sockets to listen on
+pub fn is_socket_activated() -> bool {
+    listen_fds_count().is_some()
+}
+
+/// how many fds were passed, only if they were meant for this process
+fn listen_fds_count() -> Option<usize> {
+    let pid = std::env::var("LISTEN_PID").ok()?.parse::<u32>().ok()?;
+
+    if pid != std::process::id() {
+        debug!(
+            pid,
+            "The passed sockets are for another process, ignoring them"
+        );
+        return None;
+    }
+
+    std::env::var("LISTEN_FDS")
+        .ok()?
+        .parse::<usize>()
+        .ok()
+        .filter(|&count| count > 0)
+}
+
+fn set_cloexec(fd: &OwnedFd) -> io::Result<()> {
+    // SAFETY: the fd is valid
+    let flags = unsafe { libc::fcntl(fd.as_raw_fd(), libc::F_GETFD) };
+    if flags < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    // SAFETY: the fd is valid
+    if unsafe { libc::fcntl(fd.as_raw_fd(), libc::F_SETFD, flags | libc::FD_CLOEXEC) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+/// figures out what kind of socket it is from it's address
+fn listener_from_fd(fd: OwnedFd) -> io::Result<(ListenAddr, Listener)> {
+    // SAFETY: all zeros is a valid `sockaddr_storage`
+    let mut storage: libc::sockaddr_storage = unsafe { mem::zeroed() };
+    let mut len = mem::size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+
+    // SAFETY: the storage is valid for `len` bytes
+    if unsafe { libc::getsockname(fd.as_raw_fd(), (&raw mut storage).cast(), &mut len) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    match i32::from(storage.ss_family) {
+        libc::AF_UNIX => {
+            let listener = std::os::unix::net::UnixListener::from(fd);
+            listener.set_nonblocking(true)?;
+
+            let path = listener
+                .local_addr()?
+                .as_pathname()
+                .map_or_else(|| PathBuf::from("<unnamed>"), PathBuf::from);
+
+            Ok((
+                ListenAddr::Unix(path),
+                Listener::Unix(tokio::net::UnixListener::from_std(listener)?),
+            ))
+        }
+        libc::AF_INET | libc::AF_INET6 => {
+            let listener = std::net::TcpListener::from(fd);
+            listener.set_nonblocking(true)?;
+
+            Ok((
+                ListenAddr::Tcp(listener.local_addr()?),
+                Listener::Tcp(tokio::net::TcpListener::from_std(listener)?),
+            ))
+        }
+        libc::AF_VSOCK => {
+            // SAFETY: the family says it's a `sockaddr_vm`, and it fits in the storage
+            let port = unsafe { (*(&raw const storage).cast::<libc::sockaddr_vm>()).svm_port };
+
+            Ok((
+                ListenAddr::Vsock(port),
+                Listener::Vsock(VsockListener::from_fd(fd)?),
+            ))
+        }
+        family => Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!("a passed socket has an unsupported family ({family})"),
+        )),
+    }
+}
+
+/// sends a state (e.g. `READY=1`) to systemd, it does nothing if it didn't start us
+pub fn notify(state: &str) {
+    let Some(path) = std::env::var_os("NOTIFY_SOCKET") else {
+        return;
+    };
+
+    let res = (|| {
+        let bytes = path.as_encoded_bytes();
+
+        let addr = match bytes.strip_prefix(b"@") {
+            Some(name) => UnixSocketAddr::from_abstract_name(name)?,
+            None => UnixSocketAddr::from_pathname(&path)?,
+        };
+
+        UnixDatagram::unbound()?.send_to_addr(state.as_bytes(), &addr)
+    })();
+
+    if let Err(e) = res {
+        warn!(state, err = ?e, "Failed to notify systemd");
+    }
+}
+
+/// how often to ping the watchdog, half of what systemd expects, so a late ping is not fatal
+pub fn watchdog_interval() -> Option<Duration> {
+    if let Some(pid) = std::env::var("WATCHDOG_PID")
+        .ok()
+        .and_then(|pid| pid.parse::<u32>().ok())
+        && pid != std::process::id()
+    {
+        return None;
+    }
+
+    let usec = std::env::var("WATCHDOG_USEC").ok()?.parse::<u64>().ok()?;
+
+    (usec > 0).then(|| Duration::from_micros(usec / 2))
+}
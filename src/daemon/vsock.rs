@@ -0,0 +1,192 @@
+use std::{
+    io, mem,
+    os::fd::{AsRawFd, FromRawFd, OwnedFd},
+    pin::Pin,
+    task::{Context, Poll, ready},
+};
+
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf, unix::AsyncFd};
+
+/// an `AF_VSOCK` listener, so the VMs on this host can reach the daemon
+///
+/// tokio has no vsock support, so it's done by hand on top of `AsyncFd`
+#[derive(Debug)]
+pub struct VsockListener(AsyncFd<OwnedFd>);
+
+impl VsockListener {
+    /// listens on the given port for any cid
+    pub fn bind(port: u32) -> io::Result<Self> {
+        // SAFETY: no pointers are involved
+        let fd = unsafe {
+            libc::socket(
+                libc::AF_VSOCK,
+                libc::SOCK_STREAM | libc::SOCK_NONBLOCK | libc::SOCK_CLOEXEC,
+                0,
+            )
+        };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        // SAFETY: the fd is valid and owned by us
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+
+        // SAFETY: all zeros is a valid `sockaddr_vm`
+        let mut addr: libc::sockaddr_vm = unsafe { mem::zeroed() };
+        addr.svm_family = libc::AF_VSOCK as libc::sa_family_t;
+        addr.svm_port = port;
+        addr.svm_cid = libc::VMADDR_CID_ANY;
+
+        // SAFETY: the address is valid for it's whole length
+        let ret = unsafe {
+            libc::bind(
+                fd.as_raw_fd(),
+                (&raw const addr).cast(),
+                mem::size_of::<libc::sockaddr_vm>() as libc::socklen_t,
+            )
+        };
+        if ret < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        // SAFETY: the fd is valid
+        if unsafe { libc::listen(fd.as_raw_fd(), libc::SOMAXCONN) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(Self(AsyncFd::new(fd)?))
+    }
+
+    /// takes an already listening socket, e.g. one passed by systemd
+    pub fn from_fd(fd: OwnedFd) -> io::Result<Self> {
+        // SAFETY: the fd is valid
+        let flags = unsafe { libc::fcntl(fd.as_raw_fd(), libc::F_GETFL) };
+        if flags < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        // SAFETY: the fd is valid
+        if unsafe { libc::fcntl(fd.as_raw_fd(), libc::F_SETFL, flags | libc::O_NONBLOCK) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(Self(AsyncFd::new(fd)?))
+    }
+
+    /// gives the socket back, e.g. to take it to another runtime
+    #[must_use]
+    pub fn into_fd(self) -> OwnedFd {
+        self.0.into_inner()
+    }
+
+    pub async fn accept(&self) -> io::Result<VsockStream> {
+        let fd = self
+            .0
+            .async_io(tokio::io::Interest::READABLE, |fd| {
+                // SAFETY: null address pointers are allowed, the peer address isn't needed
+                let fd = unsafe {
+                    libc::accept4(
+                        fd.as_raw_fd(),
+                        std::ptr::null_mut(),
+                        std::ptr::null_mut(),
+                        libc::SOCK_NONBLOCK | libc::SOCK_CLOEXEC,
+                    )
+                };
+
+                if fd < 0 {
+                    Err(io::Error::last_os_error())
+                } else {
+                    // SAFETY: accept4 gave us a new fd
+                    Ok(unsafe { OwnedFd::from_raw_fd(fd) })
+                }
+            })
+            .await?;
+
+        Ok(VsockStream(AsyncFd::new(fd)?))
+    }
+}
+
+#[derive(Debug)]
+pub struct VsockStream(AsyncFd<OwnedFd>);
+
+impl AsyncRead for VsockStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        loop {
+            let mut guard = ready!(self.0.poll_read_ready(cx))?;
+
+            let unfilled = buf.initialize_unfilled();
+
+            match guard.try_io(|fd| {
+                // SAFETY: the buffer is valid for it's whole length
+                let n = unsafe {
+                    libc::read(fd.as_raw_fd(), unfilled.as_mut_ptr().cast(), unfilled.len())
+                };
+
+                if n < 0 {
+                    Err(io::Error::last_os_error())
+                } else {
+                    Ok(n as usize)
+                }
+            }) {
+                Ok(Ok(n)) => {
+                    buf.advance(n);
+                    return Poll::Ready(Ok(()));
+                }
+                Ok(Err(e)) => return Poll::Ready(Err(e)),
+                Err(_would_block) => {}
+            }
+        }
+    }
+}
+
+impl AsyncWrite for VsockStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        loop {
+            let mut guard = ready!(self.0.poll_write_ready(cx))?;
+
+            match guard.try_io(|fd| {
+                // MSG_NOSIGNAL, so a closed peer is an error and not a SIGPIPE
+                //
+                // SAFETY: the buffer is valid for it's whole length
+                let n = unsafe {
+                    libc::send(
+                        fd.as_raw_fd(),
+                        buf.as_ptr().cast(),
+                        buf.len(),
+                        libc::MSG_NOSIGNAL,
+                    )
+                };
+
+                if n < 0 {
+                    Err(io::Error::last_os_error())
+                } else {
+                    Ok(n as usize)
+                }
+            }) {
+                Ok(res) => return Poll::Ready(res),
+                Err(_would_block) => {}
+            }
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        // SAFETY: the fd is valid
+        if unsafe { libc::shutdown(self.0.as_raw_fd(), libc::SHUT_WR) } < 0 {
+            return Poll::Ready(Err(io::Error::last_os_error()));
+        }
+
+        Poll::Ready(Ok(()))
+    }
+}
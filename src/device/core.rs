@@ -1,16 +1,44 @@
+use std::sync::{Arc, PoisonError, RwLock};
+
 use tokio_util::sync::CancellationToken;
 
+use crate::device::status::DeviceStatus;
+
 #[derive(Debug, Clone)]
 pub struct DeviceCore {
     pub id: u64,
 
     pub canceler: CancellationToken,
+
+    /// cancelled once the daemon starts shutting down, the connections finish what they have and
+    /// close
+    pub drainer: CancellationToken,
+
+    pub status: Arc<RwLock<DeviceStatus>>,
 }
 
 impl DeviceCore {
     #[must_use]
     pub fn new(id: u64) -> Self {
         let canceler = CancellationToken::new();
-        Self { id, canceler }
+        Self {
+            id,
+            canceler,
+            drainer: CancellationToken::new(),
+            status: Arc::default(),
+        }
+    }
+
+    /// a snapshot of the device status
+    #[must_use]
+    pub fn status(&self) -> DeviceStatus {
+        self.status
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    pub fn update_status(&self, f: impl FnOnce(&mut DeviceStatus)) {
+        f(&mut self.status.write().unwrap_or_else(PoisonError::into_inner));
     }
 }
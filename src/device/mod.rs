@@ -1,11 +1,18 @@
 pub mod core;
 pub mod network;
 pub mod packet_router;
+pub mod pairing;
 pub mod power_assertion;
+pub mod preflight;
+pub mod properties;
+pub mod status;
 pub mod usb;
+pub mod wifi_sync;
 use std::{borrow::Cow, net::IpAddr, sync::Arc};
 
+use core::DeviceCore;
 use network::NetworkDevice;
+use status::{DeviceProperties, DeviceStatus};
 use usb::UsbDevice;
 
 use crate::{conn::DeviceConn, error::RusbmuxError, usb_backend::AnyDeviceInfo};
@@ -16,7 +23,8 @@ pub enum Device {
     Usb(Arc<UsbDevice>),
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum ConnectionType {
     Usb,
     Network,
@@ -39,6 +47,27 @@ impl Device {
         }
     }
 
+    #[must_use]
+    pub fn core(&self) -> &DeviceCore {
+        match self {
+            Self::Network(dev) => &dev.core,
+            Self::Usb(dev) => &dev.core,
+        }
+    }
+
+    /// a snapshot of what the daemon knows about the device, apart from what is needed to talk to
+    /// it
+    #[must_use]
+    pub fn status(&self) -> DeviceStatus {
+        self.core().status()
+    }
+
+    /// the cached lockdown properties, if they're enabled and already read
+    #[must_use]
+    pub fn properties(&self) -> Option<DeviceProperties> {
+        self.status().properties
+    }
+
     pub fn serial_number(&self) -> Cow<'_, str> {
         match self {
             Self::Network(dev) => Cow::Borrowed(&dev.serial_number),
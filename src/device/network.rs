@@ -1,8 +1,6 @@
 use std::net::{IpAddr, SocketAddr, SocketAddrV6};
 
-use idevice::{
-    IdeviceService, heartbeat::HeartbeatClient, pairing_file::PairingFile, provider::TcpProvider,
-};
+use idevice::{IdeviceService, heartbeat::HeartbeatClient, provider::TcpProvider};
 use tokio::{sync::watch, task::JoinHandle};
 use tracing::{debug, warn};
 
@@ -10,7 +8,7 @@ use crate::{
     conn::NetworkDeviceConn,
     device::{core::DeviceCore, power_assertion::PowerAssertion},
     error::RusbmuxError,
-    handler::LOCKDOWN_PATH,
+    pair_record::{is_stale_error, mark_stale, notify_stale, read_pairing_file},
     watcher::remove_device,
 };
 
@@ -30,7 +28,7 @@ pub struct NetworkDevice {
 
     pub hb_failed: watch::Receiver<()>,
     pub hb_handler: JoinHandle<()>,
-    _power_assertion: PowerAssertion,
+    _power_assertion: Option<PowerAssertion>,
 }
 
 impl Drop for NetworkDevice {
@@ -52,13 +50,15 @@ impl NetworkDevice {
     ) -> Result<Self, RusbmuxError> {
         let (mut heartbeat_client, addr) =
             Self::connect_heartbeat_client(addr, scope_id, serial_number.clone()).await?;
-        let power_assertion = PowerAssertion::new(addr, scope_id, &serial_number).await?;
+        let power_assertion = PowerAssertion::with_policy(addr, scope_id, &serial_number).await?;
 
         let (tx, rx) = watch::channel(());
 
         let core = DeviceCore::new(id);
 
         let device_shutdown = core.canceler.clone();
+        let hb_core = core.clone();
+        let hb_serial_number = serial_number.clone();
         let hb_handler = tokio::spawn(async move {
             let mut interval = 15;
 
@@ -72,6 +72,12 @@ impl NetworkDevice {
                         i + 5
                     }
                     Err(e) => {
+                        if is_stale_error(&e) {
+                            hb_core.update_status(|s| s.pair_record_stale = true);
+                            mark_stale(&hb_serial_number, &e).await;
+                            notify_stale(id);
+                        }
+
                         if failed {
                             warn!(id, "Heartbeat failed, error: {e}, closing device");
                             let _ = tx.send(());
@@ -118,8 +124,7 @@ impl NetworkDevice {
         scope_id: Option<u32>,
         serial_number: String,
     ) -> Result<(HeartbeatClient, IpAddr), RusbmuxError> {
-        let pairing_file =
-            PairingFile::read_from_file(format!("{LOCKDOWN_PATH}/{serial_number}.plist"))?;
+        let pairing_file = read_pairing_file(&serial_number).await?;
 
         let label = format!("rusbmux_{serial_number}_heartbeat_client");
 
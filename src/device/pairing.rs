@@ -0,0 +1,130 @@
+use std::{sync::Arc, time::Duration};
+
+use idevice::{IdeviceError, lockdown::LockdownClient};
+use tokio::time::Instant;
+use tracing::{debug, info, warn};
+
+use crate::{
+    config::config,
+    device::{
+        status::{DeviceStatus, TrustState},
+        usb::UsbDevice,
+        wifi_sync::spawn_enable_wifi_connections,
+    },
+    error::RusbmuxError,
+    handler::read_buid::read_system_buid,
+    pair_record::save_pair_record,
+    watcher::{DeviceEvent, get_hotplug_event_tx},
+};
+
+/// how long to wait before asking again if the device is locked
+const LOCKED_RETRY_INTERVAL: Duration = Duration::from_secs(2);
+
+/// pairs with the device the same way usbmuxd does on macOS
+///
+/// it waits for the user to accept the trust dialog (or unlock the device), saves the pair
+/// record, then notifies the listeners with a `Paired` event
+pub async fn pair(device: &Arc<UsbDevice>) -> Result<(), RusbmuxError> {
+    let device_id = device.core.id;
+    let serial_number = device.info.serial_number().unwrap_or_default().to_string();
+
+    let host_id = uuid::Uuid::new_v4().to_string().to_uppercase();
+    let system_buid = read_system_buid().await?;
+
+    let deadline = Instant::now() + config().pairing_timeout;
+
+    info!(device_id, serial_number, "Pairing with the device");
+
+    let mut lockdown = LockdownClient::new(
+        device
+            .connect_idevice(
+                LockdownClient::LOCKDOWND_PORT,
+                format!("rusbmux_{serial_number}_pairing"),
+            )
+            .await?,
+    );
+
+    let pairing_file = loop {
+        // the trust dialog is shown as soon as the request is sent
+        device
+            .core
+            .update_status(|s| set_trust(s, TrustState::PendingTrust));
+
+        let remaining = deadline.saturating_duration_since(Instant::now());
+
+        // `pair` keeps asking while the dialog is pending
+        match tokio::time::timeout(remaining, lockdown.pair(&host_id, &system_buid, None)).await {
+            Ok(Ok(pairing_file)) => break pairing_file,
+            Ok(Err(IdeviceError::PasswordProtected)) => {
+                debug!(
+                    device_id,
+                    "The device is locked, waiting for it to be unlocked"
+                );
+                device
+                    .core
+                    .update_status(|s| set_trust(s, TrustState::Locked));
+
+                if Instant::now() + LOCKED_RETRY_INTERVAL >= deadline {
+                    return Err(RusbmuxError::Idevice(IdeviceError::PasswordProtected));
+                }
+
+                tokio::time::sleep(LOCKED_RETRY_INTERVAL).await;
+            }
+            Ok(Err(e)) => {
+                device
+                    .core
+                    .update_status(|s| set_trust(s, TrustState::Untrusted));
+                return Err(e.into());
+            }
+            Err(_) => {
+                device
+                    .core
+                    .update_status(|s| set_trust(s, TrustState::Untrusted));
+                return Err(RusbmuxError::Timeout("waiting for the trust dialog"));
+            }
+        }
+    };
+
+    save_pair_record(&serial_number, pairing_file.serialize()?).await?;
+
+    device
+        .core
+        .update_status(|s| set_trust(s, TrustState::Trusted));
+
+    info!(device_id, serial_number, "Paired with the device");
+
+    let _ = get_hotplug_event_tx()
+        .await
+        .send(DeviceEvent::Paired { id: device_id });
+
+    if config().enable_wifi_connections {
+        spawn_enable_wifi_connections(Arc::clone(device));
+    }
+
+    Ok(())
+}
+
+/// only touches the trust state if there is a preflight to attach it to
+fn set_trust(status: &mut DeviceStatus, trust: TrustState) {
+    if let Some(preflight) = status.preflight.as_mut() {
+        preflight.trust = trust;
+    }
+}
+
+/// spawns the pairing flow in the background, so the watcher doesn't wait on the user
+pub fn spawn_pairing(device: Arc<UsbDevice>) {
+    tokio::spawn(async move {
+        let device_id = device.core.id;
+
+        tokio::select! {
+            res = pair(&device) => {
+                if let Err(e) = res {
+                    warn!(device_id, err = ?e, "Failed to pair with the device");
+                }
+            }
+            _ = device.core.canceler.cancelled() => {
+                debug!(device_id, "Device went away while pairing");
+            }
+        }
+    });
+}
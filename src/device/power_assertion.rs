@@ -1,15 +1,14 @@
-use std::{net::IpAddr, time::Duration};
+use std::net::IpAddr;
 
-use idevice::{
-    Idevice, IdeviceError, IdeviceService, pairing_file::PairingFile, provider::TcpProvider,
-};
+use idevice::{Idevice, IdeviceError, IdeviceService, provider::TcpProvider};
 use tokio::task::JoinHandle;
 use tracing::{debug, info, warn};
 
-use crate::{error::RusbmuxError, handler::LOCKDOWN_PATH};
-
-const ASSERTION_TIMEOUT: Duration = Duration::from_secs(20 * 60);
-const RENEWAL_INTERVAL: Duration = Duration::from_secs(10 * 60);
+use crate::{
+    config::{PowerAssertionPolicy, config},
+    error::RusbmuxError,
+    pair_record::read_pairing_file,
+};
 
 #[derive(Debug)]
 pub struct PowerAssertion {
@@ -23,6 +22,31 @@ impl Drop for PowerAssertion {
 }
 
 impl PowerAssertion {
+    /// takes a power assertion if the config asks for one
+    ///
+    /// a failure is only an error if it's required
+    pub async fn with_policy(
+        addr: IpAddr,
+        scope_id: Option<u32>,
+        serial_number: &str,
+    ) -> Result<Option<Self>, RusbmuxError> {
+        match config().power_assertion {
+            PowerAssertionPolicy::Required => {
+                Self::new(addr, scope_id, serial_number).await.map(Some)
+            }
+            PowerAssertionPolicy::BestEffort => {
+                match Self::new(addr, scope_id, serial_number).await {
+                    Ok(assertion) => Ok(Some(assertion)),
+                    Err(e) => {
+                        warn!(serial_number, err = %e, "Failed to take a power assertion, the device may fall asleep");
+                        Ok(None)
+                    }
+                }
+            }
+            PowerAssertionPolicy::Off => Ok(None),
+        }
+    }
+
     pub async fn new(
         addr: IpAddr,
         scope_id: Option<u32>,
@@ -36,7 +60,7 @@ impl PowerAssertion {
         let renewal_handler = tokio::spawn(async move {
             let mut assertion = assertion;
             loop {
-                tokio::time::sleep(RENEWAL_INTERVAL).await;
+                tokio::time::sleep(config().power_assertion_renewal_interval).await;
 
                 let new_assertion = match Assertion::new(addr, scope_id, &serial_number).await {
                     Ok(assertion) => assertion,
@@ -68,9 +92,7 @@ impl Assertion {
         let provider = TcpProvider {
             addr,
             scope_id,
-            pairing_file: PairingFile::read_from_file(format!(
-                "{LOCKDOWN_PATH}/{serial_number}.plist"
-            ))?,
+            pairing_file: read_pairing_file(serial_number).await?,
             label: format!("rusbmux_{serial_number}_power_assertion"),
         };
         let mut assertion = Self::connect(&provider).await?;
@@ -80,7 +102,7 @@ impl Assertion {
                 "CommandKey": "CommandCreateAssertion",
                 "AssertionTypeKey": "AMDPowerAssertionTypeWirelessSync",
                 "AssertionNameKey": "rusbmux",
-                "AssertionTimeoutKey": ASSERTION_TIMEOUT.as_secs_f64(),
+                "AssertionTimeoutKey": config().power_assertion_timeout.as_secs_f64(),
                 "AssertionDetailKey": "rusbmux",
             }))
             .await?;
@@ -0,0 +1,87 @@
+use std::sync::Arc;
+
+use idevice::{IdeviceError, lockdown::LockdownClient};
+use tracing::{debug, info, warn};
+
+use crate::{
+    device::{
+        properties::get_string,
+        status::{Preflight, TrustState},
+        usb::UsbDevice,
+    },
+    error::RusbmuxError,
+    pair_record::{is_stale_error, mark_stale, read_pairing_file},
+};
+
+/// talks to lockdownd the same way usbmuxd does before announcing a device
+///
+/// it reads the device name and version, then checks if the saved pair record (if any) is still
+/// accepted by the device
+pub async fn preflight(device: &Arc<UsbDevice>) -> Result<Preflight, RusbmuxError> {
+    let device_id = device.core.id;
+    let serial_number = device.info.serial_number().unwrap_or_default().to_string();
+
+    debug!(device_id, serial_number, "Starting preflight");
+
+    let mut lockdown = LockdownClient::new(
+        device
+            .connect_idevice(
+                LockdownClient::LOCKDOWND_PORT,
+                format!("rusbmux_{serial_number}_preflight"),
+            )
+            .await?,
+    );
+
+    let device_name = get_string(&mut lockdown, "DeviceName").await;
+    let product_type = get_string(&mut lockdown, "ProductType").await;
+    let product_version = get_string(&mut lockdown, "ProductVersion").await;
+
+    let trust = match read_pairing_file(&serial_number).await {
+        Ok(pairing_file) => match lockdown.start_session(&pairing_file).await {
+            Ok(_) => TrustState::Trusted,
+            Err(e) if is_stale_error(&e) => {
+                device.core.update_status(|s| s.pair_record_stale = true);
+                mark_stale(&serial_number, &e).await;
+
+                trust_from_error(&e).unwrap_or(TrustState::Untrusted)
+            }
+            Err(e) => trust_from_error(&e).unwrap_or_else(|| {
+                warn!(device_id, err = ?e, "Failed to validate the pair record");
+                TrustState::Untrusted
+            }),
+        },
+        Err(e) => {
+            debug!(device_id, err = ?e, "No usable pair record");
+            TrustState::Untrusted
+        }
+    };
+
+    info!(
+        device_id,
+        serial_number,
+        %trust,
+        device_name,
+        product_type,
+        product_version,
+        "Preflight finished"
+    );
+
+    Ok(Preflight {
+        trust,
+        device_name,
+        product_type,
+        product_version,
+    })
+}
+
+/// maps the lockdown errors that describe the trust relationship
+pub fn trust_from_error(error: &IdeviceError) -> Option<TrustState> {
+    match error {
+        IdeviceError::InvalidHostID | IdeviceError::UserDeniedPairing => {
+            Some(TrustState::Untrusted)
+        }
+        IdeviceError::PasswordProtected | IdeviceError::DeviceLocked => Some(TrustState::Locked),
+        IdeviceError::PairingDialogResponsePending => Some(TrustState::PendingTrust),
+        _ => None,
+    }
+}
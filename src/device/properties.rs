@@ -0,0 +1,131 @@
+use std::{net::IpAddr, sync::Arc};
+
+use idevice::{IdeviceService, lockdown::LockdownClient, provider::TcpProvider};
+use tracing::{debug, warn};
+
+use crate::{
+    device::{status::DeviceProperties, usb::UsbDevice},
+    error::RusbmuxError,
+    pair_record::read_pairing_file,
+    watcher::CONNECTED_DEVICES,
+};
+
+impl DeviceProperties {
+    /// reads all the properties from an already connected lockdown client
+    ///
+    /// the ones the device refused to give are left out
+    pub async fn read(lockdown: &mut LockdownClient) -> Self {
+        Self {
+            device_name: get_string(lockdown, "DeviceName").await,
+            product_type: get_string(lockdown, "ProductType").await,
+            product_version: get_string(lockdown, "ProductVersion").await,
+            build_version: get_string(lockdown, "BuildVersion").await,
+            device_class: get_string(lockdown, "DeviceClass").await,
+            hardware_model: get_string(lockdown, "HardwareModel").await,
+        }
+    }
+}
+
+pub async fn read_usb_properties(
+    device: &Arc<UsbDevice>,
+) -> Result<DeviceProperties, RusbmuxError> {
+    let serial_number = device.info.serial_number().unwrap_or_default().to_string();
+
+    let mut lockdown = LockdownClient::new(
+        device
+            .connect_idevice(
+                LockdownClient::LOCKDOWND_PORT,
+                format!("rusbmux_{serial_number}_properties"),
+            )
+            .await?,
+    );
+
+    // some values (e.g. the device name) are only given inside a session, but the rest can still
+    // be read if the device is not paired
+    match read_pairing_file(&serial_number).await {
+        Ok(pairing_file) => {
+            if let Err(e) = lockdown.start_session(&pairing_file).await {
+                debug!(device_id = device.core.id, err = ?e, "Failed to start a lockdown session");
+            }
+        }
+        Err(e) => debug!(device_id = device.core.id, err = ?e, "No usable pair record"),
+    }
+
+    Ok(DeviceProperties::read(&mut lockdown).await)
+}
+
+pub async fn read_network_properties(
+    addr: IpAddr,
+    scope_id: Option<u32>,
+    serial_number: &str,
+) -> Result<DeviceProperties, RusbmuxError> {
+    let provider = TcpProvider {
+        addr,
+        scope_id,
+        pairing_file: read_pairing_file(serial_number).await?,
+        label: format!("rusbmux_{serial_number}_properties"),
+    };
+
+    let mut lockdown = LockdownClient::connect(&provider).await?;
+    lockdown.start_session(&provider.pairing_file).await?;
+
+    Ok(DeviceProperties::read(&mut lockdown).await)
+}
+
+/// reads the properties over whichever transport the device is connected with, then caches them in
+/// the device status
+pub fn spawn_read_properties(id: u64) {
+    // don't hold the map entry across an await, the device might get removed meanwhile
+    let Some((core, usb, network)) = CONNECTED_DEVICES.get(&id).map(|device| {
+        (
+            device.core().clone(),
+            device.as_usb().cloned(),
+            device
+                .as_network()
+                .map(|ndev| (ndev.addr, ndev.scope_id, ndev.serial_number.clone())),
+        )
+    }) else {
+        return;
+    };
+
+    if usb.is_none() && network.is_none() {
+        debug!(
+            device_id = id,
+            "The device is neither usb nor network, skipping it's properties"
+        );
+        return;
+    }
+
+    tokio::spawn(async move {
+        let properties = async {
+            match (usb, network) {
+                (Some(usb), _) => read_usb_properties(&usb).await,
+                (None, Some((addr, scope_id, serial_number))) => {
+                    read_network_properties(addr, scope_id, &serial_number).await
+                }
+                (None, None) => Err(RusbmuxError::DeviceNotFound(id)),
+            }
+        };
+
+        tokio::select! {
+            res = properties => match res {
+                Ok(properties) => {
+                    debug!(device_id = id, ?properties, "Cached the device properties");
+                    core.update_status(|s| s.properties = Some(properties));
+                }
+                Err(e) => warn!(device_id = id, err = ?e, "Failed to read the device properties"),
+            },
+            _ = core.canceler.cancelled() => {}
+        }
+    });
+}
+
+pub(crate) async fn get_string(lockdown: &mut LockdownClient, key: &str) -> Option<String> {
+    match lockdown.get_value(Some(key), None).await {
+        Ok(value) => value.into_string(),
+        Err(e) => {
+            debug!(key, err = ?e, "Failed to get lockdown value");
+            None
+        }
+    }
+}
@@ -0,0 +1,88 @@
+use std::fmt;
+
+/// what lockdownd thinks about us
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrustState {
+    /// we have a pair record, and the device accepted it
+    Trusted,
+
+    /// there is no pair record, or the device rejected the one we have
+    Untrusted,
+
+    /// the device has a passcode and it's locked, it can't pair until it's unlocked
+    Locked,
+
+    /// the "Trust This Computer?" dialog is shown on the device
+    PendingTrust,
+}
+
+impl TrustState {
+    #[must_use]
+    pub const fn as_str(&self) -> &'static str {
+        match self {
+            Self::Trusted => "Trusted",
+            Self::Untrusted => "Untrusted",
+            Self::Locked => "Locked",
+            Self::PendingTrust => "PendingTrust",
+        }
+    }
+}
+
+impl fmt::Display for TrustState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// the data collected from lockdownd before the device got announced
+#[derive(Debug, Clone)]
+pub struct Preflight {
+    pub trust: TrustState,
+
+    pub device_name: Option<String>,
+    pub product_type: Option<String>,
+    pub product_version: Option<String>,
+}
+
+/// the lockdown values that tell devices apart
+///
+/// each one is `None` if the device refused to give it
+#[derive(Debug, Clone, Default)]
+pub struct DeviceProperties {
+    pub device_name: Option<String>,
+    pub product_type: Option<String>,
+    pub product_version: Option<String>,
+    pub build_version: Option<String>,
+    pub device_class: Option<String>,
+    pub hardware_model: Option<String>,
+}
+
+/// the outcome of turning on the WiFi connections on the device
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WifiConnections {
+    /// the daemon turned it on
+    Enabled,
+
+    /// it was already on
+    AlreadyEnabled,
+
+    Failed(String),
+}
+
+/// extra information about a device that is not needed to talk to it
+///
+/// it's shared between the device and all of it's connections
+#[derive(Debug, Clone, Default)]
+pub struct DeviceStatus {
+    /// `None` if the preflight is disabled, or it failed
+    pub preflight: Option<Preflight>,
+
+    /// `None` if enabling the WiFi connections is disabled, or it's not done yet
+    pub wifi_connections: Option<WifiConnections>,
+
+    /// `None` if caching the lockdown properties is disabled, or they're not read yet
+    pub properties: Option<DeviceProperties>,
+
+    /// the device rejected our pair record
+    pub pair_record_stale: bool,
+}
@@ -1,20 +1,29 @@
-use std::sync::{
-    Arc, Weak,
-    atomic::{AtomicBool, AtomicU16},
+use std::{
+    sync::{
+        Arc, Weak,
+        atomic::{AtomicBool, AtomicU16, Ordering},
+    },
+    time::Duration,
 };
 
 use bytes::Bytes;
 use crossfire::{MAsyncRx, MAsyncTx, mpmc, mpsc};
 use dashmap::DashMap;
 use etherparse::TcpHeader;
+use idevice::Idevice;
 use pack1::U16BE;
-use tokio::{io::AsyncWriteExt, sync::OnceCell, task::JoinHandle};
+use tokio::{
+    io::AsyncWriteExt,
+    sync::{Notify, OnceCell},
+    task::JoinHandle,
+};
 use tracing::{debug, error, info, trace, warn};
 
 use crate::{
     conn::UsbDeviceConn,
     device::{core::DeviceCore, packet_router::PacketRouter},
     error::{ParseError, RusbmuxError},
+    handler::connect::handle_usb_device_connect,
     parser::device_mux::{
         UsbDevicePacket, UsbDevicePacketHeader, UsbDevicePacketHeaderV2, UsbDevicePacketPayload,
         UsbDevicePacketVersion,
@@ -24,6 +33,8 @@ use crate::{
     },
 };
 
+const IDEVICE_BUFF_SIZE: usize = 64 * 1024;
+
 #[derive(Debug)]
 pub struct UsbDevice {
     pub handler: AnyDeviceHandle,
@@ -48,8 +59,17 @@ pub struct UsbDevice {
     writer_loop_handler: OnceCell<JoinHandle<()>>,
 
     dropped: AtomicBool,
+
+    /// the writer loop is in the middle of writing a packet
+    writing: AtomicBool,
+
+    /// notified every time the writer loop empties it's queue
+    flushed: Notify,
 }
 
+/// how often to check if the connections are done while draining
+const DRAIN_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
 impl UsbDevice {
     /// # Safety
     ///
@@ -81,6 +101,8 @@ impl UsbDevice {
             reader_loop_handler: OnceCell::const_new(),
             writer_loop_handler: OnceCell::const_new(),
             dropped: AtomicBool::new(false),
+            writing: AtomicBool::new(false),
+            flushed: Notify::new(),
         });
 
         info!(device_id = id, "Spawning reader & writer loops");
@@ -178,6 +200,8 @@ impl UsbDevice {
             reader_loop_handler: OnceCell::const_new(),
             writer_loop_handler: OnceCell::const_new(),
             dropped: AtomicBool::new(false),
+            writing: AtomicBool::new(false),
+            flushed: Notify::new(),
         });
 
         info!(device_id = id, "Spawning reader & writer loops");
@@ -285,12 +309,21 @@ impl UsbDevice {
 
         info!(target: "device_writer", device_id, "Writer loop started");
         loop {
+            // done with the last one (even if it failed)
+            self.writing.store(false, Ordering::Release);
+
+            if rx.is_empty() {
+                self.flushed.notify_waiters();
+            }
+
             trace!(target: "device_writer", device_id, "Waiting for a packet");
             let Ok(mut packet) = rx.recv().await else {
                 error!(target: "device_writer", device_id, "Writer channel closed");
                 break;
             };
 
+            self.writing.store(true, Ordering::Release);
+
             debug!(
                 target: "device_writer",
                 device_id,
@@ -379,6 +412,28 @@ impl UsbDevice {
         Ok(conn)
     }
 
+    /// opens a connection to the given port, and hands it to `idevice` so the daemon it self can
+    /// talk to services on the device (e.g. lockdownd)
+    pub async fn connect_idevice(
+        self: &Arc<Self>,
+        destination_port: u16,
+        label: impl Into<String>,
+    ) -> Result<Idevice, RusbmuxError> {
+        let conn = self.connect(destination_port).await?;
+
+        // the other half acts as a client, and it's fed to the same loop used for `Connect`
+        let (socket, client) = tokio::io::duplex(IDEVICE_BUFF_SIZE);
+
+        let device_id = self.core.id;
+        tokio::spawn(async move {
+            if let Err(e) = handle_usb_device_connect(Box::new(client), conn).await {
+                debug!(device_id, destination_port, err = ?e, "Internal connection closed");
+            }
+        });
+
+        Ok(Idevice::new(Box::new(socket), label))
+    }
+
     /// # Safety
     ///
     /// make sure the connection is already opened
@@ -471,6 +526,45 @@ impl UsbDevice {
         Ok(())
     }
 
+    /// lets the connections finish what they have and close with a FIN, then waits for the writer
+    /// loop to send everything that is queued
+    ///
+    /// it doesn't shut the device down, and it doesn't give up on it's own, so bound it with a
+    /// timeout
+    pub async fn drain(&self) {
+        debug!(device_id = self.core.id, "Draining the connections");
+        self.core.drainer.cancel();
+
+        while self
+            .conns
+            .iter()
+            .any(|c| c.upgrade().is_some_and(|c| !c.dropped()))
+        {
+            tokio::time::sleep(DRAIN_POLL_INTERVAL).await;
+        }
+
+        self.wait_flushed().await;
+
+        debug!(device_id = self.core.id, "Drained");
+    }
+
+    /// resolves once the writer loop has nothing left to write
+    pub async fn wait_flushed(&self) {
+        loop {
+            let flushed = self.flushed.notified();
+            tokio::pin!(flushed);
+
+            // registered before checking, so a notification in between isn't missed
+            flushed.as_mut().enable();
+
+            if self.w_tx.is_empty() && !self.writing.load(Ordering::Acquire) {
+                return;
+            }
+
+            flushed.await;
+        }
+    }
+
     #[inline]
     fn dropped(&self) -> bool {
         self.dropped.load(std::sync::atomic::Ordering::Relaxed)
@@ -560,6 +654,9 @@ impl UsbDevice {
             "Adding device to plist"
         );
 
+        // only there if the preflight ran, clients that don't know about them just ignore them
+        let preflight = self.core.status().preflight;
+
         Ok(plist_macro::plist!({
             "DeviceID": self.core.id,
             "MessageType": "Attached",
@@ -570,6 +667,10 @@ impl UsbDevice {
                 "LocationID": location_id,
                 "ProductID": self.info.product_id(),
                 "SerialNumber": serial_number,
+                "TrustState":? preflight.as_ref().map(|p| p.trust.as_str()),
+                "DeviceName":? preflight.as_ref().and_then(|p| p.device_name.clone()),
+                "ProductType":? preflight.as_ref().and_then(|p| p.product_type.clone()),
+                "ProductVersion":? preflight.and_then(|p| p.product_version),
             }
         }))
     }
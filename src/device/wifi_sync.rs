@@ -0,0 +1,85 @@
+use std::sync::Arc;
+
+use idevice::lockdown::LockdownClient;
+use tracing::{debug, info, warn};
+
+use crate::{
+    device::{status::WifiConnections, usb::UsbDevice},
+    error::RusbmuxError,
+    pair_record::read_pairing_file,
+};
+
+const WIRELESS_LOCKDOWN_DOMAIN: &str = "com.apple.mobile.wireless_lockdown";
+const ENABLE_WIFI_CONNECTIONS_KEY: &str = "EnableWifiConnections";
+
+/// turns on "Show this iPhone when on Wi-Fi", so the device shows up in the network watcher
+///
+/// the device must already be paired
+pub async fn enable_wifi_connections(
+    device: &Arc<UsbDevice>,
+) -> Result<WifiConnections, RusbmuxError> {
+    let device_id = device.core.id;
+    let serial_number = device.info.serial_number().unwrap_or_default().to_string();
+
+    let pairing_file = read_pairing_file(&serial_number).await?;
+
+    let mut lockdown = LockdownClient::new(
+        device
+            .connect_idevice(
+                LockdownClient::LOCKDOWND_PORT,
+                format!("rusbmux_{serial_number}_wifi_sync"),
+            )
+            .await?,
+    );
+
+    lockdown.start_session(&pairing_file).await?;
+
+    let enabled = lockdown
+        .get_value(
+            Some(ENABLE_WIFI_CONNECTIONS_KEY),
+            Some(WIRELESS_LOCKDOWN_DOMAIN),
+        )
+        .await
+        .ok()
+        .and_then(|v| v.as_boolean())
+        .unwrap_or(false);
+
+    if enabled {
+        debug!(device_id, "WiFi connections are already enabled");
+        return Ok(WifiConnections::AlreadyEnabled);
+    }
+
+    lockdown
+        .set_value(
+            ENABLE_WIFI_CONNECTIONS_KEY,
+            plist::Value::Boolean(true),
+            Some(WIRELESS_LOCKDOWN_DOMAIN),
+        )
+        .await?;
+
+    info!(device_id, serial_number, "Enabled WiFi connections");
+
+    Ok(WifiConnections::Enabled)
+}
+
+/// enables the WiFi connections in the background, and records the result in the device status
+pub fn spawn_enable_wifi_connections(device: Arc<UsbDevice>) {
+    tokio::spawn(async move {
+        let device_id = device.core.id;
+
+        let state = tokio::select! {
+            res = enable_wifi_connections(&device) => match res {
+                Ok(state) => state,
+                Err(e) => {
+                    warn!(device_id, err = ?e, "Failed to enable WiFi connections");
+                    WifiConnections::Failed(e.to_string())
+                }
+            },
+            _ = device.core.canceler.cancelled() => return,
+        };
+
+        device
+            .core
+            .update_status(|s| s.wifi_connections = Some(state));
+    });
+}
@@ -1,6 +1,6 @@
 use thiserror::Error;
 
-use crate::handler::ResultCode;
+use crate::{daemon::limits::Limit, handler::ResultCode};
 
 #[derive(Debug, Error)]
 pub enum RusbmuxError {
@@ -47,9 +47,39 @@ pub enum RusbmuxError {
     #[error("Ran out of source port for connections")]
     RanOutofSourcePort,
 
+    #[error("Timed out {0}")]
+    Timeout(&'static str),
+
     #[error("The device rejected the power assertion: {0}")]
     PowerAssertion(String),
 
+    #[error("Invalid pair record: {0}")]
+    InvalidPairRecord(String),
+
+    #[error("Pair record encryption error: {0}")]
+    PairRecordCrypto(String),
+
+    #[error("Invalid configuration: {0}")]
+    Config(String),
+
+    #[error("Failed to drop the privileges: {0}")]
+    Privileges(String),
+
+    #[error("Failed to sandbox the daemon: {0}")]
+    Sandbox(String),
+
+    #[error("Another instance is already running: {0}")]
+    AlreadyRunning(String),
+
+    #[error("The device `{0}` is leased by another client")]
+    DeviceLeased(String),
+
+    #[error("Failed to generate the random bytes for {0}")]
+    Random(&'static str),
+
+    #[error("The {0} limit was reached")]
+    LimitReached(Limit),
+
     #[error("{0}")]
     Idevice(#[from] idevice::IdeviceError),
 
@@ -65,15 +95,17 @@ pub enum MissingFields {
     DeviceID,
     PortNumber,
     SystemBUID,
+    SerialNumber,
+    LeaseID,
 }
 
 impl MissingFields {
     pub fn result_code(&self) -> ResultCode {
         match self {
-            Self::PairRecordID | Self::PairRecordData | Self::SystemBUID => {
+            Self::PairRecordID | Self::PairRecordData | Self::SystemBUID | Self::LeaseID => {
                 ResultCode::InvalidInput
             }
-            Self::DeviceID => ResultCode::BadDeviceOrNoSuchFile,
+            Self::DeviceID | Self::SerialNumber => ResultCode::BadDeviceOrNoSuchFile,
             Self::PortNumber => ResultCode::BadCommand,
         }
     }
@@ -0,0 +1,171 @@
+use serde::Deserialize;
+use tracing::warn;
+
+use crate::{
+    handler::ResultCode,
+    parser::usbmux::{UsbMuxCommon, UsbMuxRequest},
+    watcher::CONNECTED_DEVICES,
+};
+
+/// who is on the other side of a client connection
+///
+/// only the unix sockets know it, the tcp and vsock clients have none of it
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Peer {
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+    pub pid: Option<i32>,
+}
+
+#[cfg(unix)]
+impl Peer {
+    pub fn from_unix(stream: &tokio::net::UnixStream) -> Self {
+        match stream.peer_cred() {
+            Ok(cred) => Self {
+                uid: Some(cred.uid()),
+                gid: Some(cred.gid()),
+                pid: cred.pid(),
+            },
+            Err(e) => {
+                warn!(err = %e, "Failed to get the peer credentials");
+                Self::default()
+            }
+        }
+    }
+}
+
+/// what the pair record requests a client can make
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum PairRecordAccess {
+    None,
+    Read,
+    #[default]
+    ReadWrite,
+}
+
+/// what a client is allowed to do, and which clients it's for
+///
+/// every matcher that is set has to match, a rule without any matches every client
+///
+/// `prog_name` and `bundle_id` are whatever the client says it is, they should only narrow down a
+/// rule that also matches the `uid` or `gid`
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AccessRule {
+    pub uid: Option<u32>,
+
+    /// only the primary group of the client is known
+    pub gid: Option<u32>,
+
+    pub prog_name: Option<String>,
+    pub bundle_id: Option<String>,
+
+    /// the devices (by udid) it can connect to and use the pair records of, all of them if it's
+    /// not set, it doesn't see the others in the device lists or the `Status` either
+    pub devices: Option<Vec<String>>,
+
+    /// the device ports it can connect to, all of them if it's not set
+    pub ports: Option<Vec<u16>>,
+
+    pub pair_records: PairRecordAccess,
+}
+
+impl AccessRule {
+    /// the rule for the clients no other rule matched, when they're denied
+    #[must_use]
+    pub const fn deny_all() -> Self {
+        Self {
+            uid: None,
+            gid: None,
+            prog_name: None,
+            bundle_id: None,
+            devices: Some(Vec::new()),
+            ports: Some(Vec::new()),
+            pair_records: PairRecordAccess::None,
+        }
+    }
+
+    #[must_use]
+    pub fn matches(&self, peer: &Peer, common: &UsbMuxCommon) -> bool {
+        let matches = |want: &Option<String>, got: &Option<String>| {
+            want.is_none() || want.as_deref() == got.as_deref()
+        };
+
+        (self.uid.is_none() || self.uid == peer.uid)
+            && (self.gid.is_none() || self.gid == peer.gid)
+            && matches(&self.prog_name, &common.prog_name)
+            && matches(&self.bundle_id, &common.bundle_id)
+    }
+
+    #[must_use]
+    pub fn can_use_device(&self, udid: &str) -> bool {
+        self.devices
+            .as_ref()
+            .is_none_or(|devices| devices.iter().any(|d| d == udid))
+    }
+
+    #[must_use]
+    pub fn can_connect_to_port(&self, port: u16) -> bool {
+        self.ports
+            .as_ref()
+            .is_none_or(|ports| ports.contains(&port))
+    }
+
+    /// the result code to refuse the request with, if it's not allowed
+    #[must_use]
+    pub fn check(&self, request: &UsbMuxRequest) -> Option<ResultCode> {
+        let allowed = match request {
+            UsbMuxRequest::Connect {
+                device_id, port, ..
+            } => {
+                // a device that isn't there is left for `Connect` to report
+                let udid = CONNECTED_DEVICES
+                    .get(device_id)
+                    .map(|d| d.serial_number().into_owned());
+
+                let allowed = udid.is_none_or(|udid| self.can_use_device(&udid))
+                    && self.can_connect_to_port(u16::from_be(*port));
+
+                return (!allowed).then_some(ResultCode::ConnectionRefused);
+            }
+
+            UsbMuxRequest::AcquireLease { serial_number, .. } => self.can_use_device(serial_number),
+
+            UsbMuxRequest::ReadPairRecord { pair_record_id, .. } => {
+                self.pair_records >= PairRecordAccess::Read && self.can_use_device(pair_record_id)
+            }
+
+            UsbMuxRequest::SavePairRecord { pair_record_id, .. }
+            | UsbMuxRequest::DeletePairRecord { pair_record_id, .. } => {
+                self.pair_records == PairRecordAccess::ReadWrite
+                    && self.can_use_device(pair_record_id)
+            }
+
+            _ => true,
+        };
+
+        (!allowed).then_some(ResultCode::BadCommand)
+    }
+}
+
+/// decides what every client can do, the first rule that matches the client is used
+///
+/// it allows everything by default, like usbmuxd
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AccessPolicy {
+    pub rules: Vec<AccessRule>,
+
+    /// used when no rule matched
+    pub default: AccessRule,
+}
+
+impl AccessPolicy {
+    #[must_use]
+    pub fn rule_for(&self, peer: &Peer, common: &UsbMuxCommon) -> &AccessRule {
+        self.rules
+            .iter()
+            .find(|rule| rule.matches(peer, common))
+            .unwrap_or(&self.default)
+    }
+}
@@ -2,9 +2,11 @@ use std::sync::Arc;
 
 use crate::{
     AsyncReading, AsyncWriting, ReadWrite,
+    audit::AuditEntry,
     conn::{DeviceConn, NetworkDeviceConn, UsbDeviceConn},
     error::RusbmuxError,
     handler::send_result,
+    usb_backend::MAX_PACKET_PAYLOAD_SIZE,
     watcher::CONNECTED_DEVICES,
 };
 
@@ -21,23 +23,26 @@ pub async fn handle_connect(
     device_id: u64,
     port_number: u16,
     tag: u32,
+    audit: AuditEntry,
 ) -> Result<(), RusbmuxError> {
     let conn = match connect(device_id, port_number, tag).await {
         Ok(c) => c,
         Err(e) => {
-            match e {
+            let code = match e {
                 RusbmuxError::DeviceNotFound(_) | RusbmuxError::RanOutofSourcePort => {
-                    send_result(&mut client, ResultCode::BadDeviceOrNoSuchFile, tag).await?;
+                    ResultCode::BadDeviceOrNoSuchFile
                 }
+                _ => ResultCode::ConnectionRefused,
+            };
+
+            audit.record_error(Some(code), &e);
+            send_result(&mut client, code, tag).await?;
 
-                _ => {
-                    send_result(&mut client, ResultCode::ConnectionRefused, tag).await?;
-                }
-            }
             return Err(e);
         }
     };
 
+    audit.record(ResultCode::OK);
     send_result(&mut client, ResultCode::OK, tag).await?;
 
     match conn {
@@ -92,6 +97,26 @@ pub async fn handle_usb_device_connect(
                 return Ok(());
             }
 
+            _ = conn.wait_drain() => {
+                debug!(device_id, port_number, "Device is draining, finishing the connection");
+
+                // what the device already sent goes to the client, and what the client already
+                // sent goes to the device
+                while let Ok(packet) = conn.rx.try_recv() {
+                    client_send(&mut client_writer, packet.payload.encode()).await?;
+                }
+
+                while !read_buf.is_empty() {
+                    let len = read_buf.len().min(MAX_PACKET_PAYLOAD_SIZE);
+                    conn.send_bytes(read_buf.split_to(len).freeze()).await?;
+                }
+
+                conn.finish().await?;
+
+                let _ = client_writer.shutdown().await;
+                return Ok(());
+            }
+
             packet = conn.recv() => {
                 let packet = packet?;
                 debug!(device_id, port_number, "Received packet from device");
@@ -1,31 +1,42 @@
 use std::io::ErrorKind;
 
-use tracing::{debug, error, warn};
+use tracing::{debug, error};
 
 use crate::{
     AsyncWriting,
+    audit::AuditEntry,
     error::RusbmuxError,
-    handler::{LOCKDOWN_PATH, ResultCode, send_result},
+    handler::{ResultCode, send_result},
+    pair_record,
 };
 
 pub async fn handle_delete_pair_record(
     writer: &mut impl AsyncWriting,
     pair_record_id: String,
+    namespace: Option<&str>,
     tag: u32,
+    audit: AuditEntry,
 ) -> Result<(), RusbmuxError> {
-    match delete_pair_record(pair_record_id, tag).await {
-        Ok(()) => send_result(writer, ResultCode::OK, tag).await?,
+    match delete_pair_record(pair_record_id, namespace, tag).await {
+        Ok(()) => {
+            audit.record(ResultCode::OK);
+            send_result(writer, ResultCode::OK, tag).await?;
+        }
         Err(e) => {
-            match e {
-                RusbmuxError::UnexpectedPacket(_) => {
-                    send_result(writer, ResultCode::BadCommand, tag).await?;
-                }
-
+            let code = match e {
+                RusbmuxError::UnexpectedPacket(_) => Some(ResultCode::BadCommand),
                 RusbmuxError::IO(ref e) if e.kind() == ErrorKind::NotFound => {
-                    send_result(writer, ResultCode::BadDeviceOrNoSuchFile, tag).await?;
+                    Some(ResultCode::BadDeviceOrNoSuchFile)
                 }
-                _ => {}
+                _ => None,
+            };
+
+            audit.record_error(code, &e);
+
+            if let Some(code) = code {
+                send_result(writer, code, tag).await?;
             }
+
             return Err(e);
         }
     }
@@ -33,26 +44,18 @@ pub async fn handle_delete_pair_record(
     Ok(())
 }
 
-pub async fn delete_pair_record(pair_record_id: String, tag: u32) -> Result<(), RusbmuxError> {
-    debug!(tag, pair_record_id, "Deleting pair record");
-
-    if pair_record_id.contains('/')
-        || pair_record_id.contains('\\')
-        || pair_record_id.contains("..")
-    {
-        warn!(?pair_record_id, "malicious pair record id detected");
-        return Err(RusbmuxError::UnexpectedPacket(
-            "Given pair record id is malformed".into(),
-        ));
-    }
-
-    let path = format!("{LOCKDOWN_PATH}/{pair_record_id}.plist");
+pub async fn delete_pair_record(
+    pair_record_id: String,
+    namespace: Option<&str>,
+    tag: u32,
+) -> Result<(), RusbmuxError> {
+    debug!(tag, pair_record_id, namespace, "Deleting pair record");
 
-    tokio::fs::remove_file(&path).await.inspect_err(
-        |e| error!(tag, pair_record_id, path, err = ?e, "Failed to delete pair record"),
-    )?;
+    pair_record::delete_pair_record_in(namespace, &pair_record_id)
+        .await
+        .inspect_err(|e| error!(tag, pair_record_id, err = ?e, "Failed to delete pair record"))?;
 
-    debug!(tag, pair_record_id, path, "Pair record deleted");
+    debug!(tag, pair_record_id, "Pair record deleted");
 
     Ok(())
 }
@@ -1,6 +1,8 @@
 use crate::{
     AsyncWriting,
+    config::{Transport, config},
     error::RusbmuxError,
+    handler::visibility::Visibility,
     parser::usbmux::{UsbMuxMsgType, UsbMuxPacket, UsbMuxVersion},
     watcher::CONNECTED_DEVICES,
 };
@@ -8,13 +10,15 @@ use crate::{
 use tokio::io::AsyncWriteExt;
 use tracing::{debug, error};
 
-pub async fn devices_plist() -> Result<plist::Value, RusbmuxError> {
+pub async fn devices_plist(visibility: &Visibility) -> Result<plist::Value, RusbmuxError> {
     let mut devices_plist = Vec::with_capacity(CONNECTED_DEVICES.len());
 
+    let prefer_usb = config().transport == Transport::PreferUsb;
+
     // perfer USB if the device is connected on both USB and WiFi
     for device in CONNECTED_DEVICES
         .iter()
-        .filter(|dev| match dev.as_network() {
+        .filter(|dev| match dev.as_network().filter(|_| prefer_usb) {
             // it's a network device and the device serial_number is also available in other device
             // but they are not the same device
             //
@@ -29,6 +33,7 @@ pub async fn devices_plist() -> Result<plist::Value, RusbmuxError> {
             }
             Some(_) | None => true,
         })
+        .filter(|dev| visibility.can_see(dev))
     {
         devices_plist.push(device.create_device_attached()?);
     }
@@ -50,8 +55,9 @@ pub async fn devices_plist() -> Result<plist::Value, RusbmuxError> {
 pub async fn handle_device_list(
     writer: &mut impl AsyncWriting,
     tag: u32,
+    visibility: &Visibility,
 ) -> Result<(), RusbmuxError> {
-    let devices_plist = devices_plist().await?;
+    let devices_plist = devices_plist(visibility).await?;
 
     let devices_xml = plist_macro::plist_value_to_xml_bytes(&devices_plist);
 
@@ -0,0 +1,169 @@
+use std::time::Duration;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tracing::{debug, error, info, trace, warn};
+
+use crate::{
+    AsyncWriting, ReadWrite,
+    audit::AuditEntry,
+    error::RusbmuxError,
+    handler::{ResultCode, send_result},
+    lease::{self, Lease, LeaseMode, LeaseOwner, LeaseToken},
+    parser::usbmux::{UsbMuxMsgType, UsbMuxPacket, UsbMuxVersion},
+};
+
+/// what `AcquireLease` asked for
+pub struct LeaseRequest {
+    pub serial_number: String,
+    pub mode: Option<String>,
+    pub wait: bool,
+    pub timeout: Option<u64>,
+}
+
+/// the leases are held by the client connection, they're released once it's closed
+pub async fn handle_acquire_lease(
+    client: &mut Box<dyn ReadWrite>,
+    leases: &mut Vec<Lease>,
+    request: LeaseRequest,
+    owner: LeaseOwner,
+    tag: u32,
+    audit: AuditEntry,
+) -> Result<(), RusbmuxError> {
+    let mode = match request.mode.as_deref().map(str::parse::<LeaseMode>) {
+        None => LeaseMode::default(),
+        Some(Ok(mode)) => mode,
+        Some(Err(e)) => {
+            warn!(tag, err = e, "Invalid lease mode");
+            audit.record(ResultCode::InvalidInput);
+            return send_result(client, ResultCode::InvalidInput, tag).await;
+        }
+    };
+
+    let udid = request.serial_number;
+
+    let lease = if request.wait {
+        let acquire = lease::acquire(&udid, mode, owner);
+
+        let acquired = match request.timeout {
+            Some(secs) => {
+                match tokio::time::timeout(
+                    Duration::from_secs(secs),
+                    while_client_waits(client, acquire),
+                )
+                .await
+                {
+                    Ok(acquired) => acquired,
+                    Err(_) => Ok(Err(RusbmuxError::Timeout("waiting for the lease"))),
+                }
+            }
+            None => while_client_waits(client, acquire).await,
+        };
+
+        // the client went away, or sent something while it was supposed to wait
+        let acquired = match acquired {
+            Ok(acquired) => acquired,
+            Err(e) => {
+                audit.record_error(None, &e);
+                return Err(e);
+            }
+        };
+
+        match acquired {
+            Ok(lease) => lease,
+            Err(e) => {
+                info!(tag, udid, err = %e, "Didn't get the lease");
+                audit.record_error(Some(ResultCode::ConnectionRefused), &e);
+                return send_result(client, ResultCode::ConnectionRefused, tag).await;
+            }
+        }
+    } else {
+        match lease::try_acquire(&udid, mode, owner) {
+            Ok(lease) => lease,
+            Err(e) => {
+                info!(tag, udid, err = %e, "Refused the lease");
+                audit.record_error(Some(ResultCode::ConnectionRefused), &e);
+                return send_result(client, ResultCode::ConnectionRefused, tag).await;
+            }
+        }
+    };
+
+    let token = lease.token();
+    leases.push(lease);
+
+    audit.record(ResultCode::OK);
+
+    send_lease_result(client, token, tag).await
+}
+
+pub async fn handle_release_lease(
+    writer: &mut impl AsyncWriting,
+    leases: &mut Vec<Lease>,
+    token: LeaseToken,
+    tag: u32,
+) -> Result<(), RusbmuxError> {
+    // only the client that has it can let go of it
+    let Some(index) = leases.iter().position(|l| l.token() == token) else {
+        warn!(tag, "The client doesn't hold the lease");
+        return send_result(writer, ResultCode::BadDeviceOrNoSuchFile, tag).await;
+    };
+
+    drop(leases.swap_remove(index));
+
+    send_result(writer, ResultCode::OK, tag).await
+}
+
+/// waits for `fut`, while making sure the client is still there
+///
+/// the client isn't supposed to send anything before it's answered, so anything it sends is an
+/// error
+pub async fn while_client_waits<T>(
+    client: &mut Box<dyn ReadWrite>,
+    fut: impl Future<Output = T>,
+) -> Result<T, RusbmuxError> {
+    let mut buf = [0; 1];
+
+    tokio::select! {
+        out = fut => Ok(out),
+
+        read = client.read(&mut buf) => match read? {
+            0 => {
+                debug!("Client disconnected while waiting");
+                Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into())
+            }
+            _ => Err(RusbmuxError::UnexpectedPacket(
+                "the client sent a request while it was waiting".to_string(),
+            )),
+        },
+    }
+}
+
+/// a `Result` with the token of the lease (as the `LeaseID`), so it can be released or used with
+/// `Connect`
+async fn send_lease_result(
+    writer: &mut impl AsyncWriting,
+    token: LeaseToken,
+    tag: u32,
+) -> Result<(), RusbmuxError> {
+    let result_payload = plist_macro::plist!({
+        "MessageType": "Result",
+        "Number": (ResultCode::OK as u16),
+        "LeaseID": token.to_string(),
+    });
+
+    let usbmux_packet = UsbMuxPacket::encode_from(
+        plist_macro::plist_value_to_xml_bytes(&result_payload),
+        UsbMuxVersion::Plist,
+        UsbMuxMsgType::MessagePlist,
+        tag,
+    );
+
+    writer.write_all(&usbmux_packet).await.inspect_err(|e| {
+        if !crate::utils::is_disconnect_io(e) {
+            error!(tag, err = ?e, "Failed to write the lease result");
+        }
+    })?;
+
+    trace!(tag, "Sent the lease result");
+
+    Ok(())
+}
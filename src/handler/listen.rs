@@ -1,7 +1,9 @@
+use std::collections::HashSet;
+
 use crate::{
     AsyncWriting,
     error::RusbmuxError,
-    handler::{ResultCode, send_result},
+    handler::{ResultCode, send_result, visibility::Visibility},
     parser::usbmux::{UsbMuxMsgType, UsbMuxPacket, UsbMuxVersion},
     watcher::{CONNECTED_DEVICES, DeviceEvent, HOTPLUG_EVENT_TX},
 };
@@ -9,7 +11,11 @@ use crate::{
 use tokio::{io::AsyncWriteExt, sync::broadcast};
 use tracing::{debug, error, info, trace, warn};
 
-pub async fn handle_listen(writer: &mut impl AsyncWriting, tag: u32) -> Result<(), RusbmuxError> {
+pub async fn handle_listen(
+    writer: &mut impl AsyncWriting,
+    tag: u32,
+    visibility: &Visibility,
+) -> Result<(), RusbmuxError> {
     let mut event_receiver = match HOTPLUG_EVENT_TX
         .get()
         .ok_or(RusbmuxError::HotPlugNotSupported)
@@ -24,7 +30,8 @@ pub async fn handle_listen(writer: &mut impl AsyncWriting, tag: u32) -> Result<(
 
     send_result(writer, ResultCode::OK, tag).await?;
 
-    send_currently_connected(writer, tag).await?;
+    // the devices it was told about, the events of the others are not for it
+    let mut announced = send_currently_connected(writer, tag, visibility).await?;
 
     debug!(tag, "Listening for device attach/detach events");
 
@@ -44,6 +51,13 @@ pub async fn handle_listen(writer: &mut impl AsyncWriting, tag: u32) -> Result<(
                     continue;
                 };
 
+                if !visibility.can_see(&device) {
+                    trace!(device_id = id, tag, "The device is not visible, skipping");
+                    continue;
+                }
+
+                announced.insert(id);
+
                 let device_plist = device.create_device_attached()?;
 
                 let device_xml = plist_macro::plist_value_to_xml_bytes(&device_plist);
@@ -65,6 +79,10 @@ pub async fn handle_listen(writer: &mut impl AsyncWriting, tag: u32) -> Result<(
             DeviceEvent::Detached { id } => {
                 info!(id, "Device detached");
 
+                if !announced.remove(&id) {
+                    continue;
+                }
+
                 let device_plist = plist_macro::plist!({
                     "MessageType": "Detached",
                     "DeviceID": id
@@ -86,6 +104,62 @@ pub async fn handle_listen(writer: &mut impl AsyncWriting, tag: u32) -> Result<(
 
                 trace!(device_id = id, tag, "Detach event sent");
             }
+            DeviceEvent::Paired { id } => {
+                info!(id, "Device paired");
+
+                if !announced.contains(&id) {
+                    continue;
+                }
+
+                let device_plist = plist_macro::plist!({
+                    "MessageType": "Paired",
+                    "DeviceID": id
+                });
+
+                let device_xml = plist_macro::plist_value_to_xml_bytes(&device_plist);
+
+                let paired_packet = UsbMuxPacket::encode_from(
+                    device_xml,
+                    UsbMuxVersion::Plist,
+                    UsbMuxMsgType::MessagePlist,
+                    tag,
+                );
+                writer.write_all(&paired_packet).await.inspect_err(|e| {
+                    if !crate::utils::is_disconnect_io(e) {
+                        error!(device_id = id, tag, err = ?e, "Failed to send device paired event")
+                    }
+                })?;
+
+                trace!(device_id = id, tag, "Paired event sent");
+            }
+            DeviceEvent::PairRecordStale { id } => {
+                info!(id, "Device rejected the pair record");
+
+                if !announced.contains(&id) {
+                    continue;
+                }
+
+                let device_plist = plist_macro::plist!({
+                    "MessageType": "PairRecordStale",
+                    "DeviceID": id
+                });
+
+                let device_xml = plist_macro::plist_value_to_xml_bytes(&device_plist);
+
+                let stale_packet = UsbMuxPacket::encode_from(
+                    device_xml,
+                    UsbMuxVersion::Plist,
+                    UsbMuxMsgType::MessagePlist,
+                    tag,
+                );
+                writer.write_all(&stale_packet).await.inspect_err(|e| {
+                    if !crate::utils::is_disconnect_io(e) {
+                        error!(device_id = id, tag, err = ?e, "Failed to send pair record stale event")
+                    }
+                })?;
+
+                trace!(device_id = id, tag, "Pair record stale event sent");
+            }
         }
     }
 
@@ -94,10 +168,14 @@ pub async fn handle_listen(writer: &mut impl AsyncWriting, tag: u32) -> Result<(
     Ok(())
 }
 
+/// gives back the ids of the devices it sent
 pub async fn send_currently_connected(
     writer: &mut impl AsyncWriting,
     tag: u32,
-) -> Result<(), RusbmuxError> {
+    visibility: &Visibility,
+) -> Result<HashSet<u64>, RusbmuxError> {
+    let mut sent = HashSet::new();
+
     // TODO: put it in a function
     for device in CONNECTED_DEVICES
         .iter()
@@ -116,6 +194,7 @@ pub async fn send_currently_connected(
             }
             Some(_) | None => true,
         })
+        .filter(|dev| visibility.can_see(dev))
     {
         let device_plist = device.create_device_attached()?;
 
@@ -132,7 +211,9 @@ pub async fn send_currently_connected(
                 error!(device_id = device.id(), tag, err = ?e, "Failed to send initial device packet")
             }
         )?;
+
+        sent.insert(device.id());
     }
 
-    Ok(())
+    Ok(sent)
 }
@@ -1,30 +1,47 @@
-use std::{io::ErrorKind, ops::ControlFlow};
+use std::{io::ErrorKind, ops::ControlFlow, time::Duration};
 
 use tokio::io::AsyncWriteExt;
 use tracing::{debug, error, info, trace, warn};
 
 use crate::{
     AsyncWriting, ReadWrite,
+    audit::AuditEntry,
+    daemon::{
+        ListenAddr,
+        limits::{self, Limit},
+    },
     error::{MissingFields, ParseError, RusbmuxError},
     handler::{
-        connect::handle_connect, delete_pair_record::handle_delete_pair_record,
-        device_list::handle_device_list, listen::handle_listen,
-        listeners_list::handle_listeners_list, read_buid::handle_read_buid,
-        read_pair_record::handle_read_pair_record, save_pair_record::handle_save_pair_record,
+        access::Peer,
+        connect::handle_connect,
+        delete_pair_record::handle_delete_pair_record,
+        device_list::handle_device_list,
+        lease::{LeaseRequest, handle_acquire_lease, handle_release_lease, while_client_waits},
+        listen::handle_listen,
+        listeners_list::handle_listeners_list,
+        read_buid::handle_read_buid,
+        read_pair_record::handle_read_pair_record,
+        save_pair_record::handle_save_pair_record,
+        status::handle_status,
     },
+    lease::{Lease, LeaseOwner, can_connect, wait_to_connect},
     parser::usbmux::{
         PayloadMessageType, UsbMuxMsgType, UsbMuxPacket, UsbMuxRequest, UsbMuxVersion,
     },
 };
 
+pub mod access;
 pub mod connect;
 pub mod delete_pair_record;
 pub mod device_list;
+pub mod lease;
 pub mod listen;
 pub mod listeners_list;
 pub mod read_buid;
 pub mod read_pair_record;
 pub mod save_pair_record;
+pub mod status;
+pub mod visibility;
 
 #[cfg(target_os = "macos")]
 pub const LOCKDOWN_PATH: &str = "/var/db/lockdown";
@@ -46,7 +63,11 @@ pub enum HandlerError {
     },
 }
 
-pub async fn handle_client(mut client: Box<dyn ReadWrite>) {
+/// `listener` is where the client came in, it decides what it can see
+pub async fn handle_client(mut client: Box<dyn ReadWrite>, peer: Peer, listener: ListenAddr) {
+    // the leases the client took, they're released once it's gone
+    let mut leases = Vec::new();
+
     loop {
         let usbmux_packet = match UsbMuxPacket::from_reader(&mut client).await {
             Ok(p) => p,
@@ -76,7 +97,7 @@ pub async fn handle_client(mut client: Box<dyn ReadWrite>) {
             "Received usbmux packet"
         );
 
-        match handle_message(&mut client, usbmux_packet).await {
+        match handle_message(&mut client, &peer, &listener, &mut leases, usbmux_packet).await {
             // comes from the ones that transforms the connection (Connect, Listen), because you're
             // not supposed to do anything else if those failed
             Ok(ControlFlow::Break(())) => {
@@ -112,6 +133,9 @@ pub async fn handle_client(mut client: Box<dyn ReadWrite>) {
 
 pub async fn handle_message(
     client: &mut Box<dyn ReadWrite>,
+    peer: &Peer,
+    listener: &ListenAddr,
+    leases: &mut Vec<Lease>,
     usbmux_packet: UsbMuxPacket,
 ) -> Result<ControlFlow<()>, HandlerError> {
     let tag = usbmux_packet.header.tag;
@@ -153,6 +177,8 @@ pub async fn handle_message(
                         MissingFields::PairRecordData,
                         MissingFields::DeviceID,
                         MissingFields::PortNumber,
+                        MissingFields::SerialNumber,
+                        MissingFields::LeaseID,
                     ] {
                         if err_str.contains(&format!("missing field `{field:?}`")) {
                             return RusbmuxError::ValueNotFound(field);
@@ -178,16 +204,109 @@ pub async fn handle_message(
                 }
             };
 
+            // the daemon is on it's way out, nothing new that would outlive it
+            if crate::daemon::is_draining()
+                && matches!(
+                    usbmux_request,
+                    UsbMuxRequest::Connect { .. } | UsbMuxRequest::Listen { .. }
+                )
+            {
+                info!(tag, "Shutting down, refusing the request");
+                send_result(client, ResultCode::ConnectionRefused, tag)
+                    .await
+                    .map_err(|e| classify(e, None))?;
+
+                return Ok(ControlFlow::Break(()));
+            }
+
+            let request = usbmux_request.message_type();
+            let audit = AuditEntry::new(peer, &usbmux_request);
+
+            // `Connect` and `Listen` take the connection over, there's nothing else to do with it
+            // once they're refused
+            let refused = if matches!(
+                request,
+                PayloadMessageType::Connect | PayloadMessageType::Listen
+            ) {
+                ControlFlow::Break(())
+            } else {
+                ControlFlow::Continue(())
+            };
+
+            if let Err(limit) = limits::check_rate(peer) {
+                refuse_over_limit(client, limit, request, audit, tag)
+                    .await
+                    .map_err(|e| classify(e, Some(request)))?;
+
+                return Ok(refused);
+            }
+
+            let config = crate::config::config();
+            let visibility = config.visibility_for(listener);
+
+            // it's the same as the device not being there, so it doesn't give away that it is
+            if let Some(code) = visibility.check(&usbmux_request) {
+                debug!(tag, %request, listen = %listener, "The device is not visible to the listener");
+
+                audit.record(code);
+
+                send_result(client, code, tag)
+                    .await
+                    .map_err(|e| classify(e, Some(request)))?;
+
+                return Ok(refused);
+            }
+
+            let rule = config.access.rule_for(peer, usbmux_request.common());
+
+            if let Some(code) = rule.check(&usbmux_request) {
+                warn!(
+                    tag,
+                    %request,
+                    uid = peer.uid,
+                    gid = peer.gid,
+                    pid = peer.pid,
+                    prog_name = usbmux_request.common().prog_name,
+                    "Denied the request"
+                );
+
+                audit.record_denied(code);
+
+                send_result(client, code, tag)
+                    .await
+                    .map_err(|e| classify(e, Some(request)))?;
+
+                return Ok(refused);
+            }
+
+            // a client limited to some devices doesn't see the others in the lists either
+            let limited = visibility.limited_to(rule);
+            let visibility = &*limited;
+
+            // the pair records of the listener, if it has it's own
+            let namespace = visibility.pair_records.as_deref();
+
             match usbmux_request {
                 UsbMuxRequest::ListDevices { .. } => {
-                    handle_device_list(client, usbmux_packet.header.tag)
+                    handle_device_list(client, usbmux_packet.header.tag, visibility)
                         .await
                         .map_err(|e| classify(e, Some(PayloadMessageType::ListDevices)))?;
                 }
 
                 UsbMuxRequest::Listen { .. } => {
+                    let _slot = match limits::take_listener() {
+                        Ok(slot) => slot,
+                        Err(limit) => {
+                            refuse_over_limit(client, limit, request, audit, tag)
+                                .await
+                                .map_err(|e| classify(e, Some(request)))?;
+
+                            return Ok(refused);
+                        }
+                    };
+
                     info!(tag, "Client entered listen mode");
-                    handle_listen(client, usbmux_packet.header.tag)
+                    handle_listen(client, usbmux_packet.header.tag, visibility)
                         .await
                         .map_err(|e| classify(e, Some(PayloadMessageType::Listen)))?;
 
@@ -200,18 +319,65 @@ pub async fn handle_message(
                         .map_err(|e| classify(e, Some(PayloadMessageType::ListListeners)))?;
                 }
                 UsbMuxRequest::ReadPairRecord { pair_record_id, .. } => {
-                    handle_read_pair_record(client, pair_record_id, usbmux_packet.header.tag)
+                    handle_read_pair_record(client, pair_record_id, namespace, tag, audit)
                         .await
                         .map_err(|e| classify(e, Some(PayloadMessageType::ReadPairRecord)))?;
                 }
                 UsbMuxRequest::Connect {
-                    device_id, port, ..
+                    ref common,
+                    device_id,
+                    port,
+                    lease_id,
                 } => {
+                    let udid = crate::watcher::CONNECTED_DEVICES
+                        .get(&device_id)
+                        .map(|d| d.serial_number().into_owned());
+
+                    // a device that isn't there is left for `Connect` to report
+                    if let Some(udid) = udid
+                        && !leases.iter().any(|l| l.udid() == udid)
+                        && !can_connect(&udid, lease_id, peer)
+                    {
+                        if config.queue_leased_connects {
+                            info!(tag, udid, "The device is leased, waiting for it");
+
+                            let owner = LeaseOwner::new(peer, common.prog_name.clone());
+                            while_client_waits(client, wait_to_connect(&udid, owner))
+                                .await
+                                .and_then(|waited| waited)
+                                .map_err(|e| classify(e, Some(request)))?;
+                        } else {
+                            info!(tag, udid, "The device is leased, refusing the connection");
+
+                            audit.record_error(
+                                Some(ResultCode::ConnectionRefused),
+                                &RusbmuxError::DeviceLeased(udid),
+                            );
+
+                            send_result(client, ResultCode::ConnectionRefused, tag)
+                                .await
+                                .map_err(|e| classify(e, Some(request)))?;
+
+                            return Ok(refused);
+                        }
+                    }
+
+                    let _slot = match limits::take_connect(device_id, u16::from_be(port)) {
+                        Ok(slot) => slot,
+                        Err(limit) => {
+                            refuse_over_limit(client, limit, request, audit, tag)
+                                .await
+                                .map_err(|e| classify(e, Some(request)))?;
+
+                            return Ok(refused);
+                        }
+                    };
+
                     info!(tag, "Client entered connect mode");
 
                     // HACK:
                     let client = std::mem::replace(client, Box::new(std::io::Cursor::new(vec![])));
-                    handle_connect(client, device_id, port, usbmux_packet.header.tag)
+                    handle_connect(client, device_id, port, tag, audit)
                         .await
                         .map_err(|e| classify(e, Some(PayloadMessageType::Connect)))?;
 
@@ -219,7 +385,7 @@ pub async fn handle_message(
                     return Ok(ControlFlow::Break(()));
                 }
                 UsbMuxRequest::ReadBUID { .. } => {
-                    handle_read_buid(client, &usbmux_packet)
+                    handle_read_buid(client, &usbmux_packet, audit, visibility)
                         .await
                         .map_err(|e| classify(e, Some(PayloadMessageType::ReadBUID)))?;
                 }
@@ -234,16 +400,47 @@ pub async fn handle_message(
                         pair_record_id,
                         pair_record_data,
                         device_id,
-                        usbmux_packet.header.tag,
+                        namespace,
+                        tag,
+                        audit,
                     )
                     .await
                     .map_err(|e| classify(e, Some(PayloadMessageType::SavePairRecord)))?;
                 }
                 UsbMuxRequest::DeletePairRecord { pair_record_id, .. } => {
-                    handle_delete_pair_record(client, pair_record_id, tag)
+                    handle_delete_pair_record(client, pair_record_id, namespace, tag, audit)
                         .await
                         .map_err(|e| classify(e, Some(PayloadMessageType::DeletePairRecord)))?;
                 }
+                UsbMuxRequest::Status { .. } => {
+                    handle_status(client, tag, visibility)
+                        .await
+                        .map_err(|e| classify(e, Some(PayloadMessageType::Status)))?;
+                }
+                UsbMuxRequest::AcquireLease {
+                    common,
+                    serial_number,
+                    mode,
+                    wait,
+                    timeout,
+                } => {
+                    let request = LeaseRequest {
+                        serial_number,
+                        mode,
+                        wait,
+                        timeout,
+                    };
+                    let owner = LeaseOwner::new(peer, common.prog_name);
+
+                    handle_acquire_lease(client, leases, request, owner, tag, audit)
+                        .await
+                        .map_err(|e| classify(e, Some(PayloadMessageType::AcquireLease)))?;
+                }
+                UsbMuxRequest::ReleaseLease { lease_id, .. } => {
+                    handle_release_lease(client, leases, lease_id, tag)
+                        .await
+                        .map_err(|e| classify(e, Some(PayloadMessageType::ReleaseLease)))?;
+                }
             }
         }
         // TODO: are others necessary?
@@ -255,7 +452,45 @@ pub async fn handle_message(
     Ok(ControlFlow::Continue(()))
 }
 
+/// tells the client it's over a limit, it's the same as the device refusing the connection
+async fn refuse_over_limit(
+    client: &mut Box<dyn ReadWrite>,
+    limit: Limit,
+    request: PayloadMessageType,
+    audit: AuditEntry,
+    tag: u32,
+) -> Result<(), RusbmuxError> {
+    warn!(tag, %request, %limit, "Over the limit, refusing the request");
+
+    audit.record_error(
+        Some(ResultCode::ConnectionRefused),
+        &RusbmuxError::LimitReached(limit),
+    );
+
+    send_result(client, ResultCode::ConnectionRefused, tag).await
+}
+
+/// how long a refused client gets to send it's first request
+const REFUSE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// answers the first request of a client that is over the limits with `ConnectionRefused`, then
+/// closes it
+pub async fn refuse_client(mut client: Box<dyn ReadWrite>) {
+    let _ = tokio::time::timeout(REFUSE_TIMEOUT, async {
+        if let Ok(packet) = UsbMuxPacket::from_reader(&mut client).await {
+            let _ = send_result(
+                &mut client,
+                ResultCode::ConnectionRefused,
+                packet.header.tag,
+            )
+            .await;
+        }
+    })
+    .await;
+}
+
 #[repr(u16)]
+#[derive(Debug, Clone, Copy)]
 pub enum ResultCode {
     OK = 0,
     BadCommand = 1,
@@ -296,11 +531,3 @@ pub async fn send_result(
 
     Ok(())
 }
-
-pub async fn create_lockdown_dir() -> Result<(), RusbmuxError> {
-    tokio::fs::create_dir_all(LOCKDOWN_PATH)
-        .await
-        .inspect_err(|e| error!(LOCKDOWN_PATH, e = ?e, "Failed to create the lockdown folder"))?;
-
-    Ok(())
-}
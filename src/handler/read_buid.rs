@@ -1,9 +1,9 @@
-use std::path::Path;
-
 use crate::{
     AsyncWriting,
+    audit::AuditEntry,
     error::{MissingFields, RusbmuxError},
-    handler::{LOCKDOWN_PATH, ResultCode, send_result},
+    handler::{ResultCode, send_result, visibility::Visibility},
+    pair_record::{SYSTEM_CONFIGURATION_ID, namespaced_id, pair_record_store},
     parser::usbmux::{UsbMuxMsgType, UsbMuxPacket, UsbMuxVersion},
 };
 use tokio::io::AsyncWriteExt;
@@ -12,40 +12,31 @@ use tracing::{debug, error, trace};
 pub async fn handle_read_buid(
     writer: &mut impl AsyncWriting,
     usbmux_packet: &UsbMuxPacket,
+    audit: AuditEntry,
+    visibility: &Visibility,
 ) -> Result<(), RusbmuxError> {
     let tag = usbmux_packet.header.tag;
 
-    let path = format!("{LOCKDOWN_PATH}/SystemConfiguration.plist");
-    let path = Path::new(&path);
-
-    trace!(tag, "Reading SystemConfiguration.plist");
-
-    if !path.exists() {
-        let id = uuid::Uuid::new_v4().to_string().to_uppercase();
-        debug!(tag, "SystemConfiguration.plist is missing, creating one");
-
-        let sbuid = plist_macro::plist_value_to_xml_bytes(&plist_macro::plist!({
-            "SystemBUID": id
-        }));
+    let buid = match &visibility.system_buid {
+        Some(buid) => Ok(buid.clone()),
+        None => read_system_buid_in(visibility.pair_records.as_deref()).await,
+    };
 
-        if let Err(e) = tokio::fs::write(&path, sbuid).await {
+    let buid = match buid {
+        Ok(buid) => buid,
+        Err(e @ RusbmuxError::IO(_)) => {
             error!(tag, err = ?e, "Failed to write a new SystemConfiguration.plist");
+            audit.record_error(Some(ResultCode::BadDeviceOrNoSuchFile), &e);
             let _ = send_result(writer, ResultCode::BadDeviceOrNoSuchFile, tag).await;
             return Ok(());
         }
-    }
+        Err(e) => {
+            audit.record_error(None, &e);
+            return Err(e);
+        }
+    };
 
-    let system_config = plist::from_file::<_, plist::Value>(&path)
-        .inspect_err(|e| error!( tag,  err = ?e, "Failed to read SystemConfiguration.plist"))?;
-    let buid = system_config
-        .as_dictionary()
-        .ok_or(RusbmuxError::UnexpectedPacket(
-            "Expected a packet with a dictionary plist payload".to_string(),
-        ))?
-        .get("SystemBUID")
-        .ok_or(RusbmuxError::ValueNotFound(MissingFields::SystemBUID))?
-        .as_string()
-        .ok_or(RusbmuxError::InvalidData("SystemBUID is not a string"))?;
+    audit.record(ResultCode::OK);
 
     trace!(tag, buid, "Extracted SystemBUID");
 
@@ -72,3 +63,47 @@ pub async fn handle_read_buid(
 
     Ok(())
 }
+
+/// reads the `SystemBUID` from `SystemConfiguration.plist`, creating it if it doesn't exist
+pub async fn read_system_buid() -> Result<String, RusbmuxError> {
+    read_system_buid_in(None).await
+}
+
+/// the same, but every pair record namespace has it's own
+pub async fn read_system_buid_in(namespace: Option<&str>) -> Result<String, RusbmuxError> {
+    let store = pair_record_store();
+    let record_id = namespaced_id(namespace, SYSTEM_CONFIGURATION_ID)?;
+
+    trace!(namespace, "Reading SystemConfiguration.plist");
+
+    let system_config = match store.read(&record_id).await {
+        Ok(system_config) => system_config,
+        Err(RusbmuxError::IO(e)) if e.kind() == std::io::ErrorKind::NotFound => {
+            let id = uuid::Uuid::new_v4().to_string().to_uppercase();
+            debug!("SystemConfiguration.plist is missing, creating one");
+
+            let sbuid = plist_macro::plist_value_to_xml_bytes(&plist_macro::plist!({
+                "SystemBUID": id
+            }));
+
+            store.save(&record_id, sbuid.clone()).await?;
+            sbuid
+        }
+        Err(e) => return Err(e),
+    };
+
+    let system_config = plist::from_bytes::<plist::Value>(&system_config)
+        .inspect_err(|e| error!(err = ?e, "Failed to read SystemConfiguration.plist"))?;
+
+    let buid = system_config
+        .into_dictionary()
+        .ok_or(RusbmuxError::UnexpectedPacket(
+            "Expected a packet with a dictionary plist payload".to_string(),
+        ))?
+        .remove("SystemBUID")
+        .ok_or(RusbmuxError::ValueNotFound(MissingFields::SystemBUID))?
+        .into_string()
+        .ok_or(RusbmuxError::InvalidData("SystemBUID is not a string"))?;
+
+    Ok(buid)
+}
@@ -2,67 +2,65 @@ use std::io::ErrorKind;
 
 use crate::{
     AsyncWriting,
+    audit::AuditEntry,
     error::RusbmuxError,
-    handler::{LOCKDOWN_PATH, ResultCode, send_result},
+    handler::{ResultCode, send_result},
+    pair_record,
     parser::usbmux::{UsbMuxMsgType, UsbMuxPacket, UsbMuxVersion},
 };
 use tokio::io::AsyncWriteExt;
-use tracing::{debug, error, trace, warn};
+use tracing::{debug, error, trace};
 
 pub async fn handle_read_pair_record(
     writer: &mut impl AsyncWriting,
     pair_record_id: String,
+    namespace: Option<&str>,
     tag: u32,
+    audit: AuditEntry,
 ) -> Result<(), RusbmuxError> {
-    if let Err(e) = read_pair_record(writer, pair_record_id, tag).await {
-        match e {
-            RusbmuxError::UnexpectedPacket(_) => {
-                send_result(writer, ResultCode::BadCommand, tag).await?;
-            }
+    if let Err(e) = read_pair_record(writer, pair_record_id, namespace, tag).await {
+        let code = match e {
+            RusbmuxError::UnexpectedPacket(_) => Some(ResultCode::BadCommand),
             RusbmuxError::IO(ref e)
                 if matches!(e.kind(), ErrorKind::PermissionDenied | ErrorKind::NotFound) =>
             {
-                send_result(writer, ResultCode::BadDeviceOrNoSuchFile, tag).await?;
+                Some(ResultCode::BadDeviceOrNoSuchFile)
             }
-            _ => {}
+            _ => None,
+        };
+
+        audit.record_error(code, &e);
+
+        if let Some(code) = code {
+            send_result(writer, code, tag).await?;
         }
 
         return Err(e);
     }
 
+    audit.record(ResultCode::OK);
+
     Ok(())
 }
 
 pub async fn read_pair_record(
     writer: &mut impl AsyncWriting,
     pair_record_id: String,
+    namespace: Option<&str>,
     tag: u32,
 ) -> Result<(), RusbmuxError> {
-    trace!(tag, pair_record_id, "Reading pair record");
-
-    if pair_record_id.contains('/')
-        || pair_record_id.contains('\\')
-        || pair_record_id.contains("..")
-    {
-        warn!(?pair_record_id, "malicious pair record id detected");
-        return Err(RusbmuxError::UnexpectedPacket(
-            "Given pair record id is malformed".into(),
-        ));
-    }
-
-    let path = format!("{LOCKDOWN_PATH}/{pair_record_id}.plist");
-
-    trace!(tag, path, "Reading pairing file");
+    trace!(tag, pair_record_id, namespace, "Reading pair record");
 
-    let pairing_file = tokio::fs::read(&path).await.inspect_err(|e| {
-        error!(
-            tag,
-            pair_record_id,
-            path,
-            err = ?e,
-            "Failed to read pairing file"
-        );
-    })?;
+    let pairing_file = pair_record::read_pair_record_in(namespace, &pair_record_id)
+        .await
+        .inspect_err(|e| {
+            error!(
+                tag,
+                pair_record_id,
+                err = ?e,
+                "Failed to read pairing file"
+            );
+        })?;
 
     trace!(
         tag,
@@ -5,8 +5,10 @@ use tracing::{debug, error, trace, warn};
 
 use crate::{
     AsyncWriting,
+    audit::AuditEntry,
     error::RusbmuxError,
-    handler::{LOCKDOWN_PATH, ResultCode, send_result},
+    handler::{ResultCode, send_result},
+    pair_record,
     parser::usbmux::{UsbMuxMsgType, UsbMuxPacket, UsbMuxVersion},
 };
 
@@ -15,26 +17,41 @@ pub async fn handle_save_pair_record(
     pair_record_id: String,
     pair_record_data: plist::Data,
     device_id: Option<u64>,
+    namespace: Option<&str>,
     tag: u32,
+    audit: AuditEntry,
 ) -> Result<(), RusbmuxError> {
-    match save_pair_record(writer, pair_record_id, pair_record_data, device_id, tag).await {
+    match save_pair_record(
+        writer,
+        pair_record_id,
+        pair_record_data,
+        device_id,
+        namespace,
+        tag,
+    )
+    .await
+    {
         Ok(()) => {
+            audit.record(ResultCode::OK);
             send_result(writer, ResultCode::OK, tag).await?;
         }
 
         Err(e) => {
-            match e {
-                RusbmuxError::UnexpectedPacket(_) => {
-                    send_result(writer, ResultCode::BadCommand, tag).await?;
-                }
-
+            let code = match e {
+                RusbmuxError::UnexpectedPacket(_) => Some(ResultCode::BadCommand),
+                RusbmuxError::InvalidPairRecord(_) => Some(ResultCode::InvalidInput),
                 RusbmuxError::IO(ref e)
                     if matches!(e.kind(), ErrorKind::PermissionDenied | ErrorKind::NotFound) =>
                 {
-                    send_result(writer, ResultCode::BadDeviceOrNoSuchFile, tag).await?;
+                    Some(ResultCode::BadDeviceOrNoSuchFile)
                 }
+                _ => None,
+            };
+
+            audit.record_error(code, &e);
 
-                _ => {}
+            if let Some(code) = code {
+                send_result(writer, code, tag).await?;
             }
 
             return Err(e);
@@ -49,6 +66,7 @@ pub async fn save_pair_record(
     pair_record_id: String,
     pair_record_data: plist::Data,
     device_id: Option<u64>,
+    namespace: Option<&str>,
     tag: u32,
 ) -> Result<(), RusbmuxError> {
     let pair_record_data: Vec<u8> = pair_record_data.into();
@@ -59,33 +77,23 @@ pub async fn save_pair_record(
         "Received pair record data"
     );
 
-    let pair_record_data = plist::Value::Data(pair_record_data);
-
-    if pair_record_id.contains('/')
-        || pair_record_id.contains('\\')
-        || pair_record_id.contains("..")
-    {
-        warn!(?pair_record_id, "malicious pair record id detected");
-        return Err(RusbmuxError::UnexpectedPacket(
-            "Given pair record id is malformed".into(),
-        ));
-    }
-
-    let path = format!("{LOCKDOWN_PATH}/{pair_record_id}.plist");
+    let pair_record = pair_record::validate_pair_record(&pair_record_data)
+        .inspect_err(|e| warn!(tag, pair_record_id, err = ?e, "Rejected the pair record"))?;
 
-    trace!(tag, pair_record_id, path, "Writing pair record to disk");
+    trace!(tag, pair_record_id, namespace, "Saving pair record");
 
-    // TODO: permissions
-    tokio::fs::write(
-        &path,
-        plist_macro::plist_value_to_xml_bytes(&pair_record_data),
+    // the file is the record it self (as an xml plist), the same as usbmuxd, so it can be shared
+    // with libimobiledevice
+    pair_record::save_pair_record_in(
+        namespace,
+        &pair_record_id,
+        plist_macro::plist_value_to_xml_bytes(&plist::Value::Dictionary(pair_record)),
     )
     .await
     .inspect_err(|e| {
         error!(
             tag,
             pair_record_id,
-            path,
             err = ?e,
             "Failed to write pair record file"
         )
@@ -0,0 +1,150 @@
+use crate::{
+    AsyncWriting,
+    daemon::limits,
+    device::{
+        ConnectionType, Device,
+        status::{DeviceProperties, WifiConnections},
+    },
+    error::RusbmuxError,
+    handler::visibility::Visibility,
+    lease::{self, LeaseInfo},
+    pair_record::stale_pair_records,
+    parser::usbmux::{UsbMuxMsgType, UsbMuxPacket, UsbMuxVersion},
+    watcher::CONNECTED_DEVICES,
+};
+
+use tokio::io::AsyncWriteExt;
+use tracing::{debug, error, trace};
+
+pub fn device_status_plist(device: &Device) -> plist::Value {
+    let status = device.status();
+
+    let connection_type = match device.connection_type() {
+        ConnectionType::Usb => "USB",
+        ConnectionType::Network => "Network",
+    };
+
+    let wifi_connections = status.wifi_connections.map(|w| match w {
+        WifiConnections::Enabled => "Enabled".to_string(),
+        WifiConnections::AlreadyEnabled => "AlreadyEnabled".to_string(),
+        WifiConnections::Failed(e) => format!("Failed: {e}"),
+    });
+
+    plist_macro::plist!({
+        "DeviceID": device.id(),
+        "SerialNumber": device.serial_number().into_owned(),
+        "ConnectionType": connection_type,
+        "TrustState":? status.preflight.map(|p| p.trust.as_str()),
+        "WiFiConnections":? wifi_connections,
+        "Properties":? status.properties.as_ref().map(properties_plist),
+        "PairRecordStale":? status.pair_record_stale.then_some(true),
+    })
+}
+
+pub fn properties_plist(properties: &DeviceProperties) -> plist::Value {
+    plist_macro::plist!({
+        "DeviceName":? properties.device_name.clone(),
+        "ProductType":? properties.product_type.clone(),
+        "ProductVersion":? properties.product_version.clone(),
+        "BuildVersion":? properties.build_version.clone(),
+        "DeviceClass":? properties.device_class.clone(),
+        "HardwareModel":? properties.hardware_model.clone(),
+    })
+}
+
+pub fn limits_plist() -> plist::Value {
+    let mut limits = plist::Dictionary::new();
+
+    for usage in limits::usage() {
+        limits.insert(
+            usage.limit.as_str().to_string(),
+            plist_macro::plist!({
+                "Max":? usage.max,
+                "Current": usage.current,
+                "Refused": usage.refused,
+            }),
+        );
+    }
+
+    plist::Value::Dictionary(limits)
+}
+
+fn lease_plist(info: &LeaseInfo) -> plist::Value {
+    plist_macro::plist!({
+        "Mode":? info.mode.map(|m| m.to_string()),
+        "ProcessID":? info.owner.pid,
+        "UserID":? info.owner.uid,
+        "ProgName":? info.owner.prog_name.clone(),
+        "Since": info.since,
+    })
+}
+
+/// who holds a lease on which device, and who is waiting for one (or to connect)
+pub fn leases_plist(visibility: &Visibility) -> plist::Value {
+    let leases = lease::leases()
+        .iter()
+        .filter(|device| visibility.can_see_udid(&device.udid))
+        .map(|device| {
+            plist_macro::plist!({
+                "SerialNumber": device.udid.clone(),
+                "Holders": device.holders.iter().map(lease_plist).collect::<Vec<_>>(),
+                "Waiters": device.waiters.iter().map(lease_plist).collect::<Vec<_>>(),
+            })
+        })
+        .collect::<Vec<_>>();
+
+    plist::Value::Array(leases)
+}
+
+/// only what the listener can see is in it
+pub fn status_plist(visibility: &Visibility) -> plist::Value {
+    let devices = CONNECTED_DEVICES
+        .iter()
+        .filter(|device| visibility.can_see(device))
+        .map(|device| device_status_plist(&device))
+        .collect::<Vec<_>>();
+
+    let stale_pair_records = stale_pair_records()
+        .into_iter()
+        .filter(|(udid, _)| visibility.can_see_udid(udid))
+        .map(|(udid, reason)| {
+            plist_macro::plist!({
+                "SerialNumber": udid,
+                "Reason": reason,
+            })
+        })
+        .collect::<Vec<_>>();
+
+    plist_macro::plist!({
+        "Devices": devices,
+        "StalePairRecords": stale_pair_records,
+        "Limits": limits_plist(),
+        "Leases": leases_plist(visibility),
+    })
+}
+
+pub async fn handle_status(
+    writer: &mut impl AsyncWriting,
+    tag: u32,
+    visibility: &Visibility,
+) -> Result<(), RusbmuxError> {
+    let status_xml = plist_macro::plist_value_to_xml_bytes(&status_plist(visibility));
+
+    let usbmux_packet = UsbMuxPacket::encode_from(
+        status_xml,
+        UsbMuxVersion::Plist,
+        UsbMuxMsgType::MessagePlist,
+        tag,
+    );
+
+    trace!(tag, "Sending status response");
+    writer.write_all(&usbmux_packet).await.inspect_err(|e| {
+        if !crate::utils::is_disconnect_io(e) {
+            error!(tag, err = ?e, "Failed to write status packet");
+        }
+    })?;
+
+    debug!(tag, "Status sent");
+
+    Ok(())
+}
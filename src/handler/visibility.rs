@@ -0,0 +1,159 @@
+use std::borrow::Cow;
+
+use serde::Deserialize;
+
+use crate::{
+    device::{ConnectionType, Device},
+    handler::{ResultCode, access::AccessRule},
+    parser::usbmux::UsbMuxRequest,
+    utils::matches_pattern,
+    watcher::CONNECTED_DEVICES,
+};
+
+/// which devices the clients of a listener can see, and whose pair records and `SystemBUID` they
+/// get, so every container (or user) on the host can have it's own socket with it's own devices
+///
+/// every filter that is set has to match, a listener without any sees every device like usbmuxd
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct Visibility {
+    /// the only devices (by udid) it can see
+    pub devices: Option<Vec<String>>,
+
+    /// patterns for the usb port the device is plugged into, either it's bus path (e.g. `1-2.*`)
+    /// or it's `LocationID` in hex (e.g. `0x0001*`), only the usb devices can match it
+    pub locations: Option<Vec<String>>,
+
+    pub connection_type: Option<ConnectionType>,
+
+    /// handed out by `ReadBUID` instead of the host's, so the devices don't see the clients of
+    /// different listeners as the same host
+    pub system_buid: Option<String>,
+
+    /// the pair records are kept apart from the rest under this name (a folder in the lockdown
+    /// folder), the daemon it self keeps using the ones outside of it
+    pub pair_records: Option<String>,
+}
+
+impl Visibility {
+    /// the listeners without a filter
+    pub const ALL: Self = Self {
+        devices: None,
+        locations: None,
+        connection_type: None,
+        system_buid: None,
+        pair_records: None,
+    };
+
+    /// for the listeners that have no entry when others are filtered
+    pub const NONE: Self = Self {
+        devices: Some(Vec::new()),
+        locations: None,
+        connection_type: None,
+        system_buid: None,
+        pair_records: None,
+    };
+
+    /// narrowed down to the devices the access rule lets the client use, so the lists don't show
+    /// it the others either
+    #[must_use]
+    pub fn limited_to(&self, rule: &AccessRule) -> Cow<'_, Self> {
+        let Some(allowed) = &rule.devices else {
+            return Cow::Borrowed(self);
+        };
+
+        let devices = match &self.devices {
+            Some(devices) => devices
+                .iter()
+                .filter(|d| allowed.contains(d))
+                .cloned()
+                .collect(),
+            None => allowed.clone(),
+        };
+
+        Cow::Owned(Self {
+            devices: Some(devices),
+            ..self.clone()
+        })
+    }
+
+    fn can_see_location(&self, device: &Device) -> bool {
+        let Some(patterns) = &self.locations else {
+            return true;
+        };
+
+        let Some(usb) = device.as_usb() else {
+            return false;
+        };
+
+        let bus_path = usb.info.bus_path();
+        let location_id = format!("0x{:08x}", usb.info.location_id());
+
+        patterns.iter().any(|pattern| {
+            bus_path
+                .as_deref()
+                .is_some_and(|path| matches_pattern(pattern, path))
+                || matches_pattern(&pattern.to_lowercase(), &location_id)
+        })
+    }
+
+    #[must_use]
+    pub fn can_see(&self, device: &Device) -> bool {
+        self.devices
+            .as_ref()
+            .is_none_or(|devices| devices.iter().any(|d| *d == device.serial_number()))
+            && self
+                .connection_type
+                .is_none_or(|kind| kind == device.connection_type())
+            && self.can_see_location(device)
+    }
+
+    /// whether it can see the device with this udid, for the requests that don't need it to be
+    /// connected (like the pair records)
+    ///
+    /// a device that isn't connected can't match the location or the connection type, so only the
+    /// udids are checked if those aren't set
+    #[must_use]
+    pub fn can_see_udid(&self, udid: &str) -> bool {
+        if !self
+            .devices
+            .as_ref()
+            .is_none_or(|devices| devices.iter().any(|d| d == udid))
+        {
+            return false;
+        }
+
+        if self.locations.is_none() && self.connection_type.is_none() {
+            return true;
+        }
+
+        CONNECTED_DEVICES
+            .iter()
+            .any(|device| device.serial_number() == udid && self.can_see(&device))
+    }
+
+    /// the result code to answer the request with, if it's about a device that can't be seen
+    ///
+    /// it's the same as the device not being there at all
+    #[must_use]
+    pub fn check(&self, request: &UsbMuxRequest) -> Option<ResultCode> {
+        let visible = match request {
+            // a device that isn't there is left for `Connect` to report
+            UsbMuxRequest::Connect { device_id, .. } => CONNECTED_DEVICES
+                .get(device_id)
+                .is_none_or(|device| self.can_see(&device)),
+
+            UsbMuxRequest::ReadPairRecord { pair_record_id, .. }
+            | UsbMuxRequest::SavePairRecord { pair_record_id, .. }
+            | UsbMuxRequest::DeletePairRecord { pair_record_id, .. } => {
+                self.can_see_udid(pair_record_id)
+            }
+
+            UsbMuxRequest::AcquireLease { serial_number, .. } => self.can_see_udid(serial_number),
+
+            _ => true,
+        };
+
+        (!visible).then_some(ResultCode::BadDeviceOrNoSuchFile)
+    }
+}
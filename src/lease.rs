@@ -0,0 +1,820 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt,
+    str::FromStr,
+    sync::{
+        LazyLock, Mutex, MutexGuard, PoisonError,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::Deserialize;
+use tokio::sync::oneshot;
+use tracing::{debug, info};
+
+use crate::{error::RusbmuxError, handler::access::Peer};
+
+/// every device (by udid) that has a lease, or someone waiting for one
+///
+/// they're kept by udid and not by device id, so a lease lives through the device reconnecting
+static LEASES: LazyLock<Mutex<HashMap<String, DeviceLeases>>> = LazyLock::new(Default::default);
+
+/// only used to tell the holders and waiters apart, it's never handed out
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+fn leases_map() -> MutexGuard<'static, HashMap<String, DeviceLeases>> {
+    LEASES.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LeaseMode {
+    /// no one else gets a lease, and only the holder can connect to the device
+    #[default]
+    Exclusive,
+
+    /// other shared leases are fine, it only keeps the exclusive ones out
+    Shared,
+}
+
+impl fmt::Display for LeaseMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Exclusive => "Exclusive",
+            Self::Shared => "Shared",
+        })
+    }
+}
+
+impl FromStr for LeaseMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "Exclusive" => Ok(Self::Exclusive),
+            "Shared" => Ok(Self::Shared),
+            _ => Err(format!(
+                "unknown lease mode `{s}`, expected Exclusive or Shared"
+            )),
+        }
+    }
+}
+
+/// what a client gets for a lease, to use it with `Connect` or to release it
+///
+/// it's random so it can't be guessed, and it's only ever sent to the client that took the lease
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+#[serde(try_from = "String")]
+pub struct LeaseToken(u128);
+
+impl LeaseToken {
+    fn generate() -> Result<Self, RusbmuxError> {
+        let mut bytes = [0; 16];
+
+        SystemRandom::new()
+            .fill(&mut bytes)
+            .map_err(|_| RusbmuxError::Random("a lease token"))?;
+
+        Ok(Self(u128::from_be_bytes(bytes)))
+    }
+}
+
+impl fmt::Display for LeaseToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:032x}", self.0)
+    }
+}
+
+// it's a secret, so it's kept out of the logs
+impl fmt::Debug for LeaseToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("LeaseToken(..)")
+    }
+}
+
+impl FromStr for LeaseToken {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.len() != 32 {
+            return Err(format!(
+                "a lease id is 32 hex digits, got {} characters",
+                s.len()
+            ));
+        }
+
+        u128::from_str_radix(s, 16)
+            .map(Self)
+            .map_err(|e| format!("invalid lease id: {e}"))
+    }
+}
+
+impl TryFrom<String> for LeaseToken {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+/// who holds or waits for a lease, it's only for the status and to let the holder's own
+/// connections through
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LeaseOwner {
+    pub pid: Option<i32>,
+    pub uid: Option<u32>,
+    pub prog_name: Option<String>,
+}
+
+impl LeaseOwner {
+    #[must_use]
+    pub const fn new(peer: &Peer, prog_name: Option<String>) -> Self {
+        Self {
+            pid: peer.pid,
+            uid: peer.uid,
+            prog_name,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Holder {
+    id: u64,
+    token: LeaseToken,
+    mode: LeaseMode,
+    owner: LeaseOwner,
+    since: SystemTime,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Want {
+    Lease(LeaseMode),
+
+    /// a `Connect` waiting for the exclusive lease to go away, it doesn't take a lease it self
+    Connect,
+}
+
+#[derive(Debug)]
+struct Waiter {
+    id: u64,
+    token: LeaseToken,
+    want: Want,
+    owner: LeaseOwner,
+    since: SystemTime,
+    granted: oneshot::Sender<()>,
+}
+
+#[derive(Debug, Default)]
+struct DeviceLeases {
+    holders: Vec<Holder>,
+
+    /// served in order, a waiter is never overtaken by a later one
+    waiters: VecDeque<Waiter>,
+}
+
+impl DeviceLeases {
+    fn exclusive(&self) -> Option<&Holder> {
+        self.holders.iter().find(|h| h.mode == LeaseMode::Exclusive)
+    }
+
+    fn can_take(&self, want: Want) -> bool {
+        match want {
+            Want::Lease(LeaseMode::Exclusive) => self.holders.is_empty(),
+            Want::Lease(LeaseMode::Shared) | Want::Connect => self.exclusive().is_none(),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.holders.is_empty() && self.waiters.is_empty()
+    }
+
+    /// hands out what it can to the waiters at the front
+    fn grant(&mut self, udid: &str) {
+        while let Some(waiter) = self.waiters.front() {
+            if !self.can_take(waiter.want) {
+                break;
+            }
+
+            let Some(waiter) = self.waiters.pop_front() else {
+                break;
+            };
+
+            if let Want::Lease(mode) = waiter.want {
+                info!(
+                    udid,
+                    id = waiter.id,
+                    %mode,
+                    pid = waiter.owner.pid,
+                    "Granted a lease to a waiter"
+                );
+
+                self.holders.push(Holder {
+                    id: waiter.id,
+                    token: waiter.token,
+                    mode,
+                    owner: waiter.owner,
+                    since: SystemTime::now(),
+                });
+            }
+
+            // a waiter that went away takes it's lease back once it's `Waiting` is dropped
+            let _ = waiter.granted.send(());
+        }
+    }
+}
+
+/// a lease on a device, it's released once it's dropped
+#[derive(Debug)]
+pub struct Lease {
+    id: u64,
+    token: LeaseToken,
+    udid: String,
+    mode: LeaseMode,
+}
+
+impl Lease {
+    #[must_use]
+    pub const fn token(&self) -> LeaseToken {
+        self.token
+    }
+
+    #[must_use]
+    pub fn udid(&self) -> &str {
+        &self.udid
+    }
+
+    #[must_use]
+    pub const fn mode(&self) -> LeaseMode {
+        self.mode
+    }
+}
+
+impl Drop for Lease {
+    fn drop(&mut self) {
+        release(&self.udid, self.id);
+    }
+}
+
+fn release(udid: &str, id: u64) {
+    let mut map = leases_map();
+
+    let Some(device) = map.get_mut(udid) else {
+        return;
+    };
+
+    let before = device.holders.len();
+    device.holders.retain(|h| h.id != id);
+
+    if device.holders.len() != before {
+        info!(udid, id, "Released a lease");
+    }
+
+    device.grant(udid);
+
+    if device.is_empty() {
+        map.remove(udid);
+    }
+}
+
+/// a place in the queue, it's taken out of it once it's dropped
+struct Waiting {
+    id: u64,
+    token: LeaseToken,
+    udid: String,
+    done: bool,
+}
+
+impl Drop for Waiting {
+    fn drop(&mut self) {
+        if self.done {
+            return;
+        }
+
+        let mut map = leases_map();
+
+        let Some(device) = map.get_mut(&self.udid) else {
+            return;
+        };
+
+        device.waiters.retain(|w| w.id != self.id);
+
+        // it was granted, but no one was there to take it
+        if device.holders.iter().any(|h| h.id == self.id) {
+            drop(map);
+            release(&self.udid, self.id);
+            return;
+        }
+
+        debug!(
+            udid = self.udid,
+            id = self.id,
+            "Gave up waiting for a lease"
+        );
+
+        device.grant(&self.udid);
+
+        if device.is_empty() {
+            map.remove(&self.udid);
+        }
+    }
+}
+
+/// a lease (or a `Connect`) that was let through
+#[derive(Debug, Clone, Copy)]
+struct Ticket {
+    id: u64,
+    token: LeaseToken,
+}
+
+/// takes it if nothing is in the way, the owner is given back otherwise
+///
+/// the ones already waiting go first
+fn take_now(
+    map: &mut HashMap<String, DeviceLeases>,
+    udid: &str,
+    want: Want,
+    owner: LeaseOwner,
+    ticket: Ticket,
+) -> Result<(), LeaseOwner> {
+    let device = map.entry(udid.to_string()).or_default();
+
+    if !device.waiters.is_empty() || !device.can_take(want) {
+        return Err(owner);
+    }
+
+    if let Want::Lease(mode) = want {
+        info!(udid, id = ticket.id, %mode, pid = owner.pid, "Took a lease");
+
+        device.holders.push(Holder {
+            id: ticket.id,
+            token: ticket.token,
+            mode,
+            owner,
+            since: SystemTime::now(),
+        });
+    } else if device.is_empty() {
+        map.remove(udid);
+    }
+
+    Ok(())
+}
+
+fn new_ticket() -> Result<Ticket, RusbmuxError> {
+    Ok(Ticket {
+        id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+        token: LeaseToken::generate()?,
+    })
+}
+
+/// takes it right away, or fails if something is in the way
+fn try_take(udid: &str, want: Want, owner: LeaseOwner) -> Result<Ticket, RusbmuxError> {
+    let ticket = new_ticket()?;
+    let mut map = leases_map();
+
+    if take_now(&mut map, udid, want, owner, ticket).is_ok() {
+        return Ok(ticket);
+    }
+
+    if map.get(udid).is_some_and(DeviceLeases::is_empty) {
+        map.remove(udid);
+    }
+
+    Err(RusbmuxError::DeviceLeased(udid.to_string()))
+}
+
+/// either takes it right away, or gives the place in the queue
+fn take_or_queue(
+    udid: &str,
+    want: Want,
+    owner: LeaseOwner,
+) -> Result<Result<Ticket, (Waiting, oneshot::Receiver<()>)>, RusbmuxError> {
+    let ticket = new_ticket()?;
+    let mut map = leases_map();
+
+    let owner = match take_now(&mut map, udid, want, owner, ticket) {
+        Ok(()) => return Ok(Ok(ticket)),
+        Err(owner) => owner,
+    };
+
+    let device = map.entry(udid.to_string()).or_default();
+    let (tx, rx) = oneshot::channel();
+
+    device.waiters.push_back(Waiter {
+        id: ticket.id,
+        token: ticket.token,
+        want,
+        owner,
+        since: SystemTime::now(),
+        granted: tx,
+    });
+
+    debug!(
+        udid,
+        id = ticket.id,
+        position = device.waiters.len(),
+        "Waiting for a lease"
+    );
+
+    Ok(Err((
+        Waiting {
+            id: ticket.id,
+            token: ticket.token,
+            udid: udid.to_string(),
+            done: false,
+        },
+        rx,
+    )))
+}
+
+async fn wait(
+    mut waiting: Waiting,
+    granted: oneshot::Receiver<()>,
+) -> Result<Ticket, RusbmuxError> {
+    granted
+        .await
+        .map_err(|_| RusbmuxError::Channel("the lease queue went away".to_string()))?;
+
+    waiting.done = true;
+
+    Ok(Ticket {
+        id: waiting.id,
+        token: waiting.token,
+    })
+}
+
+impl Ticket {
+    fn into_lease(self, udid: &str, mode: LeaseMode) -> Lease {
+        Lease {
+            id: self.id,
+            token: self.token,
+            udid: udid.to_string(),
+            mode,
+        }
+    }
+}
+
+/// takes a lease on the device, or fails if it conflicts with one that is already held (or
+/// waited for)
+pub fn try_acquire(udid: &str, mode: LeaseMode, owner: LeaseOwner) -> Result<Lease, RusbmuxError> {
+    Ok(try_take(udid, Want::Lease(mode), owner)?.into_lease(udid, mode))
+}
+
+/// takes a lease on the device, waiting behind everyone that asked for one first
+///
+/// it's taken out of the queue if the future is dropped
+pub async fn acquire(
+    udid: &str,
+    mode: LeaseMode,
+    owner: LeaseOwner,
+) -> Result<Lease, RusbmuxError> {
+    let ticket = match take_or_queue(udid, Want::Lease(mode), owner)? {
+        Ok(ticket) => ticket,
+        Err((waiting, granted)) => wait(waiting, granted).await?,
+    };
+
+    Ok(ticket.into_lease(udid, mode))
+}
+
+/// whether a new connection to the device can be made
+///
+/// only an exclusive lease stops it, unless it's made by the process that holds it, or with the
+/// lease's token by the same user that holds it
+///
+/// the clients of a tcp listener have no user, so the token is all they have
+#[must_use]
+pub fn can_connect(udid: &str, token: Option<LeaseToken>, peer: &Peer) -> bool {
+    let map = leases_map();
+
+    let Some(holder) = map.get(udid).and_then(DeviceLeases::exclusive) else {
+        return true;
+    };
+
+    let same_process = peer.pid.is_some() && holder.owner.pid == peer.pid;
+    let with_token = token == Some(holder.token) && holder.owner.uid == peer.uid;
+
+    same_process || with_token
+}
+
+/// waits until a new connection to the device can be made, in line with the ones waiting for a
+/// lease
+pub async fn wait_to_connect(udid: &str, owner: LeaseOwner) -> Result<(), RusbmuxError> {
+    if let Err((waiting, granted)) = take_or_queue(udid, Want::Connect, owner)? {
+        wait(waiting, granted).await?;
+    }
+
+    Ok(())
+}
+
+/// what is held or waited for on a device, the token isn't part of it
+#[derive(Debug, Clone)]
+pub struct LeaseInfo {
+    /// none for a `Connect` that is waiting
+    pub mode: Option<LeaseMode>,
+
+    pub owner: LeaseOwner,
+
+    /// seconds since the unix epoch, since it was held or since it started waiting
+    pub since: f64,
+}
+
+#[derive(Debug, Clone)]
+pub struct DeviceLeaseInfo {
+    pub udid: String,
+    pub holders: Vec<LeaseInfo>,
+
+    /// in the order they're served
+    pub waiters: Vec<LeaseInfo>,
+}
+
+fn secs(time: SystemTime) -> f64 {
+    time.duration_since(UNIX_EPOCH)
+        .map_or(0.0, |d| d.as_secs_f64())
+}
+
+/// every device with a lease or a waiter
+#[must_use]
+pub fn leases() -> Vec<DeviceLeaseInfo> {
+    leases_map()
+        .iter()
+        .map(|(udid, device)| DeviceLeaseInfo {
+            udid: udid.clone(),
+            holders: device
+                .holders
+                .iter()
+                .map(|h| LeaseInfo {
+                    mode: Some(h.mode),
+                    owner: h.owner.clone(),
+                    since: secs(h.since),
+                })
+                .collect(),
+            waiters: device
+                .waiters
+                .iter()
+                .map(|w| LeaseInfo {
+                    mode: match w.want {
+                        Want::Lease(mode) => Some(mode),
+                        Want::Connect => None,
+                    },
+                    owner: w.owner.clone(),
+                    since: secs(w.since),
+                })
+                .collect(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn peer(uid: u32, pid: i32) -> Peer {
+        Peer {
+            uid: Some(uid),
+            gid: Some(uid),
+            pid: Some(pid),
+        }
+    }
+
+    fn owner(uid: u32, pid: i32) -> LeaseOwner {
+        LeaseOwner::new(&peer(uid, pid), None)
+    }
+
+    /// a place in the queue, for the tests that don't need a runtime
+    fn queue(udid: &str, mode: LeaseMode) -> (Waiting, oneshot::Receiver<()>) {
+        match take_or_queue(udid, Want::Lease(mode), owner(501, 1)) {
+            Ok(Err(waiting)) => waiting,
+            _ => panic!("expected to be queued"),
+        }
+    }
+
+    fn granted(waiting: &mut (Waiting, oneshot::Receiver<()>)) -> bool {
+        waiting.1.try_recv().is_ok()
+    }
+
+    /// takes the lease the waiter was granted, like `acquire` does
+    fn collect(
+        mut waiting: (Waiting, oneshot::Receiver<()>),
+        udid: &str,
+        mode: LeaseMode,
+    ) -> Lease {
+        waiting.0.done = true;
+
+        Ticket {
+            id: waiting.0.id,
+            token: waiting.0.token,
+        }
+        .into_lease(udid, mode)
+    }
+
+    fn is_gone(udid: &str) -> bool {
+        !leases_map().contains_key(udid)
+    }
+
+    #[test]
+    fn exclusive_conflicts_with_everything() {
+        let udid = "exclusive-conflicts";
+
+        let lease = try_acquire(udid, LeaseMode::Exclusive, owner(501, 1)).unwrap();
+
+        assert!(try_acquire(udid, LeaseMode::Exclusive, owner(502, 2)).is_err());
+        assert!(try_acquire(udid, LeaseMode::Shared, owner(502, 2)).is_err());
+
+        drop(lease);
+        assert!(is_gone(udid));
+    }
+
+    #[test]
+    fn shared_only_conflicts_with_exclusive() {
+        let udid = "shared-conflicts";
+
+        let first = try_acquire(udid, LeaseMode::Shared, owner(501, 1)).unwrap();
+        let second = try_acquire(udid, LeaseMode::Shared, owner(502, 2)).unwrap();
+
+        assert!(try_acquire(udid, LeaseMode::Exclusive, owner(503, 3)).is_err());
+
+        drop(first);
+        assert!(try_acquire(udid, LeaseMode::Exclusive, owner(503, 3)).is_err());
+
+        drop(second);
+        drop(try_acquire(udid, LeaseMode::Exclusive, owner(503, 3)).unwrap());
+        assert!(is_gone(udid));
+    }
+
+    #[test]
+    fn waiters_are_not_overtaken() {
+        let udid = "not-overtaken";
+
+        let shared = try_acquire(udid, LeaseMode::Shared, owner(501, 1)).unwrap();
+        let mut exclusive = queue(udid, LeaseMode::Exclusive);
+
+        // it would fit next to the held one, but the exclusive one asked first
+        assert!(try_acquire(udid, LeaseMode::Shared, owner(502, 2)).is_err());
+        let mut later = queue(udid, LeaseMode::Shared);
+
+        assert!(!granted(&mut exclusive));
+
+        drop(shared);
+        assert!(granted(&mut exclusive));
+        assert!(!granted(&mut later));
+
+        drop(collect(exclusive, udid, LeaseMode::Exclusive));
+        assert!(granted(&mut later));
+
+        drop(collect(later, udid, LeaseMode::Shared));
+        assert!(is_gone(udid));
+    }
+
+    #[test]
+    fn shared_waiters_are_granted_together() {
+        let udid = "shared-together";
+
+        let exclusive = try_acquire(udid, LeaseMode::Exclusive, owner(501, 1)).unwrap();
+        let mut first = queue(udid, LeaseMode::Shared);
+        let mut second = queue(udid, LeaseMode::Shared);
+        let mut last = queue(udid, LeaseMode::Exclusive);
+
+        drop(exclusive);
+        assert!(granted(&mut first));
+        assert!(granted(&mut second));
+        assert!(!granted(&mut last));
+
+        drop(collect(first, udid, LeaseMode::Shared));
+        assert!(!granted(&mut last));
+
+        drop(collect(second, udid, LeaseMode::Shared));
+        assert!(granted(&mut last));
+
+        drop(collect(last, udid, LeaseMode::Exclusive));
+        assert!(is_gone(udid));
+    }
+
+    #[test]
+    fn dropped_waiter_leaves_the_queue() {
+        let udid = "dropped-waiter";
+
+        let exclusive = try_acquire(udid, LeaseMode::Exclusive, owner(501, 1)).unwrap();
+        let gave_up = queue(udid, LeaseMode::Exclusive);
+        let mut next = queue(udid, LeaseMode::Shared);
+
+        drop(gave_up);
+        let device = leases().into_iter().find(|d| d.udid == udid).unwrap();
+        assert_eq!(device.waiters.len(), 1);
+
+        drop(exclusive);
+        assert!(granted(&mut next));
+
+        drop(collect(next, udid, LeaseMode::Shared));
+        assert!(is_gone(udid));
+    }
+
+    #[test]
+    fn dropped_waiter_at_the_front_lets_the_next_in() {
+        let udid = "dropped-front";
+
+        let shared = try_acquire(udid, LeaseMode::Shared, owner(501, 1)).unwrap();
+        let blocking = queue(udid, LeaseMode::Exclusive);
+        let mut behind = queue(udid, LeaseMode::Shared);
+
+        assert!(!granted(&mut behind));
+
+        // the exclusive one was the only thing keeping it out
+        drop(blocking);
+        assert!(granted(&mut behind));
+
+        drop(collect(behind, udid, LeaseMode::Shared));
+        drop(shared);
+        assert!(is_gone(udid));
+    }
+
+    #[test]
+    fn uncollected_grant_is_released() {
+        let udid = "uncollected";
+
+        let exclusive = try_acquire(udid, LeaseMode::Exclusive, owner(501, 1)).unwrap();
+        let mut waiting = queue(udid, LeaseMode::Exclusive);
+
+        drop(exclusive);
+        assert!(granted(&mut waiting));
+
+        // granted, but the client went away before taking it
+        drop(waiting);
+        assert!(is_gone(udid));
+    }
+
+    #[tokio::test]
+    async fn acquire_waits_for_the_release() {
+        let udid = "acquire-waits";
+
+        let exclusive = try_acquire(udid, LeaseMode::Exclusive, owner(501, 1)).unwrap();
+
+        let waiter = tokio::spawn(acquire(udid, LeaseMode::Shared, owner(502, 2)));
+        tokio::task::yield_now().await;
+        assert!(!waiter.is_finished());
+
+        drop(exclusive);
+
+        let lease = waiter.await.unwrap().unwrap();
+        assert_eq!(lease.mode(), LeaseMode::Shared);
+
+        drop(lease);
+        assert!(is_gone(udid));
+    }
+
+    #[test]
+    fn connect_without_an_exclusive_lease() {
+        let udid = "connect-free";
+
+        assert!(can_connect(udid, None, &peer(502, 2)));
+
+        let _shared = try_acquire(udid, LeaseMode::Shared, owner(501, 1)).unwrap();
+        assert!(can_connect(udid, None, &peer(502, 2)));
+    }
+
+    #[test]
+    fn connect_with_an_exclusive_lease() {
+        let udid = "connect-exclusive";
+
+        let lease = try_acquire(udid, LeaseMode::Exclusive, owner(501, 1)).unwrap();
+        let token = lease.token();
+
+        // someone else
+        assert!(!can_connect(udid, None, &peer(502, 2)));
+
+        // the holder's process, with or without the token
+        assert!(can_connect(udid, None, &peer(501, 1)));
+
+        // another process of the holder's user, with the token
+        assert!(can_connect(udid, Some(token), &peer(501, 3)));
+        assert!(!can_connect(udid, None, &peer(501, 3)));
+
+        // the token of another user is no use
+        assert!(!can_connect(udid, Some(token), &peer(502, 2)));
+
+        let other = try_acquire(
+            "connect-exclusive-other",
+            LeaseMode::Exclusive,
+            owner(501, 1),
+        )
+        .unwrap();
+        assert!(!can_connect(udid, Some(other.token()), &peer(501, 3)));
+
+        drop(lease);
+        assert!(can_connect(udid, None, &peer(502, 2)));
+    }
+
+    #[test]
+    fn tokens_are_random_and_round_trip() {
+        let first = try_acquire("token-first", LeaseMode::Shared, owner(501, 1)).unwrap();
+        let second = try_acquire("token-second", LeaseMode::Shared, owner(501, 1)).unwrap();
+
+        assert_ne!(first.token(), second.token());
+
+        let text = first.token().to_string();
+        assert_eq!(text.len(), 32);
+        assert_eq!(text.parse::<LeaseToken>().unwrap(), first.token());
+
+        assert!("1".parse::<LeaseToken>().is_err());
+        assert!("z".repeat(32).parse::<LeaseToken>().is_err());
+    }
+}
@@ -1,10 +1,14 @@
 use tokio::io::{AsyncRead, AsyncWrite};
 
+pub mod audit;
+pub mod config;
 pub mod conn;
 pub mod daemon;
 pub mod device;
 pub mod error;
 pub mod handler;
+pub mod lease;
+pub mod pair_record;
 pub mod parser;
 pub mod usb_backend;
 pub mod utils;
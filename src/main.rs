@@ -1,8 +1,64 @@
-#[tokio::main]
-async fn main() {
-    tracing_subscriber::fmt::init();
+use clap::Parser;
+use rusbmux::config::set_config;
 
-    if let Err(e) = rusbmux::daemon::run().await {
+mod cli;
+
+fn main() {
+    let cli = cli::Cli::parse();
+
+    #[cfg(unix)]
+    if cli.daemonize()
+        && let Err(e) = cli::daemonize()
+    {
+        eprintln!("Failed to daemonize: {e}");
+        std::process::exit(1);
+    }
+
+    cli.init_tracing();
+
+    let res = (|| {
+        let (config_path, file) = cli.load_config()?;
+        let lockdown_dir = cli.lockdown_dir(&file);
+
+        match cli.command {
+            Some(ref command) => runtime().block_on(cli::run_command(command, &lockdown_dir)),
+            None => {
+                let options = cli.daemon_options(config_path, &file);
+
+                // held until it exits, it's taken before the sandbox, it can't make files after
+                #[cfg(unix)]
+                let _instance = rusbmux::daemon::Instance::claim(&options)?;
+
+                set_config(file.to_config());
+
+                // the setup gets it's own runtime, it's threads are gone before the one that
+                // serves is built, so every thread that one spawns is in the sandbox
+                let prepared = setup_runtime().block_on(rusbmux::daemon::prepare(options))?;
+                runtime().block_on(prepared.serve())
+            }
+        }
+    })();
+
+    if let Err(e) = res {
         tracing::error!(err = ?e, "Daemon failed");
+        std::process::exit(1);
+    }
+}
+
+fn runtime() -> tokio::runtime::Runtime {
+    build_runtime(tokio::runtime::Builder::new_multi_thread())
+}
+
+fn setup_runtime() -> tokio::runtime::Runtime {
+    build_runtime(tokio::runtime::Builder::new_current_thread())
+}
+
+fn build_runtime(mut builder: tokio::runtime::Builder) -> tokio::runtime::Runtime {
+    match builder.enable_all().build() {
+        Ok(runtime) => runtime,
+        Err(e) => {
+            tracing::error!(err = ?e, "Failed to start the runtime");
+            std::process::exit(1);
+        }
     }
 }
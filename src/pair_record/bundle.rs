@@ -0,0 +1,459 @@
+use std::{collections::BTreeMap, fmt, path::Path, str::FromStr};
+
+use tracing::{debug, info, warn};
+
+use crate::{
+    error::RusbmuxError,
+    pair_record::{
+        PairRecordStore, SYSTEM_CONFIGURATION_ID, is_listed, validate_pair_record,
+        validate_record_id,
+    },
+};
+
+const BUNDLE_FORMAT: &str = "rusbmux-pair-records";
+const BUNDLE_VERSION: u64 = 1;
+
+/// where each platform keeps the pair records
+///
+/// they all use the same `<udid>.plist` and `SystemConfiguration.plist` layout, only the directory
+/// differs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LockdownLayout {
+    Linux,
+    MacOs,
+    Windows,
+}
+
+impl LockdownLayout {
+    #[must_use]
+    pub const fn path(&self) -> &'static str {
+        match self {
+            Self::Linux => "/var/lib/lockdown",
+            Self::MacOs => "/var/db/lockdown",
+            Self::Windows => "C:\\ProgramData\\Apple\\Lockdown",
+        }
+    }
+}
+
+impl fmt::Display for LockdownLayout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Linux => "linux",
+            Self::MacOs => "macos",
+            Self::Windows => "windows",
+        })
+    }
+}
+
+impl FromStr for LockdownLayout {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "linux" => Ok(Self::Linux),
+            "macos" => Ok(Self::MacOs),
+            "windows" => Ok(Self::Windows),
+            _ => Err(format!(
+                "unknown layout `{s}`, expected linux, macos or windows"
+            )),
+        }
+    }
+}
+
+/// a set of pair records and the `SystemBUID` they were made with, in one file
+///
+/// it's a plist, so it can be inspected with any plist tool
+#[derive(Debug, Clone, Default)]
+pub struct PairRecordBundle {
+    pub system_buid: Option<String>,
+
+    /// the records keyed by their udid
+    pub records: BTreeMap<String, plist::Dictionary>,
+}
+
+impl PairRecordBundle {
+    /// collects the given records (all of them if `ids` is empty) and the `SystemBUID` from the
+    /// store
+    ///
+    /// when collecting everything, files that aren't pair records are skipped, but asking for a
+    /// specific record that isn't valid is an error
+    pub async fn export(store: &dyn PairRecordStore, ids: &[String]) -> Result<Self, RusbmuxError> {
+        let system_buid = read_store_system_buid(store).await?;
+
+        let (ids, strict) = if ids.is_empty() {
+            (store.list().await?, false)
+        } else {
+            (ids.to_vec(), true)
+        };
+
+        let mut records = BTreeMap::new();
+
+        for id in ids {
+            match read_record(store, &id).await {
+                Ok(record) => {
+                    records.insert(id, record);
+                }
+                Err(e) if strict => return Err(e),
+                Err(e) => warn!(id, err = ?e, "Skipping a file that's not a pair record"),
+            }
+        }
+
+        Ok(Self {
+            system_buid,
+            records,
+        })
+    }
+
+    /// saves the records into the store, and the `SystemBUID` too if the store has none
+    ///
+    /// records made with another `SystemBUID` than the store's fail to connect, so they're refused
+    /// unless `force` is set, [`Self::rewrite_system_buid`] makes them use the store's
+    ///
+    /// returns the ids of the imported records
+    pub async fn import(
+        &self,
+        store: &dyn PairRecordStore,
+        force: bool,
+    ) -> Result<Vec<String>, RusbmuxError> {
+        // check everything before touching the store, so it's never half imported because of a
+        // bad record
+        for (id, record) in &self.records {
+            validate_record_id(id)?;
+            validate_pair_record(&plist_macro::plist_value_to_xml_bytes(
+                &plist::Value::Dictionary(record.clone()),
+            ))?;
+        }
+
+        let host_buid = read_store_system_buid(store).await?;
+
+        if let Some(host) = host_buid.as_ref().or(self.system_buid.as_ref()) {
+            let mismatched = self
+                .records
+                .iter()
+                .filter(|(_, record)| {
+                    record.get("SystemBUID").and_then(|b| b.as_string()) != Some(host)
+                })
+                .map(|(id, _)| id.as_str())
+                .collect::<Vec<_>>();
+
+            if !mismatched.is_empty() {
+                if !force {
+                    return Err(RusbmuxError::InvalidPairRecord(format!(
+                        "{} made with another SystemBUID than {host}, rewrite them or force it",
+                        mismatched.join(", ")
+                    )));
+                }
+
+                warn!(
+                    host,
+                    ?mismatched,
+                    "Importing records made with another SystemBUID"
+                );
+            }
+        }
+
+        if host_buid.is_none()
+            && let Some(buid) = &self.system_buid
+        {
+            info!(buid, "Using the SystemBUID from the bundle");
+
+            store
+                .save(
+                    SYSTEM_CONFIGURATION_ID,
+                    plist_macro::plist_value_to_xml_bytes(&plist_macro::plist!({
+                        "SystemBUID": buid.clone()
+                    })),
+                )
+                .await?;
+        }
+
+        let mut imported = Vec::with_capacity(self.records.len());
+
+        for (id, record) in &self.records {
+            store
+                .save(
+                    id,
+                    plist_macro::plist_value_to_xml_bytes(&plist::Value::Dictionary(
+                        record.clone(),
+                    )),
+                )
+                .await?;
+
+            debug!(id, "Imported the pair record");
+            imported.push(id.clone());
+        }
+
+        Ok(imported)
+    }
+
+    /// makes every record (and the bundle) use the given `SystemBUID`
+    pub fn rewrite_system_buid(&mut self, system_buid: &str) {
+        for record in self.records.values_mut() {
+            record.insert(
+                "SystemBUID".to_string(),
+                plist::Value::String(system_buid.to_string()),
+            );
+        }
+
+        self.system_buid = Some(system_buid.to_string());
+    }
+
+    #[must_use]
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let records = self
+            .records
+            .iter()
+            .map(|(id, record)| (id.clone(), plist::Value::Dictionary(record.clone())))
+            .collect::<plist::Dictionary>();
+
+        plist_macro::plist_value_to_xml_bytes(&plist_macro::plist!({
+            "Format": BUNDLE_FORMAT,
+            "Version": BUNDLE_VERSION,
+            "SystemBUID":? self.system_buid.clone(),
+            "PairRecords": records,
+        }))
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<Self, RusbmuxError> {
+        let invalid = |reason: &str| RusbmuxError::InvalidPairRecord(format!("bundle {reason}"));
+
+        let mut bundle = plist::from_bytes::<plist::Value>(data)?
+            .into_dictionary()
+            .ok_or_else(|| invalid("is not a dictionary"))?;
+
+        if bundle.get("Format").and_then(|f| f.as_string()) != Some(BUNDLE_FORMAT) {
+            return Err(invalid("is not a rusbmux pair record bundle"));
+        }
+
+        match bundle.get("Version").and_then(|v| v.as_unsigned_integer()) {
+            Some(BUNDLE_VERSION) => {}
+            _ => return Err(invalid("version is not supported")),
+        }
+
+        let system_buid = bundle
+            .remove("SystemBUID")
+            .map(|b| {
+                b.into_string()
+                    .ok_or_else(|| invalid("SystemBUID is not a string"))
+            })
+            .transpose()?;
+
+        let records = bundle
+            .remove("PairRecords")
+            .and_then(|r| r.into_dictionary())
+            .ok_or_else(|| invalid("has no PairRecords"))?
+            .into_iter()
+            .map(|(id, record)| {
+                record
+                    .into_dictionary()
+                    .map(|r| (id, r))
+                    .ok_or_else(|| invalid("has a record that is not a dictionary"))
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(Self {
+            system_buid,
+            records,
+        })
+    }
+
+    pub async fn read_from_file(path: &Path) -> Result<Self, RusbmuxError> {
+        Self::from_bytes(&tokio::fs::read(path).await?)
+    }
+
+    /// the bundle holds private keys, so it's only readable by it's owner
+    pub async fn write_to_file(&self, path: &Path) -> Result<(), RusbmuxError> {
+        let data = self.to_bytes();
+        let path = path.to_path_buf();
+
+        tokio::task::spawn_blocking(move || super::fs::write_atomically(&path, &data))
+            .await
+            .map_err(|e| std::io::Error::other(e.to_string()))??;
+
+        Ok(())
+    }
+}
+
+/// reads a record, and unwraps it if it was saved by an older version
+async fn read_record(
+    store: &dyn PairRecordStore,
+    id: &str,
+) -> Result<plist::Dictionary, RusbmuxError> {
+    if !is_listed(id) {
+        return Err(RusbmuxError::InvalidPairRecord(format!(
+            "{id} is not a pair record"
+        )));
+    }
+
+    validate_record_id(id)?;
+
+    let data = store.read(id).await?;
+
+    match plist::from_bytes::<plist::Value>(&data) {
+        Ok(plist::Value::Data(inner)) => validate_pair_record(&inner),
+        _ => validate_pair_record(&data),
+    }
+}
+
+/// the `SystemBUID` the store was set up with, if any
+pub async fn read_store_system_buid(
+    store: &dyn PairRecordStore,
+) -> Result<Option<String>, RusbmuxError> {
+    let data = match store.read(SYSTEM_CONFIGURATION_ID).await {
+        Ok(data) => data,
+        Err(RusbmuxError::IO(e)) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+
+    Ok(plist::from_bytes::<plist::Value>(&data)?
+        .into_dictionary()
+        .and_then(|mut d| d.remove("SystemBUID"))
+        .and_then(|b| b.into_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pair_record::MemoryPairRecordStore;
+
+    fn record(system_buid: &str) -> plist::Dictionary {
+        let data = || plist::Value::Data(vec![1, 2, 3]);
+
+        plist::Dictionary::from_iter([
+            ("DeviceCertificate".to_string(), data()),
+            ("HostCertificate".to_string(), data()),
+            ("HostPrivateKey".to_string(), data()),
+            ("RootCertificate".to_string(), data()),
+            ("RootPrivateKey".to_string(), data()),
+            ("HostID".to_string(), plist::Value::String("HOST".into())),
+            (
+                "SystemBUID".to_string(),
+                plist::Value::String(system_buid.into()),
+            ),
+        ])
+    }
+
+    fn bundle(system_buid: &str, ids: &[&str]) -> PairRecordBundle {
+        PairRecordBundle {
+            system_buid: Some(system_buid.to_string()),
+            records: ids
+                .iter()
+                .map(|id| ((*id).to_string(), record(system_buid)))
+                .collect(),
+        }
+    }
+
+    async fn store_with_buid(system_buid: &str) -> MemoryPairRecordStore {
+        let store = MemoryPairRecordStore::new();
+        bundle(system_buid, &[])
+            .import(&store, false)
+            .await
+            .unwrap();
+        store
+    }
+
+    fn with_header(format: &str, version: u64) -> Vec<u8> {
+        plist_macro::plist_value_to_xml_bytes(&plist_macro::plist!({
+            "Format": format,
+            "Version": version,
+            "PairRecords": {},
+        }))
+    }
+
+    #[test]
+    fn round_trip() {
+        let bundle = bundle("BUID", &["U1", "U2"]);
+        let read = PairRecordBundle::from_bytes(&bundle.to_bytes()).unwrap();
+
+        assert_eq!(read.system_buid, bundle.system_buid);
+        assert_eq!(read.records, bundle.records);
+    }
+
+    #[test]
+    fn rejects_other_formats_and_versions() {
+        assert!(PairRecordBundle::from_bytes(&with_header(BUNDLE_FORMAT, BUNDLE_VERSION)).is_ok());
+
+        assert!(
+            PairRecordBundle::from_bytes(&with_header("something-else", BUNDLE_VERSION)).is_err()
+        );
+        assert!(
+            PairRecordBundle::from_bytes(&with_header(BUNDLE_FORMAT, BUNDLE_VERSION + 1)).is_err()
+        );
+        assert!(PairRecordBundle::from_bytes(b"not a plist").is_err());
+    }
+
+    #[tokio::test]
+    async fn imports_into_an_empty_store() {
+        let store = MemoryPairRecordStore::new();
+
+        let imported = bundle("BUID", &["U1", "U2"])
+            .import(&store, false)
+            .await
+            .unwrap();
+
+        assert_eq!(imported, ["U1", "U2"]);
+        assert_eq!(
+            read_store_system_buid(&store).await.unwrap().as_deref(),
+            Some("BUID")
+        );
+    }
+
+    #[tokio::test]
+    async fn a_bad_record_imports_nothing() {
+        let store = MemoryPairRecordStore::new();
+
+        let mut missing_key = bundle("BUID", &["U1", "U3"]);
+        missing_key
+            .records
+            .get_mut("U3")
+            .unwrap()
+            .remove("HostPrivateKey");
+
+        assert!(missing_key.import(&store, false).await.is_err());
+        assert!(store.list().await.unwrap().is_empty());
+        assert_eq!(read_store_system_buid(&store).await.unwrap(), None);
+
+        let mut bad_id = bundle("BUID", &["U1"]);
+        bad_id.records.insert("../U2".to_string(), record("BUID"));
+
+        assert!(bad_id.import(&store, false).await.is_err());
+        assert!(store.list().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn another_system_buid_is_refused() {
+        let store = store_with_buid("HOST").await;
+
+        assert!(matches!(
+            bundle("OTHER", &["U1"]).import(&store, false).await,
+            Err(RusbmuxError::InvalidPairRecord(_))
+        ));
+        assert!(store.list().await.unwrap().is_empty());
+
+        // a record that doesn't match the bundle's own
+        let mut mixed = bundle("HOST", &["U1"]);
+        mixed.records.insert("U2".to_string(), record("OTHER"));
+        assert!(mixed.import(&store, false).await.is_err());
+        assert!(store.list().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn another_system_buid_is_rewritten_or_forced() {
+        let store = store_with_buid("HOST").await;
+
+        let mut rewritten = bundle("OTHER", &["U1"]);
+        rewritten.rewrite_system_buid("HOST");
+        assert_eq!(rewritten.import(&store, false).await.unwrap(), ["U1"]);
+
+        assert_eq!(
+            bundle("OTHER", &["U2"]).import(&store, true).await.unwrap(),
+            ["U2"]
+        );
+
+        // the store keeps it's own
+        assert_eq!(
+            read_store_system_buid(&store).await.unwrap().as_deref(),
+            Some("HOST")
+        );
+    }
+}
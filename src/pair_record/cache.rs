@@ -0,0 +1,127 @@
+use std::{
+    collections::HashSet,
+    sync::{
+        LazyLock,
+        atomic::{AtomicBool, Ordering},
+    },
+};
+
+use dashmap::DashMap;
+use idevice::pairing_file::PairingFile;
+use tokio::sync::{Mutex, Notify};
+use tracing::{debug, info};
+
+use crate::{error::RusbmuxError, pair_record::pair_record_store};
+
+struct CachedRecord {
+    raw: Vec<u8>,
+    pairing_file: PairingFile,
+}
+
+/// the parsed pair records, so the mDNS matching doesn't have to read and parse every record for
+/// every announcement
+static PAIRING_FILES: LazyLock<DashMap<String, CachedRecord>> = LazyLock::new(DashMap::new);
+
+static LOADED: AtomicBool = AtomicBool::new(false);
+
+/// only one refresh at a time, otherwise two refreshes might both report the same change
+static REFRESH_LOCK: Mutex<()> = Mutex::const_new(());
+
+/// woken up whenever the daemon it self saves or deletes a record
+static CHANGED: Notify = Notify::const_new();
+
+/// what changed in the store since the last refresh
+#[derive(Debug, Default)]
+pub struct PairRecordChanges {
+    /// new records, or ones that got replaced
+    pub added: Vec<String>,
+
+    /// deleted records, or ones that can't be parsed anymore
+    pub removed: Vec<String>,
+}
+
+impl PairRecordChanges {
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty()
+    }
+}
+
+pub(crate) fn notify_changed() {
+    CHANGED.notify_one();
+}
+
+/// waits until the daemon it self saves or deletes a record
+pub async fn wait_changed() {
+    CHANGED.notified().await;
+}
+
+/// re-reads the store, and updates the cache with what changed
+pub async fn refresh_pairing_files() -> Result<PairRecordChanges, RusbmuxError> {
+    let _guard = REFRESH_LOCK.lock().await;
+
+    let store = pair_record_store();
+    let ids = store.list().await?;
+
+    let mut changes = PairRecordChanges::default();
+    let mut seen = HashSet::with_capacity(ids.len());
+
+    for id in ids {
+        let raw = match store.read(&id).await {
+            Ok(raw) => raw,
+            Err(e) => {
+                debug!(id, err = ?e, "Failed to read the pair record");
+                continue;
+            }
+        };
+
+        if PAIRING_FILES.get(&id).is_some_and(|c| c.raw == raw) {
+            seen.insert(id);
+            continue;
+        }
+
+        match PairingFile::from_bytes(&raw) {
+            Ok(pairing_file) => {
+                PAIRING_FILES.insert(id.clone(), CachedRecord { raw, pairing_file });
+                seen.insert(id.clone());
+                changes.added.push(id);
+            }
+            Err(e) => debug!(id, err = ?e, "Skipping an unusable pair record"),
+        }
+    }
+
+    PAIRING_FILES.retain(|id, _| {
+        let keep = seen.contains(id);
+        if !keep {
+            changes.removed.push(id.clone());
+        }
+        keep
+    });
+
+    LOADED.store(true, Ordering::Release);
+
+    if !changes.is_empty() {
+        info!(
+            added = ?changes.added,
+            removed = ?changes.removed,
+            "The pair records changed"
+        );
+    }
+
+    Ok(changes)
+}
+
+/// gets all the valid pairing files along side it's id (udid)
+pub async fn get_saved_pairing_files() -> Vec<(String, PairingFile)> {
+    if !LOADED.load(Ordering::Acquire)
+        && let Err(e) = refresh_pairing_files().await
+    {
+        debug!(err = ?e, "Failed to list the pair records");
+        return Vec::new();
+    }
+
+    PAIRING_FILES
+        .iter()
+        .map(|c| (c.key().clone(), c.pairing_file.clone()))
+        .collect()
+}
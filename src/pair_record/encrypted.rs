@@ -0,0 +1,563 @@
+use std::{path::Path, sync::Arc};
+
+use ring::{
+    aead::{Aad, CHACHA20_POLY1305, LessSafeKey, NONCE_LEN, Nonce, UnboundKey},
+    rand::{SecureRandom, SystemRandom},
+};
+use tracing::{info, warn};
+
+use crate::{
+    error::RusbmuxError,
+    pair_record::{BoxFuture, PairRecordStore, SYSTEM_CONFIGURATION_ID},
+};
+
+/// every sealed record starts with this, anything else is treated as a plaintext record
+const MAGIC: &[u8] = b"RUSBMUX-SEALED-1";
+
+/// the default key file, it's used if it exists
+#[cfg(unix)]
+pub const DEFAULT_KEY_PATH: &str = "/etc/rusbmux/pair-record.key";
+
+#[cfg(windows)]
+pub const DEFAULT_KEY_PATH: &str = "C:\\ProgramData\\rusbmux\\pair-record.key";
+
+/// the name of the systemd credential (`LoadCredential=rusbmux-pair-record-key:...`)
+pub const CREDENTIAL_NAME: &str = "rusbmux-pair-record-key";
+
+const KEY_LEN: usize = 32;
+
+/// the key the pair records are sealed with
+///
+/// the key file holds either 32 raw bytes or 64 hex characters
+pub struct PairRecordKey([u8; KEY_LEN]);
+
+impl std::fmt::Debug for PairRecordKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("PairRecordKey(..)")
+    }
+}
+
+impl PairRecordKey {
+    /// looks for the key in the systemd credentials first, then in [`DEFAULT_KEY_PATH`]
+    ///
+    /// it's `None` if neither exists, which means the records are kept in plaintext
+    pub fn find() -> Result<Option<Self>, RusbmuxError> {
+        if let Some(dir) = std::env::var_os("CREDENTIALS_DIRECTORY") {
+            let path = Path::new(&dir).join(CREDENTIAL_NAME);
+            if path.exists() {
+                info!(
+                    ?path,
+                    "Using the pair record key from the systemd credentials"
+                );
+                return Self::load(&path).map(Some);
+            }
+        }
+
+        let path = Path::new(DEFAULT_KEY_PATH);
+        if path.exists() {
+            info!(?path, "Using the pair record key file");
+            return Self::load(path).map(Some);
+        }
+
+        Ok(None)
+    }
+
+    pub fn load(path: &Path) -> Result<Self, RusbmuxError> {
+        check_key_file_permissions(path)?;
+
+        let data = std::fs::read(path)?;
+        Self::from_bytes(&data)
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<Self, RusbmuxError> {
+        if let Ok(key) = <[u8; KEY_LEN]>::try_from(data) {
+            return Ok(Self(key));
+        }
+
+        let hex = data.trim_ascii();
+        if hex.len() != KEY_LEN * 2 {
+            return Err(RusbmuxError::PairRecordCrypto(format!(
+                "the key must be {KEY_LEN} bytes or {} hex characters",
+                KEY_LEN * 2
+            )));
+        }
+
+        let mut key = [0; KEY_LEN];
+        for (byte, pair) in key.iter_mut().zip(hex.chunks_exact(2)) {
+            *byte = std::str::from_utf8(pair)
+                .ok()
+                .and_then(|s| u8::from_str_radix(s, 16).ok())
+                .ok_or_else(|| {
+                    RusbmuxError::PairRecordCrypto("the key is not valid hex".to_string())
+                })?;
+        }
+
+        Ok(Self(key))
+    }
+
+    fn aead_key(&self) -> LessSafeKey {
+        LessSafeKey::new(
+            UnboundKey::new(&CHACHA20_POLY1305, &self.0).expect("the key length is always valid"),
+        )
+    }
+}
+
+/// the key must only be readable by us
+#[cfg(unix)]
+fn check_key_file_permissions(path: &Path) -> Result<(), RusbmuxError> {
+    use std::os::unix::fs::MetadataExt;
+
+    let metadata = std::fs::metadata(path)?;
+
+    // SAFETY: geteuid can't fail
+    let euid = unsafe { libc::geteuid() };
+
+    if metadata.uid() != 0 && metadata.uid() != euid {
+        return Err(RusbmuxError::PairRecordCrypto(format!(
+            "{} must be owned by root",
+            path.display()
+        )));
+    }
+
+    if metadata.mode() & 0o077 != 0 {
+        return Err(RusbmuxError::PairRecordCrypto(format!(
+            "{} must not be accessible by the group or others (chmod 600)",
+            path.display()
+        )));
+    }
+
+    Ok(())
+}
+
+#[cfg(windows)]
+fn check_key_file_permissions(_path: &Path) -> Result<(), RusbmuxError> {
+    Ok(())
+}
+
+/// seals every record before it reaches the inner store, and opens it only in memory when read
+///
+/// plaintext records found in the inner store are still readable, and they get sealed the first
+/// time they're read (or all at once on [`PairRecordStore::init`])
+///
+/// the `SystemConfiguration` isn't sealed, it only has the `SystemBUID` in it, and usbmuxd (or
+/// anything else reading the lockdown folder) keeps the same host id with it
+///
+/// nothing but us can read the sealed records, [`Self::decrypt_all`] turns them back into plaintext
+/// before going back to usbmuxd
+#[derive(Debug)]
+pub struct EncryptedPairRecordStore {
+    inner: Arc<dyn PairRecordStore>,
+    key: LessSafeKey,
+    rng: SystemRandom,
+}
+
+impl EncryptedPairRecordStore {
+    pub fn new(inner: Arc<dyn PairRecordStore>, key: &PairRecordKey) -> Self {
+        Self {
+            inner,
+            key: key.aead_key(),
+            rng: SystemRandom::new(),
+        }
+    }
+
+    #[must_use]
+    pub fn is_sealed(data: &[u8]) -> bool {
+        data.starts_with(MAGIC)
+    }
+
+    /// the record id is used as the associated data, so a sealed record can't be swapped with
+    /// another one
+    fn seal(&self, id: &str, mut data: Vec<u8>) -> Result<Vec<u8>, RusbmuxError> {
+        let mut nonce = [0; NONCE_LEN];
+        self.rng.fill(&mut nonce).map_err(|_| {
+            RusbmuxError::PairRecordCrypto("failed to generate a nonce".to_string())
+        })?;
+
+        self.key
+            .seal_in_place_append_tag(
+                Nonce::assume_unique_for_key(nonce),
+                Aad::from(id.as_bytes()),
+                &mut data,
+            )
+            .map_err(|_| RusbmuxError::PairRecordCrypto(format!("failed to seal {id}")))?;
+
+        let mut sealed = Vec::with_capacity(MAGIC.len() + NONCE_LEN + data.len());
+        sealed.extend_from_slice(MAGIC);
+        sealed.extend_from_slice(&nonce);
+        sealed.extend_from_slice(&data);
+
+        Ok(sealed)
+    }
+
+    fn open(&self, id: &str, sealed: &[u8]) -> Result<Vec<u8>, RusbmuxError> {
+        let rest = &sealed[MAGIC.len()..];
+        if rest.len() < NONCE_LEN {
+            return Err(RusbmuxError::PairRecordCrypto(format!("{id} is truncated")));
+        }
+
+        let (nonce, ciphertext) = rest.split_at(NONCE_LEN);
+        let nonce = Nonce::try_assume_unique_for_key(nonce)
+            .map_err(|_| RusbmuxError::PairRecordCrypto(format!("{id} is truncated")))?;
+
+        let mut data = ciphertext.to_vec();
+        let len = self
+            .key
+            .open_in_place(nonce, Aad::from(id.as_bytes()), &mut data)
+            .map_err(|_| {
+                RusbmuxError::PairRecordCrypto(format!(
+                    "failed to open {id}, the key is wrong or the record is corrupted"
+                ))
+            })?
+            .len();
+
+        data.truncate(len);
+
+        Ok(data)
+    }
+
+    /// unseals the records in the inner store, so the ones that can't open them can read them again
+    ///
+    /// only the listed records and the `SystemConfiguration` are, not the quarantined or the
+    /// namespaced ones, returns the ids of the ones that were sealed
+    pub async fn decrypt_all(&self) -> Result<Vec<String>, RusbmuxError> {
+        let mut ids = self.inner.list().await?;
+        ids.push(SYSTEM_CONFIGURATION_ID.to_string());
+
+        let mut decrypted = Vec::new();
+
+        for id in ids {
+            let data = match self.inner.read(&id).await {
+                Ok(data) => data,
+                Err(RusbmuxError::IO(e)) if e.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e),
+            };
+
+            if Self::is_sealed(&data) {
+                let data = self.open(&id, &data)?;
+                self.inner.save(&id, data).await?;
+
+                info!(id, "Decrypted the pair record");
+                decrypted.push(id);
+            }
+        }
+
+        Ok(decrypted)
+    }
+
+    /// seals a plaintext record, or opens the `SystemConfiguration` if it was sealed by an older
+    /// version
+    async fn migrate(&self, id: &str) -> Result<(), RusbmuxError> {
+        let data = self.inner.read(id).await?;
+
+        if id == SYSTEM_CONFIGURATION_ID {
+            if Self::is_sealed(&data) {
+                let data = self.open(id, &data)?;
+                self.inner.save(id, data).await?;
+                info!(id, "Unsealed the SystemConfiguration");
+            }
+        } else if !Self::is_sealed(&data) {
+            let sealed = self.seal(id, data)?;
+            self.inner.save(id, sealed).await?;
+            info!(id, "Sealed a plaintext pair record");
+        }
+
+        Ok(())
+    }
+}
+
+impl PairRecordStore for EncryptedPairRecordStore {
+    fn init(&self) -> BoxFuture<'_, Result<(), RusbmuxError>> {
+        Box::pin(async {
+            self.inner.init().await?;
+
+            let mut ids = self.inner.list().await?;
+            ids.push(SYSTEM_CONFIGURATION_ID.to_string());
+
+            for id in ids {
+                match self.migrate(&id).await {
+                    Ok(()) => {}
+                    Err(RusbmuxError::IO(e)) if e.kind() == std::io::ErrorKind::NotFound => {}
+                    Err(e) => warn!(id, err = ?e, "Failed to seal the pair record"),
+                }
+            }
+
+            Ok(())
+        })
+    }
+
+    fn read<'a>(&'a self, id: &'a str) -> BoxFuture<'a, Result<Vec<u8>, RusbmuxError>> {
+        Box::pin(async move {
+            let data = self.inner.read(id).await?;
+
+            if Self::is_sealed(&data) {
+                return self.open(id, &data);
+            }
+
+            if id == SYSTEM_CONFIGURATION_ID {
+                return Ok(data);
+            }
+
+            match self.seal(id, data.clone()) {
+                Ok(sealed) => match self.inner.save(id, sealed).await {
+                    Ok(()) => info!(id, "Sealed a plaintext pair record"),
+                    Err(e) => warn!(id, err = ?e, "Failed to seal the pair record"),
+                },
+                Err(e) => warn!(id, err = ?e, "Failed to seal the pair record"),
+            }
+
+            Ok(data)
+        })
+    }
+
+    fn save<'a>(&'a self, id: &'a str, data: Vec<u8>) -> BoxFuture<'a, Result<(), RusbmuxError>> {
+        Box::pin(async move {
+            if id == SYSTEM_CONFIGURATION_ID {
+                return self.inner.save(id, data).await;
+            }
+
+            let sealed = self.seal(id, data)?;
+            self.inner.save(id, sealed).await
+        })
+    }
+
+    fn delete<'a>(&'a self, id: &'a str) -> BoxFuture<'a, Result<(), RusbmuxError>> {
+        self.inner.delete(id)
+    }
+
+    fn list(&self) -> BoxFuture<'_, Result<Vec<String>, RusbmuxError>> {
+        self.inner.list()
+    }
+
+    fn watch_path(&self) -> Option<&Path> {
+        self.inner.watch_path()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pair_record::MemoryPairRecordStore;
+
+    const RECORD: &[u8] = b"<plist>the pair record</plist>";
+
+    fn key(byte: u8) -> PairRecordKey {
+        PairRecordKey::from_bytes(&[byte; KEY_LEN]).unwrap()
+    }
+
+    /// the encrypted store, and the store under it to look at what really got saved
+    fn stores(key: &PairRecordKey) -> (EncryptedPairRecordStore, Arc<MemoryPairRecordStore>) {
+        let inner = Arc::new(MemoryPairRecordStore::new());
+        (EncryptedPairRecordStore::new(inner.clone(), key), inner)
+    }
+
+    fn is_crypto_error<T>(result: Result<T, RusbmuxError>) -> bool {
+        matches!(result, Err(RusbmuxError::PairRecordCrypto(_)))
+    }
+
+    /// a file only the tests use, it's removed once dropped
+    struct TempFile(std::path::PathBuf);
+
+    impl TempFile {
+        fn new(name: &str, data: &[u8]) -> Self {
+            let path =
+                std::env::temp_dir().join(format!("rusbmux-test-{}-{name}", std::process::id()));
+            std::fs::write(&path, data).unwrap();
+            Self(path)
+        }
+
+        #[cfg(unix)]
+        fn chmod(&self, mode: u32) {
+            use std::os::unix::fs::PermissionsExt;
+
+            std::fs::set_permissions(&self.0, std::fs::Permissions::from_mode(mode)).unwrap();
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    #[tokio::test]
+    async fn round_trip() {
+        let (store, inner) = stores(&key(1));
+
+        store.save("U1", RECORD.to_vec()).await.unwrap();
+
+        let sealed = inner.read("U1").await.unwrap();
+        assert!(EncryptedPairRecordStore::is_sealed(&sealed));
+        assert!(!sealed.windows(RECORD.len()).any(|w| w == RECORD));
+
+        assert_eq!(store.read("U1").await.unwrap(), RECORD);
+    }
+
+    #[tokio::test]
+    async fn wrong_key() {
+        let (store, inner) = stores(&key(1));
+        store.save("U1", RECORD.to_vec()).await.unwrap();
+
+        let other = EncryptedPairRecordStore::new(inner, &key(2));
+        assert!(is_crypto_error(other.read("U1").await));
+    }
+
+    #[tokio::test]
+    async fn renamed_record() {
+        let (store, inner) = stores(&key(1));
+        store.save("U1", RECORD.to_vec()).await.unwrap();
+
+        // moved under another id without going through the store
+        let sealed = inner.read("U1").await.unwrap();
+        inner.save("U2", sealed).await.unwrap();
+
+        assert!(is_crypto_error(store.read("U2").await));
+    }
+
+    #[tokio::test]
+    async fn truncated_and_garbage() {
+        let (store, inner) = stores(&key(1));
+        store.save("U1", RECORD.to_vec()).await.unwrap();
+        let sealed = inner.read("U1").await.unwrap();
+
+        // only part of the nonce is there
+        inner
+            .save("U1", sealed[..MAGIC.len() + NONCE_LEN / 2].to_vec())
+            .await
+            .unwrap();
+        assert!(is_crypto_error(store.read("U1").await));
+
+        // the tag is cut off
+        inner
+            .save("U1", sealed[..sealed.len() - 1].to_vec())
+            .await
+            .unwrap();
+        assert!(is_crypto_error(store.read("U1").await));
+
+        let mut garbage = MAGIC.to_vec();
+        garbage.extend_from_slice(&[0xAB; 64]);
+        inner.save("U1", garbage).await.unwrap();
+        assert!(is_crypto_error(store.read("U1").await));
+
+        let mut flipped = sealed;
+        let last = flipped.len() - 1;
+        flipped[last] ^= 1;
+        inner.save("U1", flipped).await.unwrap();
+        assert!(is_crypto_error(store.read("U1").await));
+    }
+
+    #[tokio::test]
+    async fn plaintext_is_sealed_when_read() {
+        let (store, inner) = stores(&key(1));
+        inner.save("U1", RECORD.to_vec()).await.unwrap();
+
+        assert_eq!(store.read("U1").await.unwrap(), RECORD);
+
+        assert!(EncryptedPairRecordStore::is_sealed(
+            &inner.read("U1").await.unwrap()
+        ));
+        assert_eq!(store.read("U1").await.unwrap(), RECORD);
+    }
+
+    #[tokio::test]
+    async fn plaintext_is_sealed_on_init() {
+        let (store, inner) = stores(&key(1));
+        inner.save("U1", RECORD.to_vec()).await.unwrap();
+        inner
+            .save(SYSTEM_CONFIGURATION_ID, b"buid".to_vec())
+            .await
+            .unwrap();
+
+        store.init().await.unwrap();
+
+        assert!(EncryptedPairRecordStore::is_sealed(
+            &inner.read("U1").await.unwrap()
+        ));
+        assert_eq!(store.read("U1").await.unwrap(), RECORD);
+
+        // usbmuxd still reads it
+        assert_eq!(inner.read(SYSTEM_CONFIGURATION_ID).await.unwrap(), b"buid");
+    }
+
+    #[tokio::test]
+    async fn system_configuration_is_not_sealed() {
+        let (store, inner) = stores(&key(1));
+
+        store
+            .save(SYSTEM_CONFIGURATION_ID, b"buid".to_vec())
+            .await
+            .unwrap();
+        assert_eq!(inner.read(SYSTEM_CONFIGURATION_ID).await.unwrap(), b"buid");
+
+        // sealed by an older version
+        let sealed = store
+            .seal(SYSTEM_CONFIGURATION_ID, b"buid".to_vec())
+            .unwrap();
+        inner.save(SYSTEM_CONFIGURATION_ID, sealed).await.unwrap();
+        assert_eq!(store.read(SYSTEM_CONFIGURATION_ID).await.unwrap(), b"buid");
+
+        store.init().await.unwrap();
+        assert_eq!(inner.read(SYSTEM_CONFIGURATION_ID).await.unwrap(), b"buid");
+    }
+
+    #[tokio::test]
+    async fn decrypts_everything() {
+        let (store, inner) = stores(&key(1));
+        store.save("U1", RECORD.to_vec()).await.unwrap();
+        store.save("U2", RECORD.to_vec()).await.unwrap();
+        store
+            .save(SYSTEM_CONFIGURATION_ID, b"buid".to_vec())
+            .await
+            .unwrap();
+
+        let mut decrypted = store.decrypt_all().await.unwrap();
+        decrypted.sort();
+        assert_eq!(decrypted, ["U1", "U2"]);
+
+        for id in ["U1", "U2"] {
+            assert_eq!(inner.read(id).await.unwrap(), RECORD);
+        }
+
+        assert!(store.decrypt_all().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn init_without_a_system_configuration() {
+        let (store, _) = stores(&key(1));
+        store.init().await.unwrap();
+    }
+
+    #[test]
+    fn key_formats() {
+        let raw = [0x5A; KEY_LEN];
+        let hex = "5a".repeat(KEY_LEN);
+
+        assert_eq!(PairRecordKey::from_bytes(&raw).unwrap().0, raw);
+        assert_eq!(PairRecordKey::from_bytes(hex.as_bytes()).unwrap().0, raw);
+        assert_eq!(
+            PairRecordKey::from_bytes(format!("{}\n", hex.to_uppercase()).as_bytes())
+                .unwrap()
+                .0,
+            raw
+        );
+
+        assert!(PairRecordKey::from_bytes(&[0; KEY_LEN - 1]).is_err());
+        assert!(PairRecordKey::from_bytes("zz".repeat(KEY_LEN).as_bytes()).is_err());
+        assert!(PairRecordKey::from_bytes(b"").is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn key_file_permissions() {
+        let hex = "5a".repeat(KEY_LEN);
+        let file = TempFile::new("key", hex.as_bytes());
+
+        file.chmod(0o600);
+        assert_eq!(PairRecordKey::load(&file.0).unwrap().0, [0x5A; KEY_LEN]);
+
+        file.chmod(0o640);
+        assert!(PairRecordKey::load(&file.0).is_err());
+
+        file.chmod(0o604);
+        assert!(PairRecordKey::load(&file.0).is_err());
+    }
+}
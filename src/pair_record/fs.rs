@@ -0,0 +1,163 @@
+use std::{
+    fs::OpenOptions,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use tracing::{error, trace};
+
+use crate::{
+    error::RusbmuxError,
+    pair_record::{BoxFuture, PairRecordStore, is_listed},
+};
+
+/// keeps every record as `<id>.plist` in a directory, the same layout usbmuxd uses
+#[derive(Debug, Clone)]
+pub struct FsPairRecordStore {
+    root: PathBuf,
+}
+
+impl FsPairRecordStore {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    #[must_use]
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    #[must_use]
+    pub fn path_of(&self, id: &str) -> PathBuf {
+        self.root.join(format!("{id}.plist"))
+    }
+}
+
+impl PairRecordStore for FsPairRecordStore {
+    fn init(&self) -> BoxFuture<'_, Result<(), RusbmuxError>> {
+        Box::pin(async {
+            tokio::fs::create_dir_all(&self.root).await.inspect_err(
+                |e| error!(root = ?self.root, err = ?e, "Failed to create the lockdown folder"),
+            )?;
+
+            Ok(())
+        })
+    }
+
+    fn read<'a>(&'a self, id: &'a str) -> BoxFuture<'a, Result<Vec<u8>, RusbmuxError>> {
+        Box::pin(async move {
+            let path = self.path_of(id);
+            trace!(id, ?path, "Reading pair record file");
+
+            Ok(tokio::fs::read(&path).await?)
+        })
+    }
+
+    fn save<'a>(&'a self, id: &'a str, data: Vec<u8>) -> BoxFuture<'a, Result<(), RusbmuxError>> {
+        Box::pin(async move {
+            let path = self.path_of(id);
+            trace!(id, ?path, "Writing pair record file");
+
+            // the namespaced records are in a folder of their own
+            if let Some(dir) = path.parent() {
+                tokio::fs::create_dir_all(dir).await?;
+            }
+
+            tokio::task::spawn_blocking(move || write_atomically(&path, &data))
+                .await
+                .map_err(|e| std::io::Error::other(e.to_string()))??;
+
+            Ok(())
+        })
+    }
+
+    fn delete<'a>(&'a self, id: &'a str) -> BoxFuture<'a, Result<(), RusbmuxError>> {
+        Box::pin(async move {
+            let path = self.path_of(id);
+            trace!(id, ?path, "Deleting pair record file");
+
+            tokio::fs::remove_file(&path).await?;
+
+            Ok(())
+        })
+    }
+
+    fn list(&self) -> BoxFuture<'_, Result<Vec<String>, RusbmuxError>> {
+        Box::pin(async {
+            let mut entries = tokio::fs::read_dir(&self.root).await?;
+            let mut ids = Vec::new();
+
+            while let Some(entry) = entries.next_entry().await? {
+                let path = entry.path();
+
+                if path.extension().is_none_or(|ext| ext != "plist") {
+                    continue;
+                }
+
+                let Some(id) = path.file_stem().and_then(|s| s.to_str()) else {
+                    continue;
+                };
+
+                if is_listed(id) {
+                    ids.push(id.to_string());
+                }
+            }
+
+            Ok(ids)
+        })
+    }
+
+    fn watch_path(&self) -> Option<&Path> {
+        Some(&self.root)
+    }
+}
+
+/// writes to a temporary file next to the record, then renames it into place, so a crash never
+/// leaves a half written record behind
+///
+/// the record is only readable by it's owner, and owned by root if we're root
+pub(crate) fn write_atomically(path: &Path, data: &[u8]) -> std::io::Result<()> {
+    let dir = path.parent().unwrap_or(Path::new("."));
+    let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+    let tmp_path = dir.join(format!(
+        ".{file_name}.{}.tmp",
+        uuid::Uuid::new_v4().simple()
+    ));
+
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+
+    let write = || {
+        let mut file = options.open(&tmp_path)?;
+
+        #[cfg(unix)]
+        {
+            // SAFETY: geteuid can't fail
+            if unsafe { libc::geteuid() } == 0 {
+                std::os::unix::fs::fchown(&file, Some(0), Some(0))?;
+            }
+        }
+
+        file.write_all(data)?;
+        file.sync_all()?;
+
+        std::fs::rename(&tmp_path, path)
+    };
+
+    if let Err(e) = write() {
+        let _ = std::fs::remove_file(&tmp_path);
+        return Err(e);
+    }
+
+    // the rename it self is only durable once the directory is synced
+    #[cfg(unix)]
+    std::fs::File::open(dir)?.sync_all()?;
+
+    Ok(())
+}
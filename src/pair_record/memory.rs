@@ -0,0 +1,59 @@
+use dashmap::DashMap;
+
+use crate::{
+    error::RusbmuxError,
+    pair_record::{BoxFuture, PairRecordStore, is_listed, not_found},
+};
+
+/// keeps the records in memory only, they are gone once the daemon exits
+///
+/// useful when the host must not keep the pair records around, or for embedding the daemon
+#[derive(Debug, Default)]
+pub struct MemoryPairRecordStore {
+    records: DashMap<String, Vec<u8>>,
+}
+
+impl MemoryPairRecordStore {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl PairRecordStore for MemoryPairRecordStore {
+    fn read<'a>(&'a self, id: &'a str) -> BoxFuture<'a, Result<Vec<u8>, RusbmuxError>> {
+        Box::pin(async move {
+            self.records
+                .get(id)
+                .map(|r| r.value().clone())
+                .ok_or_else(|| not_found(id))
+        })
+    }
+
+    fn save<'a>(&'a self, id: &'a str, data: Vec<u8>) -> BoxFuture<'a, Result<(), RusbmuxError>> {
+        Box::pin(async move {
+            self.records.insert(id.to_string(), data);
+            Ok(())
+        })
+    }
+
+    fn delete<'a>(&'a self, id: &'a str) -> BoxFuture<'a, Result<(), RusbmuxError>> {
+        Box::pin(async move {
+            self.records
+                .remove(id)
+                .map(|_| ())
+                .ok_or_else(|| not_found(id))
+        })
+    }
+
+    fn list(&self) -> BoxFuture<'_, Result<Vec<String>, RusbmuxError>> {
+        Box::pin(async {
+            Ok(self
+                .records
+                .iter()
+                .map(|r| r.key().clone())
+                .filter(|id| is_listed(id))
+                .collect())
+        })
+    }
+}
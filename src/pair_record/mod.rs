@@ -0,0 +1,268 @@
+use std::{
+    borrow::Cow,
+    fmt::Debug,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    pin::Pin,
+    sync::{Arc, OnceLock},
+};
+
+use idevice::pairing_file::PairingFile;
+use tracing::{info, warn};
+
+use crate::{error::RusbmuxError, handler::LOCKDOWN_PATH};
+
+mod bundle;
+mod cache;
+mod encrypted;
+mod fs;
+mod memory;
+mod stale;
+
+pub use bundle::{LockdownLayout, PairRecordBundle, read_store_system_buid};
+pub use cache::{PairRecordChanges, get_saved_pairing_files, refresh_pairing_files, wait_changed};
+pub use encrypted::{CREDENTIAL_NAME, DEFAULT_KEY_PATH, EncryptedPairRecordStore, PairRecordKey};
+pub use fs::FsPairRecordStore;
+pub use memory::MemoryPairRecordStore;
+pub use stale::{
+    QUARANTINE_SUFFIX, is_stale, is_stale_error, mark_stale, notify_stale, quarantine_pair_record,
+    restore_pair_record, stale_pair_records,
+};
+
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// the id of the record that holds the `SystemBUID`, it's not a pair record, but it's stored with
+/// them just like usbmuxd does
+pub const SYSTEM_CONFIGURATION_ID: &str = "SystemConfiguration";
+
+/// where the pair records (and the `SystemConfiguration`) are kept
+///
+/// the records are the raw plist bytes, the ids are already validated with [`validate_record_id`]
+/// before reaching the store (a namespaced one is `<namespace>/<id>`, see [`namespaced_id`]), and a
+/// missing record must be reported as an [`ErrorKind::NotFound`] io error, so the handlers can
+/// answer with the right result code
+pub trait PairRecordStore: Debug + Send + Sync {
+    /// prepares the store before the daemon starts using it
+    fn init(&self) -> BoxFuture<'_, Result<(), RusbmuxError>> {
+        Box::pin(async { Ok(()) })
+    }
+
+    fn read<'a>(&'a self, id: &'a str) -> BoxFuture<'a, Result<Vec<u8>, RusbmuxError>>;
+
+    fn save<'a>(&'a self, id: &'a str, data: Vec<u8>) -> BoxFuture<'a, Result<(), RusbmuxError>>;
+
+    fn delete<'a>(&'a self, id: &'a str) -> BoxFuture<'a, Result<(), RusbmuxError>>;
+
+    /// the ids of all the pair records, without the `SystemConfiguration`
+    fn list(&self) -> BoxFuture<'_, Result<Vec<String>, RusbmuxError>>;
+
+    /// the directory to watch for changes made outside of the daemon, if the store has one
+    ///
+    /// stores without it are polled instead
+    fn watch_path(&self) -> Option<&Path> {
+        None
+    }
+}
+
+static PAIR_RECORD_STORE: OnceLock<Arc<dyn PairRecordStore>> = OnceLock::new();
+
+/// the store the daemon is configured with, it defaults to the filesystem at [`LOCKDOWN_PATH`]
+pub fn pair_record_store() -> &'static Arc<dyn PairRecordStore> {
+    PAIR_RECORD_STORE.get_or_init(|| Arc::new(FsPairRecordStore::new(LOCKDOWN_PATH)))
+}
+
+/// opens the filesystem store at `root`, the records are sealed if a key is found (see
+/// [`PairRecordKey::find`])
+pub fn open_store(root: impl Into<PathBuf>) -> Result<Arc<dyn PairRecordStore>, RusbmuxError> {
+    let store = Arc::new(FsPairRecordStore::new(root));
+
+    Ok(match PairRecordKey::find()? {
+        Some(key) => Arc::new(EncryptedPairRecordStore::new(store, &key)),
+        None => store,
+    })
+}
+
+/// sets the store, it must be called before anything touches the pair records
+///
+/// gives the store back if one is already set
+pub fn set_pair_record_store(
+    store: Arc<dyn PairRecordStore>,
+) -> Result<(), Arc<dyn PairRecordStore>> {
+    PAIR_RECORD_STORE.set(store)
+}
+
+/// makes sure the id can't escape the store (e.g. `../../etc/passwd`)
+pub fn validate_record_id(id: &str) -> Result<(), RusbmuxError> {
+    if id.is_empty() || id.contains('/') || id.contains('\\') || id.contains("..") {
+        warn!(?id, "malicious pair record id detected");
+        return Err(RusbmuxError::UnexpectedPacket(
+            "Given pair record id is malformed".into(),
+        ));
+    }
+
+    Ok(())
+}
+
+/// the id of the record in the namespace, it's `<namespace>/<id>`, so the filesystem store keeps
+/// them in a folder of their own
+///
+/// both are validated, so it still can't escape the store
+pub fn namespaced_id<'a>(
+    namespace: Option<&str>,
+    id: &'a str,
+) -> Result<Cow<'a, str>, RusbmuxError> {
+    validate_record_id(id)?;
+
+    match namespace {
+        Some(namespace) => {
+            validate_record_id(namespace)?;
+            Ok(Cow::Owned(format!("{namespace}/{id}")))
+        }
+        None => Ok(Cow::Borrowed(id)),
+    }
+}
+
+pub async fn read_pair_record(id: &str) -> Result<Vec<u8>, RusbmuxError> {
+    read_pair_record_in(None, id).await
+}
+
+/// reads the record from a namespace, or from the main one if there's none
+pub async fn read_pair_record_in(
+    namespace: Option<&str>,
+    id: &str,
+) -> Result<Vec<u8>, RusbmuxError> {
+    let id = namespaced_id(namespace, id)?;
+    pair_record_store().read(&id).await
+}
+
+/// the keys every pair record must have, the rest (`WiFiMACAddress`, `EscrowBag`, `UDID`) are
+/// missing on some devices
+const REQUIRED_KEYS: &[(&str, KeyKind)] = &[
+    ("DeviceCertificate", KeyKind::Data),
+    ("HostCertificate", KeyKind::Data),
+    ("HostPrivateKey", KeyKind::Data),
+    ("RootCertificate", KeyKind::Data),
+    ("RootPrivateKey", KeyKind::Data),
+    ("HostID", KeyKind::String),
+    ("SystemBUID", KeyKind::String),
+];
+
+#[derive(Debug, Clone, Copy)]
+enum KeyKind {
+    Data,
+    String,
+}
+
+/// checks that the data is actually a pair record, and not just any plist
+pub fn validate_pair_record(data: &[u8]) -> Result<plist::Dictionary, RusbmuxError> {
+    let record = plist::from_bytes::<plist::Value>(data)
+        .map_err(|e| RusbmuxError::InvalidPairRecord(format!("not a plist: {e}")))?
+        .into_dictionary()
+        .ok_or_else(|| RusbmuxError::InvalidPairRecord("not a dictionary".to_string()))?;
+
+    for (key, kind) in REQUIRED_KEYS {
+        let valid = match (record.get(key), kind) {
+            (Some(plist::Value::Data(d)), KeyKind::Data) => !d.is_empty(),
+            (Some(plist::Value::String(s)), KeyKind::String) => !s.is_empty(),
+            _ => false,
+        };
+
+        if !valid {
+            return Err(RusbmuxError::InvalidPairRecord(format!(
+                "`{key}` is missing or malformed"
+            )));
+        }
+    }
+
+    Ok(record)
+}
+
+pub async fn save_pair_record(id: &str, data: Vec<u8>) -> Result<(), RusbmuxError> {
+    save_pair_record_in(None, id, data).await
+}
+
+/// the records in a namespace are only for it's clients, the daemon doesn't use them
+pub async fn save_pair_record_in(
+    namespace: Option<&str>,
+    id: &str,
+    data: Vec<u8>,
+) -> Result<(), RusbmuxError> {
+    pair_record_store()
+        .save(&namespaced_id(namespace, id)?, data)
+        .await?;
+
+    if namespace.is_none() {
+        stale::clear_stale(id);
+        cache::notify_changed();
+    }
+
+    Ok(())
+}
+
+/// older versions saved the record wrapped inside a plist `<data>`, this unwraps them back into the
+/// record it self
+pub async fn migrate_wrapped_records() -> Result<(), RusbmuxError> {
+    let store = pair_record_store();
+
+    for id in store.list().await? {
+        let data = match store.read(&id).await {
+            Ok(data) => data,
+            Err(e) => {
+                warn!(id, err = ?e, "Failed to read the pair record");
+                continue;
+            }
+        };
+
+        let Ok(plist::Value::Data(inner)) = plist::from_bytes::<plist::Value>(&data) else {
+            continue;
+        };
+
+        let record = match validate_pair_record(&inner) {
+            Ok(record) => record,
+            Err(e) => {
+                warn!(id, err = ?e, "Found a wrapped pair record, but it's not valid");
+                continue;
+            }
+        };
+
+        let record = plist_macro::plist_value_to_xml_bytes(&plist::Value::Dictionary(record));
+
+        match store.save(&id, record).await {
+            Ok(()) => info!(id, "Migrated a wrapped pair record"),
+            Err(e) => warn!(id, err = ?e, "Failed to migrate the wrapped pair record"),
+        }
+    }
+
+    Ok(())
+}
+
+pub async fn delete_pair_record(id: &str) -> Result<(), RusbmuxError> {
+    delete_pair_record_in(None, id).await
+}
+
+pub async fn delete_pair_record_in(namespace: Option<&str>, id: &str) -> Result<(), RusbmuxError> {
+    pair_record_store()
+        .delete(&namespaced_id(namespace, id)?)
+        .await?;
+
+    if namespace.is_none() {
+        cache::notify_changed();
+    }
+
+    Ok(())
+}
+
+/// reads and parses the pair record of a device
+pub async fn read_pairing_file(udid: &str) -> Result<PairingFile, RusbmuxError> {
+    let data = read_pair_record(udid).await?;
+    Ok(PairingFile::from_bytes(&data)?)
+}
+
+/// whether the id should be returned from [`PairRecordStore::list`]
+pub(crate) fn is_listed(id: &str) -> bool {
+    id != SYSTEM_CONFIGURATION_ID && !id.ends_with(QUARANTINE_SUFFIX)
+}
+
+pub(crate) fn not_found(id: &str) -> RusbmuxError {
+    std::io::Error::new(ErrorKind::NotFound, format!("no pair record for {id}")).into()
+}
@@ -0,0 +1,156 @@
+use std::sync::LazyLock;
+
+use dashmap::DashMap;
+use idevice::IdeviceError;
+use tracing::{info, warn};
+
+use crate::{
+    config::config,
+    error::RusbmuxError,
+    pair_record::{PairRecordStore, delete_pair_record, pair_record_store, validate_record_id},
+    watcher::{CONNECTED_DEVICES, DeviceEvent, HOTPLUG_EVENT_TX},
+};
+
+/// the quarantined records are kept under `<udid>.quarantined`, so they're out of the way
+///
+/// they're restored with [`restore_pair_record`] (`rusbmux restore <udid>`), renaming them by hand
+/// doesn't work for sealed records, since the id they're sealed with changes too
+pub const QUARANTINE_SUFFIX: &str = ".quarantined";
+
+/// the records the devices rejected, along side the reason
+static STALE_PAIR_RECORDS: LazyLock<DashMap<String, String>> = LazyLock::new(DashMap::new);
+
+/// whether the device rejected our record, meaning it forgot about us (e.g. "Reset Location &
+/// Privacy", or it paired with another host)
+///
+/// a pending pairing dialog isn't, the user just didn't tap Trust yet
+#[must_use]
+pub const fn is_stale_error(error: &IdeviceError) -> bool {
+    matches!(error, IdeviceError::InvalidHostID)
+}
+
+#[must_use]
+pub fn is_stale(udid: &str) -> bool {
+    STALE_PAIR_RECORDS.contains_key(udid)
+}
+
+/// all the stale records, along side why they're stale
+#[must_use]
+pub fn stale_pair_records() -> Vec<(String, String)> {
+    STALE_PAIR_RECORDS
+        .iter()
+        .map(|r| (r.key().clone(), r.value().clone()))
+        .collect()
+}
+
+/// remembers that the record of `udid` is stale, and marks every connected device using it
+///
+/// the record gets quarantined if that's enabled
+pub async fn mark_stale(udid: &str, reason: &IdeviceError) {
+    if STALE_PAIR_RECORDS
+        .insert(udid.to_string(), reason.to_string())
+        .is_none()
+    {
+        warn!(udid, %reason, "The device rejected our pair record, it's stale");
+    }
+
+    for device in CONNECTED_DEVICES.iter() {
+        if device.serial_number() == udid {
+            device.core().update_status(|s| s.pair_record_stale = true);
+        }
+    }
+
+    if config().quarantine_stale_pair_records
+        && let Err(e) = quarantine_pair_record(udid).await
+    {
+        warn!(udid, err = ?e, "Failed to quarantine the stale pair record");
+    }
+}
+
+/// tells the `Listen` clients that the device needs to be paired again, if that's enabled
+pub fn notify_stale(id: u64) {
+    if !config().notify_stale_pair_records {
+        return;
+    }
+
+    if let Some(tx) = HOTPLUG_EVENT_TX.get() {
+        let _ = tx.send(DeviceEvent::PairRecordStale { id });
+    }
+}
+
+/// the record is no longer stale once it got replaced
+pub(crate) fn clear_stale(udid: &str) {
+    if STALE_PAIR_RECORDS.remove(udid).is_some() {
+        for device in CONNECTED_DEVICES.iter() {
+            if device.serial_number() == udid {
+                device.core().update_status(|s| s.pair_record_stale = false);
+            }
+        }
+    }
+}
+
+/// moves the record out of the way, so it's not used again until the device is paired again
+pub async fn quarantine_pair_record(udid: &str) -> Result<(), RusbmuxError> {
+    validate_record_id(udid)?;
+
+    let store = pair_record_store();
+    let data = store.read(udid).await?;
+
+    store
+        .save(&format!("{udid}{QUARANTINE_SUFFIX}"), data)
+        .await?;
+    delete_pair_record(udid).await?;
+
+    info!(udid, "Quarantined the stale pair record");
+
+    Ok(())
+}
+
+/// puts a quarantined record back in place of the current one (if there is any)
+///
+/// it goes through the store, so a sealed record is opened with the id it was quarantined under and
+/// sealed again with it's own
+pub async fn restore_pair_record(
+    store: &dyn PairRecordStore,
+    udid: &str,
+) -> Result<(), RusbmuxError> {
+    validate_record_id(udid)?;
+
+    let quarantined = format!("{udid}{QUARANTINE_SUFFIX}");
+
+    let data = store.read(&quarantined).await?;
+    store.save(udid, data).await?;
+    store.delete(&quarantined).await?;
+
+    info!(udid, "Restored the quarantined pair record");
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::pair_record::{EncryptedPairRecordStore, MemoryPairRecordStore, PairRecordKey};
+
+    #[tokio::test]
+    async fn restores_a_sealed_record() {
+        let key = PairRecordKey::from_bytes(&[7; 32]).unwrap();
+        let store = EncryptedPairRecordStore::new(Arc::new(MemoryPairRecordStore::new()), &key);
+
+        let quarantined = format!("U1{QUARANTINE_SUFFIX}");
+        store.save(&quarantined, b"record".to_vec()).await.unwrap();
+
+        restore_pair_record(&store, "U1").await.unwrap();
+
+        assert_eq!(store.read("U1").await.unwrap(), b"record");
+        assert!(store.read(&quarantined).await.is_err());
+    }
+
+    #[test]
+    fn pending_trust_is_not_stale() {
+        assert!(is_stale_error(&IdeviceError::InvalidHostID));
+        assert!(!is_stale_error(&IdeviceError::PairingDialogResponsePending));
+    }
+}
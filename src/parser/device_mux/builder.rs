@@ -16,6 +16,7 @@ bitflags! {
         const ACK = 1 << 0;
         const SYN = 1 << 1;
         const RST = 1 << 2;
+        const FIN = 1 << 3;
     }
 }
 
@@ -124,6 +125,7 @@ impl<P, MH> UsbDevicePacketBuilder<P, MH, Empty> {
         hdr.ack = flags.contains(TcpFlags::ACK);
         hdr.syn = flags.contains(TcpFlags::SYN);
         hdr.rst = flags.contains(TcpFlags::RST);
+        hdr.fin = flags.contains(TcpFlags::FIN);
         hdr.acknowledgment_number = acknowledgment_number;
 
         UsbDevicePacketBuilder {
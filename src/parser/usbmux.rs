@@ -1,6 +1,6 @@
 use tokio::io::AsyncReadExt;
 
-use crate::{AsyncReading, error::ParseError};
+use crate::{AsyncReading, error::ParseError, lease::LeaseToken};
 
 #[derive(Debug, Clone)]
 pub struct UsbMuxPacket {
@@ -252,6 +252,9 @@ pub enum PayloadMessageType {
     SavePairRecord,
     DeletePairRecord,
     Connect,
+    Status,
+    AcquireLease,
+    ReleaseLease,
 }
 
 impl std::fmt::Display for PayloadMessageType {
@@ -265,6 +268,9 @@ impl std::fmt::Display for PayloadMessageType {
             Self::SavePairRecord => write!(f, "SavePairRecord"),
             Self::DeletePairRecord => write!(f, "DeletePairRecord"),
             Self::Connect => write!(f, "Connect"),
+            Self::Status => write!(f, "Status"),
+            Self::AcquireLease => write!(f, "AcquireLease"),
+            Self::ReleaseLease => write!(f, "ReleaseLease"),
         }
     }
 }
@@ -282,6 +288,9 @@ impl TryFrom<&str> for PayloadMessageType {
             "SavePairRecord" => Ok(Self::SavePairRecord),
             "DeletePairRecord" => Ok(Self::DeletePairRecord),
             "Connect" => Ok(Self::Connect),
+            "Status" => Ok(Self::Status),
+            "AcquireLease" => Ok(Self::AcquireLease),
+            "ReleaseLease" => Ok(Self::ReleaseLease),
             _ => Err(format!("unknown payload message type: {value}")),
         }
     }
@@ -364,7 +373,84 @@ pub enum UsbMuxRequest {
 
         #[serde(rename = "PortNumber", deserialize_with = "deserialize_port_number")]
         port: u16,
+
+        /// not part of usbmuxd, the lease it's made under, if the device is leased
+        #[serde(rename = "LeaseID")]
+        lease_id: Option<LeaseToken>,
+    },
+
+    /// not part of usbmuxd, it reports what the daemon knows about every device
+    Status {
+        #[serde(flatten)]
+        common: UsbMuxCommon,
     },
+
+    /// not part of usbmuxd, takes a lease on a device, so other clients can't use it at the same
+    /// time
+    AcquireLease {
+        #[serde(flatten)]
+        common: UsbMuxCommon,
+
+        #[serde(rename = "SerialNumber")]
+        serial_number: String,
+
+        /// `Exclusive` (the default) or `Shared`
+        #[serde(rename = "Mode")]
+        mode: Option<String>,
+
+        /// wait in line for it, instead of being refused
+        #[serde(rename = "Wait", default)]
+        wait: bool,
+
+        /// how long to wait for it, in seconds, forever if it's not set
+        #[serde(rename = "Timeout")]
+        timeout: Option<u64>,
+    },
+
+    /// not part of usbmuxd
+    ReleaseLease {
+        #[serde(flatten)]
+        common: UsbMuxCommon,
+
+        #[serde(rename = "LeaseID")]
+        lease_id: LeaseToken,
+    },
+}
+
+impl UsbMuxRequest {
+    #[must_use]
+    pub const fn common(&self) -> &UsbMuxCommon {
+        match self {
+            Self::Listen { common }
+            | Self::ListDevices { common }
+            | Self::ListListeners { common }
+            | Self::ReadBUID { common }
+            | Self::ReadPairRecord { common, .. }
+            | Self::SavePairRecord { common, .. }
+            | Self::DeletePairRecord { common, .. }
+            | Self::Connect { common, .. }
+            | Self::Status { common }
+            | Self::AcquireLease { common, .. }
+            | Self::ReleaseLease { common, .. } => common,
+        }
+    }
+
+    #[must_use]
+    pub const fn message_type(&self) -> PayloadMessageType {
+        match self {
+            Self::Listen { .. } => PayloadMessageType::Listen,
+            Self::ListDevices { .. } => PayloadMessageType::ListDevices,
+            Self::ListListeners { .. } => PayloadMessageType::ListListeners,
+            Self::ReadBUID { .. } => PayloadMessageType::ReadBUID,
+            Self::ReadPairRecord { .. } => PayloadMessageType::ReadPairRecord,
+            Self::SavePairRecord { .. } => PayloadMessageType::SavePairRecord,
+            Self::DeletePairRecord { .. } => PayloadMessageType::DeletePairRecord,
+            Self::Connect { .. } => PayloadMessageType::Connect,
+            Self::Status { .. } => PayloadMessageType::Status,
+            Self::AcquireLease { .. } => PayloadMessageType::AcquireLease,
+            Self::ReleaseLease { .. } => PayloadMessageType::ReleaseLease,
+        }
+    }
 }
 
 fn deserialize_port_number<'de, D>(deserializer: D) -> Result<u16, D::Error>
@@ -0,0 +1,214 @@
+use std::{
+    fs::File,
+    io::{self, Read, Write},
+    mem,
+    os::{
+        fd::{AsRawFd, FromRawFd, OwnedFd},
+        unix::net::UnixStream,
+    },
+    process::{Child, Command, Stdio},
+    sync::{Mutex, OnceLock, PoisonError},
+};
+
+use tracing::{debug, info, warn};
+
+use super::APPLE_VID;
+
+/// the hidden subcommand the helper is started with
+const USB_HELPER_COMMAND: &str = "usb-helper";
+
+static USB_HELPER: OnceLock<UsbHelper> = OnceLock::new();
+
+/// a small process that stays root after the daemon drops it, it opens the usb devices the daemon
+/// can't open by it self and passes their fds back
+///
+/// it only opens apple devices, and nothing else can be asked from it
+#[derive(Debug)]
+pub struct UsbHelper {
+    socket: Mutex<UnixStream>,
+    _child: Child,
+}
+
+impl UsbHelper {
+    /// starts the same binary again as the helper, it must be done before dropping root
+    pub fn spawn() -> io::Result<Self> {
+        let (ours, theirs) = UnixStream::pair()?;
+
+        let child = Command::new(std::env::current_exe()?)
+            .arg(USB_HELPER_COMMAND)
+            .stdin(Stdio::from(OwnedFd::from(theirs)))
+            .stdout(Stdio::null())
+            .spawn()?;
+
+        info!(pid = child.id(), "Started the usb helper");
+
+        Ok(Self {
+            socket: Mutex::new(ours),
+            _child: child,
+        })
+    }
+
+    /// asks the helper to open `/dev/bus/usb/<bus>/<address>`
+    pub fn open(&self, bus: u8, address: u8) -> io::Result<OwnedFd> {
+        let mut socket = self.socket.lock().unwrap_or_else(PoisonError::into_inner);
+
+        socket.write_all(&[bus, address])?;
+
+        let mut errno = [0; 4];
+        let fd = recv_with_fd(&socket, &mut errno)?;
+
+        match i32::from_le_bytes(errno) {
+            0 => fd.ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidData, "the usb helper sent no fd")
+            }),
+            errno => Err(io::Error::from_raw_os_error(errno)),
+        }
+    }
+}
+
+/// the helper, if it was started
+pub fn usb_helper() -> Option<&'static UsbHelper> {
+    USB_HELPER.get()
+}
+
+/// gives the helper back if one is already set
+pub fn set_usb_helper(helper: UsbHelper) -> Result<(), UsbHelper> {
+    USB_HELPER.set(helper)
+}
+
+/// the helper side, it answers the daemon on stdin until the daemon goes away
+pub fn serve_usb_helper() -> io::Result<()> {
+    // SAFETY: the daemon gave us the socket as stdin, nothing else uses it
+    let mut socket = UnixStream::from(unsafe { OwnedFd::from_raw_fd(0) });
+
+    let mut request = [0; 2];
+
+    loop {
+        match socket.read_exact(&mut request) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                debug!("The daemon is gone, stopping the usb helper");
+                return Ok(());
+            }
+            Err(e) => return Err(e),
+        }
+
+        let [bus, address] = request;
+
+        match open_apple_device(bus, address) {
+            Ok(file) => {
+                debug!(bus, address, "Opened a device for the daemon");
+                send_with_fd(&socket, &0i32.to_le_bytes(), Some(&OwnedFd::from(file)))?;
+            }
+            Err(e) => {
+                warn!(bus, address, err = %e, "Failed to open a device for the daemon");
+                let errno = e.raw_os_error().unwrap_or(libc::EIO);
+                send_with_fd(&socket, &errno.to_le_bytes(), None)?;
+            }
+        }
+    }
+}
+
+fn open_apple_device(bus: u8, address: u8) -> io::Result<File> {
+    let mut file = File::options()
+        .read(true)
+        .write(true)
+        .open(format!("/dev/bus/usb/{bus:03}/{address:03}"))?;
+
+    // the device descriptor comes first, the vendor id is at 8..10
+    let mut descriptor = [0; 18];
+    file.read_exact(&mut descriptor)?;
+
+    if u16::from_le_bytes([descriptor[8], descriptor[9]]) != APPLE_VID {
+        return Err(io::Error::from_raw_os_error(libc::EACCES));
+    }
+
+    Ok(file)
+}
+
+const FD_SPACE: usize = mem::size_of::<libc::c_int>();
+
+fn send_with_fd(socket: &UnixStream, data: &[u8], fd: Option<&OwnedFd>) -> io::Result<()> {
+    // SAFETY: no pointers are involved
+    let mut control = vec![0u8; unsafe { libc::CMSG_SPACE(FD_SPACE as u32) } as usize];
+
+    let mut iov = libc::iovec {
+        iov_base: data.as_ptr().cast_mut().cast(),
+        iov_len: data.len(),
+    };
+
+    // SAFETY: all zeros is a valid `msghdr`
+    let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+
+    if let Some(fd) = fd {
+        msg.msg_control = control.as_mut_ptr().cast();
+        msg.msg_controllen = control.len() as _;
+
+        // SAFETY: the control buffer has room for one fd
+        unsafe {
+            let cmsg = libc::CMSG_FIRSTHDR(&msg);
+            (*cmsg).cmsg_level = libc::SOL_SOCKET;
+            (*cmsg).cmsg_type = libc::SCM_RIGHTS;
+            (*cmsg).cmsg_len = libc::CMSG_LEN(FD_SPACE as u32) as _;
+            libc::CMSG_DATA(cmsg)
+                .cast::<libc::c_int>()
+                .write_unaligned(fd.as_raw_fd());
+        }
+    }
+
+    // SAFETY: the message points to valid buffers
+    if unsafe { libc::sendmsg(socket.as_raw_fd(), &msg, libc::MSG_NOSIGNAL) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+fn recv_with_fd(socket: &UnixStream, data: &mut [u8]) -> io::Result<Option<OwnedFd>> {
+    // SAFETY: no pointers are involved
+    let mut control = vec![0u8; unsafe { libc::CMSG_SPACE(FD_SPACE as u32) } as usize];
+
+    let mut iov = libc::iovec {
+        iov_base: data.as_mut_ptr().cast(),
+        iov_len: data.len(),
+    };
+
+    // SAFETY: all zeros is a valid `msghdr`
+    let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = control.as_mut_ptr().cast();
+    msg.msg_controllen = control.len() as _;
+
+    // SAFETY: the message points to valid buffers
+    let n = unsafe { libc::recvmsg(socket.as_raw_fd(), &mut msg, libc::MSG_CMSG_CLOEXEC) };
+    if n < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    if n as usize != data.len() {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "the usb helper went away",
+        ));
+    }
+
+    // SAFETY: the kernel filled the control buffer
+    let cmsg = unsafe { libc::CMSG_FIRSTHDR(&msg) };
+
+    // SAFETY: checked that it's not null, and that it's carrying fds
+    unsafe {
+        if cmsg.is_null()
+            || (*cmsg).cmsg_level != libc::SOL_SOCKET
+            || (*cmsg).cmsg_type != libc::SCM_RIGHTS
+        {
+            return Ok(None);
+        }
+
+        let fd = libc::CMSG_DATA(cmsg).cast::<libc::c_int>().read_unaligned();
+
+        Ok(Some(OwnedFd::from_raw_fd(fd)))
+    }
+}
@@ -13,6 +13,8 @@ use tokio::io::{AsyncRead, AsyncWrite};
 
 use crate::{AsyncReading, AsyncWriting, error::RusbmuxError, parser::device_mux::UsbDevicePacket};
 
+#[cfg(all(feature = "nusb", target_os = "linux"))]
+pub mod helper;
 #[cfg(feature = "nusb")]
 mod nusb;
 #[cfg(feature = "rusb")]
@@ -36,6 +38,62 @@ pub const DEFAULT_BACKEND: nusb::NusbBackend = nusb::NusbBackend;
 ))]
 pub const DEFAULT_BACKEND: rusb::RusbBackend = rusb::RusbBackend;
 
+#[cfg(feature = "nusb")]
+pub use nusb::NusbBackend;
+#[cfg(feature = "rusb")]
+pub use rusb::RusbBackend;
+
+/// the usb backends that were compiled in, to choose one at runtime
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UsbBackendKind {
+    #[cfg(feature = "nusb")]
+    Nusb,
+    #[cfg(feature = "rusb")]
+    Rusb,
+}
+
+impl Default for UsbBackendKind {
+    /// the same one as [`DEFAULT_BACKEND`]
+    fn default() -> Self {
+        #[cfg(any(
+            all(feature = "nusb", feature = "rusb", unix),
+            all(feature = "nusb", not(feature = "rusb"))
+        ))]
+        return Self::Nusb;
+
+        #[cfg(any(
+            all(feature = "nusb", feature = "rusb", windows),
+            all(feature = "rusb", not(feature = "nusb"))
+        ))]
+        return Self::Rusb;
+    }
+}
+
+impl fmt::Display for UsbBackendKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            #[cfg(feature = "nusb")]
+            Self::Nusb => f.write_str("nusb"),
+            #[cfg(feature = "rusb")]
+            Self::Rusb => f.write_str("rusb"),
+        }
+    }
+}
+
+impl std::str::FromStr for UsbBackendKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            #[cfg(feature = "nusb")]
+            "nusb" => Ok(Self::Nusb),
+            #[cfg(feature = "rusb")]
+            "rusb" => Ok(Self::Rusb),
+            _ => Err(format!("`{s}` is not one of the compiled in usb backends")),
+        }
+    }
+}
+
 pub type BoxStream<T> = Pin<Box<dyn Stream<Item = T> + Send>>;
 
 pub const APPLE_VID: u16 = 0x5ac;
@@ -276,6 +334,36 @@ impl AnyDeviceInfo {
         }
     }
 
+    /// the physical port it's plugged into, like the sysfs name on linux (e.g. `1-2.3`), it stays
+    /// the same across reconnects
+    pub fn bus_path(&self) -> Option<String> {
+        let join = |bus: &str, ports: &[u8]| {
+            let ports = ports.iter().map(u8::to_string).collect::<Vec<_>>();
+            format!("{bus}-{}", ports.join("."))
+        };
+
+        match self {
+            #[cfg(feature = "nusb")]
+            #[allow(unused_variables)]
+            Self::Nusb(info) => {
+                #[cfg(any(target_os = "linux", target_os = "macos", windows))]
+                {
+                    Some(join(info.bus_id(), info.port_chain()))
+                }
+
+                #[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+                {
+                    None
+                }
+            }
+            #[cfg(feature = "rusb")]
+            Self::Rusb(dev) => dev
+                .port_numbers()
+                .ok()
+                .map(|ports| join(&dev.bus_number().to_string(), &ports)),
+        }
+    }
+
     pub fn bus_number(&self) -> u8 {
         match self {
             #[cfg(feature = "nusb")]
@@ -322,7 +410,27 @@ impl AnyDeviceInfo {
     pub async fn open(&self) -> Result<AnyDeviceHandle, RusbmuxError> {
         match self {
             #[cfg(feature = "nusb")]
-            Self::Nusb(info) => Ok(AnyDeviceHandle::Nusb(::nusb::DeviceInfo::open(info).await?)),
+            Self::Nusb(info) => match ::nusb::DeviceInfo::open(info).await {
+                // root was dropped, so the helper opens it instead
+                #[cfg(target_os = "linux")]
+                Err(e)
+                    if e.kind() == ::nusb::ErrorKind::PermissionDenied
+                        && helper::usb_helper().is_some() =>
+                {
+                    let (bus, address) = (info.busnum(), info.device_address());
+
+                    let fd = tokio::task::spawn_blocking(move || {
+                        helper::usb_helper()
+                            .expect("checked above")
+                            .open(bus, address)
+                    })
+                    .await
+                    .map_err(std::io::Error::other)??;
+
+                    Ok(AnyDeviceHandle::Nusb(::nusb::Device::from_fd(fd).await?))
+                }
+                res => Ok(AnyDeviceHandle::Nusb(res?)),
+            },
             #[cfg(feature = "rusb")]
             Self::Rusb(dev) => {
                 let dev_handle = Arc::new(dev.open()?);
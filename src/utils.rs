@@ -79,3 +79,36 @@ pub(crate) fn is_disconnect(error: &RusbmuxError) -> bool {
 
     false
 }
+
+/// a shell like pattern, `*` matches anything (even nothing) and `?` matches a single character
+pub(crate) fn matches_pattern(pattern: &str, s: &str) -> bool {
+    let (pattern, s) = (pattern.as_bytes(), s.as_bytes());
+    let (mut p, mut i) = (0, 0);
+
+    // where the last `*` was, and where it started matching
+    let mut star = None;
+
+    while i < s.len() {
+        match pattern.get(p) {
+            Some(b'*') => {
+                star = Some((p, i));
+                p += 1;
+            }
+            Some(&c) if c == b'?' || c == s[i] => {
+                p += 1;
+                i += 1;
+            }
+            _ => match star {
+                // let the `*` take one more character
+                Some((sp, si)) => {
+                    star = Some((sp, si + 1));
+                    p = sp + 1;
+                    i = si + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == b'*')
+}
@@ -5,15 +5,19 @@ use dashmap::DashMap;
 use tokio::sync::{OnceCell, broadcast};
 
 mod network;
+mod pair_record;
 mod usb;
 
 use crate::{
+    config::{Transport, config},
     device::{ConnectionType, Device},
     error::RusbmuxError,
 };
 pub(crate) use network::watch_network_daemon;
 pub use network::{NetworkEvent, watch_network};
 
+pub(crate) use pair_record::watch_pair_records_daemon;
+
 pub(crate) use usb::watch_usb_daemon;
 pub use usb::{UsbEvent, watch_usb};
 
@@ -31,8 +35,22 @@ pub static CONNECTED_DEVICES: LazyLock<DashMap<u64, Device>> = LazyLock::new(Das
 
 #[derive(Debug, Clone)]
 pub enum DeviceEvent {
-    Attached { id: u64 },
-    Detached { id: u64 },
+    Attached {
+        id: u64,
+    },
+    Detached {
+        id: u64,
+    },
+
+    /// the daemon paired with the device it self
+    Paired {
+        id: u64,
+    },
+
+    /// the device rejected our pair record, it must be paired again
+    PairRecordStale {
+        id: u64,
+    },
 }
 
 /// Removes the device from the connected devices and shut it down
@@ -42,6 +60,18 @@ pub async fn remove_device(id: u64) -> Result<Device, RusbmuxError> {
         .ok_or(RusbmuxError::DeviceNotFound(id))?;
     device.shutdown().await?;
 
+    // both of them are exposed, so there's nothing to dedup
+    if config().transport == Transport::Both {
+        // the usb watcher sends it for the usb devices
+        if matches!(device.connection_type(), ConnectionType::Network) {
+            let _ = get_hotplug_event_tx()
+                .await
+                .send(DeviceEvent::Detached { id: device.id() });
+        }
+
+        return Ok(device);
+    }
+
     // if the removed device is a usb, and there's a network device connected with the same
     // serial number, it would notify the apps (whoever doing a `Listen`)
     // that the network device is now connected
@@ -1,5 +1,6 @@
-use std::{collections::HashMap, net::IpAddr, path::Path};
+use std::{collections::HashMap, net::IpAddr, sync::LazyLock};
 
+use dashmap::DashMap;
 use futures_lite::Stream;
 use idevice::{
     mdns::{decode_auth_tag, derive_auth_tag},
@@ -9,9 +10,10 @@ use mdns_sd::{ResolvedService, ServiceDaemon, ServiceEvent};
 use tracing::{debug, error, info, warn};
 
 use crate::{
-    device::Device,
+    config::{Transport, config},
+    device::{Device, properties::spawn_read_properties},
     error::RusbmuxError,
-    handler::LOCKDOWN_PATH,
+    pair_record::{get_saved_pairing_files, is_stale_error, mark_stale},
     usb_backend::take_new_id,
     watcher::{DeviceEvent, get_hotplug_event_tx},
 };
@@ -26,6 +28,13 @@ use super::CONNECTED_DEVICES;
 
 pub const SERVICE_TYPE: &str = "_apple-mobdev2._tcp.local.";
 
+/// the services that were announced before we had a pair record for them, keyed by the service
+/// name
+///
+/// they're tried again once a new pair record shows up
+static UNRESOLVED_SERVICES: LazyLock<DashMap<String, Box<ResolvedService>>> =
+    LazyLock::new(DashMap::new);
+
 pub async fn watch_network_daemon() {
     let Ok(mdns) = ServiceDaemon::new() else {
         error!("Failed to create mDNS daemon");
@@ -52,6 +61,8 @@ pub async fn watch_network_daemon() {
                     continue;
                 };
 
+                UNRESOLVED_SERVICES.remove(&name);
+
                 debug!(mac_address, "Removing network device");
 
                 let hotplug = get_hotplug_event_tx().await;
@@ -103,7 +114,7 @@ pub async fn watch_network() -> impl Stream<Item = Result<NetworkEvent, RusbmuxE
         while let Ok(event) = receiver.recv_async().await {
             match event {
                 ServiceEvent::ServiceResolved(rs) => {
-                    let Some(rd) = resolve_service(rs) else {
+                    let Some(rd) = resolve_service(rs).await else {
                         continue;
                     };
 
@@ -150,21 +161,28 @@ struct ResolvedDevice {
     udid: String,
 }
 
-fn resolve_service(rs: Box<ResolvedService>) -> Option<ResolvedDevice> {
+async fn resolve_service(rs: Box<ResolvedService>) -> Option<ResolvedDevice> {
     debug!("Discovered network device via mDNS: {rs:#?}");
+    let config = config();
     let addresses = rs.addresses.clone();
 
     // perfer ipv6 if available
     let ipv6 = addresses.iter().find_map(|addr| match addr {
-        mdns_sd::ScopedIp::V6(addr) => Some((IpAddr::V6(*addr.addr()), addr.scope_id().index)),
+        mdns_sd::ScopedIp::V6(addr) if config.is_interface_allowed(&addr.scope_id().name) => {
+            Some((IpAddr::V6(*addr.addr()), addr.scope_id().index))
+        }
         _ => None,
     });
 
     let ipv4 = addresses.iter().find_map(|addr| match addr {
-        mdns_sd::ScopedIp::V4(addr) => Some((
-            IpAddr::V4(*addr.addr()),
-            addr.interface_ids().first().map_or(0, |i| i.index),
-        )),
+        mdns_sd::ScopedIp::V4(addr) => match addr.interface_ids() {
+            // the interface is unknown, so it can only be used if there's no filter
+            [] if config.network_interfaces.is_empty() => Some((IpAddr::V4(*addr.addr()), 0)),
+            ids => ids
+                .iter()
+                .find(|i| config.is_interface_allowed(&i.name))
+                .map(|i| (IpAddr::V4(*addr.addr()), i.index)),
+        },
         _ => None,
     });
 
@@ -172,8 +190,13 @@ fn resolve_service(rs: Box<ResolvedService>) -> Option<ResolvedDevice> {
         (Some((v6, scope)), Some((v4, _))) => ((v6, Some(v4)), scope),
         (Some((v6, scope)), None) => ((v6, None), scope),
         (None, Some((v4, scope))) => ((v4, None), scope),
-        // FIXME: what if no address is given
-        (None, None) => unreachable!(),
+        (None, None) => {
+            debug!(
+                service_name = rs.fullname,
+                "The service has no address on the allowed interfaces, skipping"
+            );
+            return None;
+        }
     };
 
     // iOS 26.4+: match by Bonjour TXT record (identifier + authTag HMACs).
@@ -200,7 +223,7 @@ fn resolve_service(rs: Box<ResolvedService>) -> Option<ResolvedDevice> {
     let udid = if let Some(ident) = identifier
         && !auth_tags.is_empty()
     {
-        let Some(udid) = find_udid_from_txt(ident, &auth_tags) else {
+        let Some(udid) = find_udid_from_txt(ident, &auth_tags).await else {
             warn!("The device doesn't have a pairing file saved, skipping");
             return None;
         };
@@ -208,7 +231,7 @@ fn resolve_service(rs: Box<ResolvedService>) -> Option<ResolvedDevice> {
         udid
     } else {
         // iOS < 26.4 fallback: parse MAC out of the instance name (`<MAC>@<id>.…`).
-        let Some(udid) = get_udid_from_mac_addr(mac_address) else {
+        let Some(udid) = get_udid_from_mac_addr(mac_address).await else {
             warn!(
                 mac_address,
                 "The device doesn't have a pairing file saved, skipping"
@@ -228,10 +251,19 @@ fn resolve_service(rs: Box<ResolvedService>) -> Option<ResolvedDevice> {
 }
 
 async fn network_device_add(rs: Box<ResolvedService>) {
-    let Some(rd) = resolve_service(rs) else {
+    let unresolved = rs.clone();
+    let Some(rd) = resolve_service(rs).await else {
+        UNRESOLVED_SERVICES.insert(unresolved.fullname.clone(), unresolved);
         return;
     };
 
+    UNRESOLVED_SERVICES.remove(&rd.service_name);
+
+    if !config().is_device_allowed(&rd.udid) {
+        debug!(udid = rd.udid, "The device is not allowed, ignoring it");
+        return;
+    }
+
     // if the device broadcasted twice, and the first is still connecting to the heartbeat, this
     // would get the device connect twice
     //
@@ -257,6 +289,13 @@ async fn network_device_add(rs: Box<ResolvedService>) {
         Ok(d) => d,
         Err(e) => {
             error!(udid = rd.udid, error = ?e, "Coudn't create a new network device");
+
+            if let RusbmuxError::Idevice(e) = &e
+                && is_stale_error(e)
+            {
+                mark_stale(&rd.udid, e).await;
+            }
+
             return;
         }
     };
@@ -264,16 +303,21 @@ async fn network_device_add(rs: Box<ResolvedService>) {
     let id = device.id();
     CONNECTED_DEVICES.insert(id, device);
 
+    if config().device_properties {
+        spawn_read_properties(id);
+    }
+
     // prefer usb devices over network devices for the same udid
     //
     // skip hotplug notifications when the device is already connected via usb
     //
     // it would get notified only if the usb is disconnected
-    let has_usb_connection = CONNECTED_DEVICES.iter().any(|device| {
-        device
-            .as_usb()
-            .is_some_and(|_| device.serial_number() == rd.udid)
-    });
+    let has_usb_connection = config().transport == Transport::PreferUsb
+        && CONNECTED_DEVICES.iter().any(|device| {
+            device
+                .as_usb()
+                .is_some_and(|_| device.serial_number() == rd.udid)
+        });
 
     if !has_usb_connection {
         let _ = super::get_hotplug_event_tx()
@@ -282,7 +326,22 @@ async fn network_device_add(rs: Box<ResolvedService>) {
     }
 }
 
-fn find_udid_from_txt(identifier: &[u8], auth_tags: &[&[u8]]) -> Option<String> {
+/// tries to add the services that got dropped for not having a pair record
+pub(crate) fn retry_unresolved_services() {
+    let names = UNRESOLVED_SERVICES
+        .iter()
+        .map(|s| s.key().clone())
+        .collect::<Vec<_>>();
+
+    for name in names {
+        if let Some((_, rs)) = UNRESOLVED_SERVICES.remove(&name) {
+            debug!(service_name = name, "Retrying an unresolved service");
+            tokio::spawn(network_device_add(rs));
+        }
+    }
+}
+
+async fn find_udid_from_txt(identifier: &[u8], auth_tags: &[&[u8]]) -> Option<String> {
     if auth_tags.is_empty() {
         return None;
     }
@@ -298,7 +357,7 @@ fn find_udid_from_txt(identifier: &[u8], auth_tags: &[&[u8]]) -> Option<String>
         return None;
     }
 
-    for (udid, PairingFile { host_id, .. }) in get_saved_pairing_files() {
+    for (udid, PairingFile { host_id, .. }) in get_saved_pairing_files().await {
         let expected = derive_auth_tag(host_id.as_bytes(), identifier);
         if decoded_tags.contains(&expected) {
             info!(udid, "TXT record matched UDID");
@@ -309,46 +368,13 @@ fn find_udid_from_txt(identifier: &[u8], auth_tags: &[&[u8]]) -> Option<String>
     None
 }
 
-/// gets all the valid pairing files along side it's file stem (udid)
-fn get_saved_pairing_files() -> Vec<(String, PairingFile)> {
-    let entries = match Path::new(LOCKDOWN_PATH).read_dir() {
-        Ok(dirs) => dirs,
-        Err(e) => {
-            debug!(err = ?e, "Failed to read the directories of `{LOCKDOWN_PATH}`");
-            return Vec::new();
-        }
-    };
-
-    entries
-        .flatten()
-        .map(|di| di.path())
-        .map(|path| {
-            (
-                path.file_stem()
-                    .and_then(|fstem| fstem.to_str())
-                    .map(|s| s.to_string()),
-                path,
-            )
-        })
-        .flat_map(|(fstem, path)| {
-            let udid = fstem?;
-
-            let Ok(pf) = PairingFile::read_from_file(path) else {
-                return None;
-            };
-
-            Some((udid, pf))
-        })
-        .collect()
-}
-
-fn get_udid_from_mac_addr(mac_addr: &str) -> Option<String> {
+async fn get_udid_from_mac_addr(mac_addr: &str) -> Option<String> {
     for (
         udid,
         PairingFile {
             wifi_mac_address, ..
         },
-    ) in get_saved_pairing_files()
+    ) in get_saved_pairing_files().await
     {
         if mac_addr == wifi_mac_address {
             return Some(udid);
@@ -0,0 +1,159 @@
+use tracing::{debug, info, warn};
+
+use crate::{
+    config::config,
+    pair_record::{PairRecordChanges, refresh_pairing_files, wait_changed},
+    watcher::{CONNECTED_DEVICES, network::retry_unresolved_services, remove_device},
+};
+
+/// keeps the network devices in sync with the pair records
+///
+/// when a record appears, the mDNS services that got dropped for not having one are tried again,
+/// and when a record is removed, the network devices using it are detached
+pub async fn watch_pair_records_daemon() {
+    if let Err(e) = refresh_pairing_files().await {
+        warn!(err = ?e, "Failed to load the pair records");
+    }
+
+    #[cfg(target_os = "linux")]
+    if let Some(path) = crate::pair_record::pair_record_store().watch_path() {
+        match inotify::Inotify::new(path) {
+            Ok(inotify) => {
+                info!(?path, "Watching the pair records for changes");
+
+                loop {
+                    tokio::select! {
+                        res = inotify.wait() => if let Err(e) = res {
+                            warn!(err = ?e, "Failed to watch the pair records, falling back to polling");
+                            break;
+                        },
+                        _ = wait_changed() => {}
+                    }
+
+                    reconcile().await;
+                }
+            }
+            Err(e) => {
+                warn!(?path, err = ?e, "Failed to watch the pair records, falling back to polling");
+            }
+        }
+    }
+
+    debug!("Polling the pair records for changes");
+
+    loop {
+        tokio::select! {
+            _ = tokio::time::sleep(config().pair_record_poll_interval) => {}
+            _ = wait_changed() => {}
+        }
+
+        reconcile().await;
+    }
+}
+
+async fn reconcile() {
+    let PairRecordChanges { added, removed } = match refresh_pairing_files().await {
+        Ok(changes) => changes,
+        Err(e) => {
+            debug!(err = ?e, "Failed to refresh the pair records");
+            return;
+        }
+    };
+
+    for udid in removed {
+        let ids = CONNECTED_DEVICES
+            .iter()
+            .filter(|dev| {
+                dev.as_network()
+                    .is_some_and(|ndev| ndev.serial_number == udid)
+            })
+            .map(|dev| dev.id())
+            .collect::<Vec<_>>();
+
+        for id in ids {
+            info!(id, udid, "The pair record got removed, detaching");
+            let _ = remove_device(id).await;
+        }
+    }
+
+    if !added.is_empty() {
+        retry_unresolved_services();
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod inotify {
+    use std::{
+        ffi::CString,
+        io,
+        os::{
+            fd::{AsRawFd, FromRawFd, OwnedFd},
+            unix::ffi::OsStrExt,
+        },
+        path::Path,
+    };
+
+    use tokio::io::unix::AsyncFd;
+
+    pub struct Inotify(AsyncFd<OwnedFd>);
+
+    impl Inotify {
+        pub fn new(path: &Path) -> io::Result<Self> {
+            // SAFETY: no pointers are involved
+            let fd = unsafe { libc::inotify_init1(libc::IN_NONBLOCK | libc::IN_CLOEXEC) };
+            if fd < 0 {
+                return Err(io::Error::last_os_error());
+            }
+
+            // SAFETY: the fd is valid and owned by us
+            let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+
+            let path = CString::new(path.as_os_str().as_bytes())?;
+
+            // SAFETY: the fd and path are valid
+            let wd = unsafe {
+                libc::inotify_add_watch(
+                    fd.as_raw_fd(),
+                    path.as_ptr(),
+                    libc::IN_CLOSE_WRITE
+                        | libc::IN_MOVED_TO
+                        | libc::IN_MOVED_FROM
+                        | libc::IN_DELETE
+                        | libc::IN_DELETE_SELF
+                        | libc::IN_MOVE_SELF,
+                )
+            };
+            if wd < 0 {
+                return Err(io::Error::last_os_error());
+            }
+
+            Ok(Self(AsyncFd::new(fd)?))
+        }
+
+        /// waits for any change, the events them selves are dropped since the whole store is
+        /// re-read anyway
+        pub async fn wait(&self) -> io::Result<()> {
+            let mut buf = [0u8; 4096];
+
+            loop {
+                let mut guard = self.0.readable().await?;
+
+                match guard.try_io(|fd| {
+                    // SAFETY: the buffer is valid for it's whole length
+                    let n =
+                        unsafe { libc::read(fd.as_raw_fd(), buf.as_mut_ptr().cast(), buf.len()) };
+
+                    if n < 0 {
+                        Err(io::Error::last_os_error())
+                    } else {
+                        Ok(n)
+                    }
+                }) {
+                    Ok(Ok(_)) => return Ok(()),
+                    Ok(Err(e)) => return Err(e),
+                    Err(_would_block) => {}
+                }
+            }
+        }
+    }
+}
@@ -1,17 +1,22 @@
-use std::time::Duration;
+use std::{collections::HashMap, sync::Arc, time::Duration};
 
 use crossfire::mpsc;
 use futures_lite::{Stream, StreamExt};
-use tokio::time::Instant;
+use tokio::{sync::broadcast, task::AbortHandle, time::Instant};
 
 use crate::{
-    device::Device,
+    config::{Transport, config},
+    device::{
+        Device, pairing::spawn_pairing, preflight::preflight, properties::spawn_read_properties,
+        status::TrustState, usb::UsbDevice, wifi_sync::spawn_enable_wifi_connections,
+    },
     error::RusbmuxError,
+    pair_record::notify_stale,
     usb_backend::{self, APPLE_VID, UsbBackend},
 };
 
 use super::{CONNECTED_DEVICES, DeviceEvent};
-use tracing::{debug, error, trace};
+use tracing::{debug, error, trace, warn};
 
 pub enum UsbEvent {
     Connected((Device, u64)),
@@ -30,6 +35,15 @@ pub fn watch_usb(backend: &impl UsbBackend) -> impl Stream<Item = Result<UsbEven
                     return None;
                 }
 
+                // or if it's not allowed by the config
+                if let Ok(usb_backend::Event::Connected(dev, _)) = &e
+                    && let Some(serial_number) = dev.serial_number()
+                    && !config().is_device_allowed(&serial_number)
+                {
+                    debug!(%serial_number, "The device is not allowed, ignoring it");
+                    return None;
+                }
+
                 Some(e)
             });
 
@@ -87,9 +101,14 @@ pub async fn watch_usb_daemon(backend: impl UsbBackend) {
 
     let (disconnected_tx, disconnected_rx) = mpsc::bounded_async(32);
 
+    // the devices that are still in their preflight, they're not announced yet
+    let mut preflights: HashMap<u64, PendingPreflight> = HashMap::new();
+
     loop {
         tokio::select! {
             Some(event) = usb_hotplug.next() => {
+                let prefer_usb = config().transport == Transport::PreferUsb;
+
                 if let Ok(UsbEvent::Connected((device, _))) = &event
                     && let Some(usb) = device.as_usb()
                 {
@@ -98,7 +117,7 @@ pub async fn watch_usb_daemon(backend: impl UsbBackend) {
 
                 match event {
                     Ok(UsbEvent::Connected((device, id))) => {
-                        if let Some(ndev) = CONNECTED_DEVICES.iter().find(|dev| {
+                        if prefer_usb && let Some(ndev) = CONNECTED_DEVICES.iter().find(|dev| {
                             dev.as_network()
                                 .is_some_and(|_| dev.serial_number() == device.serial_number())
                         }) {
@@ -108,12 +127,21 @@ pub async fn watch_usb_daemon(backend: impl UsbBackend) {
                         // A device may emit multiple connect events (especially during boot), and the
                         // initial connection may not receive a matching disconnect event. Remove any
                         // stale entry before registering the new connection.
-                        CONNECTED_DEVICES.retain(|_, d| d.serial_number() != device.serial_number());
+                        //
+                        // the network one is kept only if both are exposed
+                        CONNECTED_DEVICES.retain(|_, d| {
+                            d.serial_number() != device.serial_number()
+                                || (!prefer_usb && d.as_network().is_some())
+                        });
 
-                        // TODO: do preflight
-                        CONNECTED_DEVICES.insert(id, device);
+                        attach(&mut preflights, id, device, hotplug_event_tx);
                     }
                     Ok(UsbEvent::Disconnected(id)) => {
+                        // it's gone before it was announced, so there is nothing to detach
+                        if let Some(preflight) = preflights.remove(&id) {
+                            preflight.cancel().await;
+                        }
+
                         match super::remove_device(id).await {
                             Ok(_) => {
                                 // TODO: maybe we can put this into the `remove_device` function instead
@@ -132,6 +160,11 @@ pub async fn watch_usb_daemon(backend: impl UsbBackend) {
 
             // the io kind of disconnection happens only in usb
             Ok((id, opaque_id)) = disconnected_rx.recv() => {
+                // shut down before it's reopened, so it's interface is free again
+                if let Some(preflight) = preflights.remove(&id) {
+                    preflight.cancel().await;
+                }
+
                 CONNECTED_DEVICES.retain(|_, d| d.as_usb().map(|d| d.info.opaque_id()).unwrap_or_default() != opaque_id);
 
                 let _ = hotplug_event_tx.send(DeviceEvent::Detached { id });
@@ -150,12 +183,142 @@ pub async fn watch_usb_daemon(backend: impl UsbBackend) {
                 {
                     device.as_usb().unwrap().set_disconnected_tx(disconnected_tx.clone());
 
-                    CONNECTED_DEVICES.insert(id, device);
-
-                    let _ = hotplug_event_tx.send(DeviceEvent::Attached { id });
+                    attach(&mut preflights, id, device, hotplug_event_tx);
                 }
             }
 
         }
     }
 }
+
+/// a device that is still in it's preflight, it's not announced yet
+struct PendingPreflight {
+    task: AbortHandle,
+    device: Arc<UsbDevice>,
+}
+
+impl PendingPreflight {
+    /// stops the preflight and shuts the device down
+    ///
+    /// dropping the device isn't enough, it's reader and writer loops keep it (and the claimed
+    /// interface) alive until it's cancelled
+    async fn cancel(self) {
+        // it's announced already, it's removed like any other device
+        if self.task.is_finished() {
+            return;
+        }
+
+        // the task checks it before announcing, in case it's already past the preflight
+        self.device.core.canceler.cancel();
+        self.task.abort();
+
+        if let Err(e) = self.device.shutdown().await {
+            warn!(device_id = self.device.core.id, err = ?e, "Failed to shut down the device");
+        }
+    }
+}
+
+/// registers the device and announces it to the listeners
+///
+/// if it needs a preflight, it's done on it's own task and the device is announced once it's done,
+/// so a slow device doesn't hold up the others (or their disconnects)
+fn attach(
+    preflights: &mut HashMap<u64, PendingPreflight>,
+    id: u64,
+    device: Device,
+    hotplug_event_tx: &'static broadcast::Sender<DeviceEvent>,
+) {
+    let announce = move |device: Device| {
+        CONNECTED_DEVICES.insert(id, device);
+
+        let _ = hotplug_event_tx.send(DeviceEvent::Attached { id });
+
+        after_attach(id);
+    };
+
+    preflights.retain(|_, preflight| !preflight.task.is_finished());
+
+    // the new device is already open, so it doesn't have to wait for the old one
+    if let Some(preflight) = preflights.remove(&id) {
+        tokio::spawn(preflight.cancel());
+    }
+
+    let Some(usb) = device
+        .as_usb()
+        .cloned()
+        .filter(|_| config().needs_preflight())
+    else {
+        announce(device);
+        return;
+    };
+
+    let task = tokio::spawn(async move {
+        run_preflight(&device).await;
+
+        if let Some(usb) = device.as_usb()
+            && usb.core.canceler.is_cancelled()
+        {
+            return;
+        }
+
+        announce(device);
+    });
+
+    preflights.insert(
+        id,
+        PendingPreflight {
+            task: task.abort_handle(),
+            device: usb,
+        },
+    );
+}
+
+/// runs the lockdown preflight, and attaches the result to the device
+///
+/// the device is still announced if it failed, the same as when it's disabled
+async fn run_preflight(device: &Device) {
+    let config = config();
+
+    let Some(usb) = device.as_usb() else {
+        return;
+    };
+
+    match tokio::time::timeout(config.preflight_timeout, preflight(usb)).await {
+        Ok(Ok(preflight)) => usb.core.update_status(|s| s.preflight = Some(preflight)),
+        Ok(Err(e)) => warn!(device_id = usb.core.id, err = ?e, "Preflight failed"),
+        Err(_) => warn!(device_id = usb.core.id, "Preflight timed out"),
+    }
+}
+
+/// acts on the trust state found by the preflight
+///
+/// untrusted devices get paired, and trusted ones get their WiFi connections turned on, if those
+/// are enabled
+///
+/// it also starts reading the lockdown properties
+fn after_attach(id: u64) {
+    let config = config();
+
+    let Some(usb) = CONNECTED_DEVICES
+        .get(&id)
+        .and_then(|device| device.as_usb().cloned())
+    else {
+        return;
+    };
+
+    if config.device_properties {
+        spawn_read_properties(id);
+    }
+
+    if usb.core.status().pair_record_stale {
+        notify_stale(id);
+    }
+
+    match usb.core.status().preflight.map(|p| p.trust) {
+        Some(TrustState::Untrusted) if config.auto_pair => spawn_pairing(usb),
+        Some(TrustState::Trusted) if config.enable_wifi_connections => {
+            spawn_enable_wifi_connections(usb);
+        }
+        _ => {}
+    }
+}